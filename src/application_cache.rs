@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+/// Applications beyond this many, across exporters, are not cached, so an
+/// exporter sending many application ids cannot grow the cache forever.
+const MAX_SIZE: usize = 100_000;
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct ApplicationCacheKey {
+    exporter_ip: String,
+    application_id: Vec<u8>,
+}
+
+impl ApplicationCacheKey {
+    pub fn new(exporter_ip: String, application_id: Vec<u8>) -> ApplicationCacheKey {
+        ApplicationCacheKey {
+            exporter_ip,
+            application_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationCacheValue {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl ApplicationCacheValue {
+    pub fn new(name: Option<String>, description: Option<String>) -> ApplicationCacheValue {
+        ApplicationCacheValue { name, description }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApplicationCache {
+    map: HashMap<ApplicationCacheKey, ApplicationCacheValue>,
+}
+
+impl ApplicationCache {
+    pub fn new() -> ApplicationCache {
+        ApplicationCache {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Inserts or updates an application; new applications are ignored once
+    /// the cache holds MAX_SIZE.
+    pub fn insert(
+        &mut self,
+        k: ApplicationCacheKey,
+        v: ApplicationCacheValue,
+    ) -> Option<ApplicationCacheValue> {
+        if self.map.len() >= MAX_SIZE && !self.map.contains_key(&k) {
+            return None;
+        }
+        self.map.insert(k, v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get(&self, k: &ApplicationCacheKey) -> Option<&ApplicationCacheValue> {
+        self.map.get(k)
+    }
+
+    pub fn contains_key(&self, k: &ApplicationCacheKey) -> bool {
+        self.map.contains_key(k)
+    }
+}
+
+impl Default for ApplicationCache {
+    fn default() -> ApplicationCache {
+        ApplicationCache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_is_capped() {
+        let mut cache = ApplicationCache::new();
+        let value = ApplicationCacheValue::new(Some("http".to_string()), None);
+        for i in 0..MAX_SIZE as u32 + 1 {
+            let k = ApplicationCacheKey::new("192.0.2.1".to_string(), i.to_be_bytes().to_vec());
+            cache.insert(k, value.clone());
+        }
+        assert_eq!(cache.len(), MAX_SIZE);
+        let k = ApplicationCacheKey::new("192.0.2.1".to_string(), 0u32.to_be_bytes().to_vec());
+        let https = ApplicationCacheValue::new(Some("https".to_string()), None);
+        cache.insert(k, https.clone());
+        let k = ApplicationCacheKey::new("192.0.2.1".to_string(), 0u32.to_be_bytes().to_vec());
+        assert_eq!(cache.get(&k), Some(&https));
+    }
+}
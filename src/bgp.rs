@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::Cursor;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::prefix::Prefix;
+
+pub const ATTR_ORIGIN: u8 = 1;
+pub const ATTR_AS_PATH: u8 = 2;
+pub const ATTR_NEXT_HOP: u8 = 3;
+pub const ATTR_COMMUNITIES: u8 = 8;
+pub const ATTR_MP_REACH_NLRI: u8 = 14;
+pub const ATTR_MP_UNREACH_NLRI: u8 = 15;
+pub const ATTR_AS4_PATH: u8 = 17;
+pub const ATTR_LARGE_COMMUNITIES: u8 = 32;
+
+const AS_SET: u8 = 1;
+
+pub const AFI_IPV4: u16 = 1;
+pub const AFI_IPV6: u16 = 2;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathAttributes {
+    pub origin: Option<u8>,
+    pub as_path: Vec<u32>,
+    pub next_hop: Option<IpAddr>,
+    pub communities: Vec<u32>,
+    pub large_communities: Vec<(u32, u32, u32)>,
+    pub mp_reach: Vec<Prefix>,
+    pub mp_unreach: Vec<Prefix>,
+}
+
+impl PathAttributes {
+    /// Parses BGP path attributes. `as4` selects 4-octet AS numbers in AS_PATH,
+    /// as used by MRT TABLE_DUMP_V2 and sessions that negotiated them.
+    pub fn parse(buf: &[u8], as4: bool) -> Result<PathAttributes> {
+        PathAttributes::parse_with(buf, as4, false)
+    }
+
+    /// Parses the path attributes of a TABLE_DUMP_V2 RIB entry, whose
+    /// MP_REACH_NLRI is abbreviated to the next hop length and next hop
+    /// (RFC 6396 section 4.3.4).
+    pub fn parse_mrt(buf: &[u8]) -> Result<PathAttributes> {
+        PathAttributes::parse_with(buf, true, true)
+    }
+
+    fn parse_with(buf: &[u8], as4: bool, abbreviated: bool) -> Result<PathAttributes> {
+        let mut attrs = PathAttributes::default();
+        let mut as4_path = Vec::new();
+        let mut rdr = Cursor::new(buf);
+        while (rdr.position() as usize) < buf.len() {
+            let flags = rdr.read_u8()?;
+            let type_ = rdr.read_u8()?;
+            let length = if flags & 0x10 != 0 {
+                rdr.read_u16::<BigEndian>()? as usize
+            } else {
+                rdr.read_u8()? as usize
+            };
+            let mut data = vec![0u8; length];
+            rdr.read_exact(&mut data)?;
+            match type_ {
+                ATTR_ORIGIN => attrs.origin = data.first().copied(),
+                ATTR_AS_PATH => attrs.as_path = parse_as_path(&data, as4)?,
+                ATTR_AS4_PATH => as4_path = parse_as_path(&data, true)?,
+                ATTR_NEXT_HOP if length == 4 => {
+                    attrs.next_hop = Some(IpAddr::V4(Ipv4Addr::new(
+                        data[0], data[1], data[2], data[3],
+                    )));
+                }
+                ATTR_COMMUNITIES => {
+                    let mut rdr_data = Cursor::new(data.as_slice());
+                    for _ in 0..length / 4 {
+                        attrs.communities.push(rdr_data.read_u32::<BigEndian>()?);
+                    }
+                }
+                ATTR_LARGE_COMMUNITIES => {
+                    let mut rdr_data = Cursor::new(data.as_slice());
+                    for _ in 0..length / 12 {
+                        attrs.large_communities.push((
+                            rdr_data.read_u32::<BigEndian>()?,
+                            rdr_data.read_u32::<BigEndian>()?,
+                            rdr_data.read_u32::<BigEndian>()?,
+                        ));
+                    }
+                }
+                ATTR_MP_REACH_NLRI if abbreviated => {
+                    let next_hop_length = *data.first().unwrap_or(&0) as usize;
+                    let next_hop = data
+                        .get(1..1 + next_hop_length)
+                        .ok_or_else(|| anyhow!("bgp: truncated MP_REACH_NLRI"))?;
+                    attrs.next_hop = match next_hop_length {
+                        4 => Some(IpAddr::V4(Ipv4Addr::new(
+                            next_hop[0],
+                            next_hop[1],
+                            next_hop[2],
+                            next_hop[3],
+                        ))),
+                        16 | 32 => {
+                            let mut octets = [0u8; 16];
+                            octets.copy_from_slice(&next_hop[..16]);
+                            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+                        }
+                        _ => None,
+                    };
+                }
+                ATTR_MP_REACH_NLRI => {
+                    let mut rdr_data = Cursor::new(data.as_slice());
+                    let afi = rdr_data.read_u16::<BigEndian>()?;
+                    let _safi = rdr_data.read_u8()?;
+                    let next_hop_length = rdr_data.read_u8()? as usize;
+                    let mut next_hop = vec![0u8; next_hop_length];
+                    rdr_data.read_exact(&mut next_hop)?;
+                    if afi == AFI_IPV6 && next_hop_length >= 16 {
+                        let mut octets = [0u8; 16];
+                        octets.copy_from_slice(&next_hop[..16]);
+                        attrs.next_hop = Some(IpAddr::V6(Ipv6Addr::from(octets)));
+                    } else if afi == AFI_IPV4 && next_hop_length >= 4 {
+                        attrs.next_hop = Some(IpAddr::V4(Ipv4Addr::new(
+                            next_hop[0],
+                            next_hop[1],
+                            next_hop[2],
+                            next_hop[3],
+                        )));
+                    }
+                    let _reserved = rdr_data.read_u8()?;
+                    let position = rdr_data.position() as usize;
+                    attrs.mp_reach = parse_nlri(&data[position..], afi)?;
+                }
+                ATTR_MP_UNREACH_NLRI => {
+                    let mut rdr_data = Cursor::new(data.as_slice());
+                    let afi = rdr_data.read_u16::<BigEndian>()?;
+                    let _safi = rdr_data.read_u8()?;
+                    attrs.mp_unreach = parse_nlri(&data[3..], afi)?;
+                }
+                _ => {}
+            }
+        }
+        if !as4 && !as4_path.is_empty() && as4_path.len() <= attrs.as_path.len() {
+            let keep = attrs.as_path.len() - as4_path.len();
+            attrs.as_path.truncate(keep);
+            attrs.as_path.extend(as4_path);
+        }
+        Ok(attrs)
+    }
+
+    /// The origin AS is the last AS number in the path.
+    pub fn origin_as(&self) -> Option<u32> {
+        self.as_path.last().copied()
+    }
+}
+
+fn parse_as_path(buf: &[u8], as4: bool) -> Result<Vec<u32>> {
+    let mut as_path = Vec::new();
+    let mut rdr = Cursor::new(buf);
+    while (rdr.position() as usize) < buf.len() {
+        let segment_type = rdr.read_u8()?;
+        let count = rdr.read_u8()?;
+        for i in 0..count {
+            let asn = if as4 {
+                rdr.read_u32::<BigEndian>()?
+            } else {
+                rdr.read_u16::<BigEndian>()? as u32
+            };
+            // An AS_SET counts as a single hop, represented by its first member.
+            if segment_type != AS_SET || i == 0 {
+                as_path.push(asn);
+            }
+        }
+    }
+    Ok(as_path)
+}
+
+/// Parses a sequence of (length, prefix) NLRI entries for the given AFI.
+pub fn parse_nlri(buf: &[u8], afi: u16) -> Result<Vec<Prefix>> {
+    let mut prefixes = Vec::new();
+    let mut rdr = Cursor::new(buf);
+    while (rdr.position() as usize) < buf.len() {
+        let len = rdr.read_u8()?;
+        prefixes.push(read_prefix(&mut rdr, afi, len)?);
+    }
+    Ok(prefixes)
+}
+
+/// Reads the significant octets of a prefix of the given length.
+pub fn read_prefix(rdr: &mut Cursor<&[u8]>, afi: u16, len: u8) -> Result<Prefix> {
+    let mut octets = [0u8; 16];
+    let size = (len as usize).div_ceil(8);
+    let addr = match afi {
+        AFI_IPV4 if size <= 4 => {
+            rdr.read_exact(&mut octets[..size])?;
+            IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+        }
+        AFI_IPV6 if size <= 16 => {
+            rdr.read_exact(&mut octets[..size])?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(anyhow!("invalid prefix afi = {} length = {}", afi, len)),
+    };
+    Prefix::new(addr, len)
+}
+
+pub const MESSAGE_UPDATE: u8 = 2;
+
+const HEADER_LENGTH: usize = 19;
+
+/// A BGP UPDATE message with IPv4 routes from the message body and other
+/// families from MP_REACH_NLRI and MP_UNREACH_NLRI.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    pub withdrawn: Vec<Prefix>,
+    pub attributes: PathAttributes,
+    pub announced: Vec<Prefix>,
+}
+
+impl Update {
+    /// Parses a whole BGP message including the 19-byte header.
+    pub fn parse(buf: &[u8], as4: bool) -> Result<Update> {
+        if buf.len() < HEADER_LENGTH {
+            return Err(anyhow!("bgp: message too short"));
+        }
+        let length = u16::from_be_bytes([buf[16], buf[17]]) as usize;
+        let type_ = buf[18];
+        if type_ != MESSAGE_UPDATE {
+            return Err(anyhow!("bgp: unexpected message type {}", type_));
+        }
+        let body = buf
+            .get(HEADER_LENGTH..length)
+            .ok_or_else(|| anyhow!("bgp: truncated message"))?;
+        let mut rdr = Cursor::new(body);
+        let withdrawn_length = rdr.read_u16::<BigEndian>()? as usize;
+        let mut withdrawn = vec![0u8; withdrawn_length];
+        rdr.read_exact(&mut withdrawn)?;
+        let attributes_length = rdr.read_u16::<BigEndian>()? as usize;
+        let mut attributes = vec![0u8; attributes_length];
+        rdr.read_exact(&mut attributes)?;
+        let position = rdr.position() as usize;
+
+        let mut attributes = PathAttributes::parse(&attributes, as4)?;
+        let mut withdrawn = parse_nlri(&withdrawn, AFI_IPV4)?;
+        let mut announced = parse_nlri(&body[position..], AFI_IPV4)?;
+        withdrawn.append(&mut attributes.mp_unreach);
+        announced.append(&mut attributes.mp_reach);
+        Ok(Update {
+            withdrawn,
+            attributes,
+            announced,
+        })
+    }
+}
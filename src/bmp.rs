@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ByteOrder};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpListener;
+
+use super::bgp::Update;
+use super::prefix::{Prefix, PrefixMap};
+
+const VERSION: u8 = 3;
+const HEADER_LENGTH: usize = 6;
+const PER_PEER_HEADER_LENGTH: usize = 42;
+const MAX_MESSAGE_LENGTH: usize = 1 << 20;
+
+const ROUTE_MONITORING: u8 = 0;
+const STATISTICS_REPORT: u8 = 1;
+const PEER_DOWN: u8 = 2;
+const PEER_UP: u8 = 3;
+const ROUTE_MIRRORING: u8 = 6;
+
+const PEER_FLAG_IPV6: u8 = 0x80;
+const PEER_FLAG_POST_POLICY: u8 = 0x40;
+const PEER_FLAG_LEGACY_AS_PATH: u8 = 0x20;
+
+/// A monitored peer; routers may send both its pre-policy and post-policy
+/// Adj-RIB-In, which are kept apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerKey {
+    pub router: IpAddr,
+    pub distinguisher: u64,
+    pub addr: IpAddr,
+    pub post_policy: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Route {
+    pub as_path: Vec<u32>,
+    pub communities: Vec<u32>,
+    pub large_communities: Vec<(u32, u32, u32)>,
+}
+
+impl Route {
+    pub fn origin_as(&self) -> Option<u32> {
+        self.as_path.last().copied()
+    }
+
+    pub fn as_path_string(&self) -> String {
+        self.as_path
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Standard communities as `asn:value` followed by large communities as
+    /// `global:local1:local2`, separated by spaces.
+    pub fn communities_string(&self) -> String {
+        self.communities
+            .iter()
+            .map(|x| format!("{}:{}", x >> 16, x & 0xffff))
+            .chain(
+                self.large_communities
+                    .iter()
+                    .map(|(a, b, c)| format!("{}:{}:{}", a, b, c)),
+            )
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Routes received over BMP, kept per monitored peer.
+#[derive(Debug, Default)]
+pub struct Rib {
+    peers: BTreeMap<PeerKey, PrefixMap<Arc<Route>>>,
+}
+
+impl Rib {
+    pub fn new() -> Rib {
+        Rib::default()
+    }
+
+    pub fn peer_up(&mut self, peer: PeerKey) {
+        self.peers.insert(peer, PrefixMap::new());
+    }
+
+    /// Drops both the pre-policy and the post-policy routes of the peer.
+    pub fn peer_down(&mut self, peer: &PeerKey) {
+        self.peers.retain(|k, _| {
+            (k.router, k.distinguisher, k.addr) != (peer.router, peer.distinguisher, peer.addr)
+        });
+    }
+
+    pub fn router_down(&mut self, router: &IpAddr) {
+        self.peers.retain(|k, _| k.router != *router);
+    }
+
+    pub fn update(&mut self, peer: PeerKey, update: Update) {
+        let rib = self.peers.entry(peer).or_default();
+        for prefix in update.withdrawn.iter() {
+            rib.remove(prefix);
+        }
+        if update.announced.is_empty() {
+            return;
+        }
+        let route = Arc::new(Route {
+            as_path: update.attributes.as_path,
+            communities: update.attributes.communities,
+            large_communities: update.attributes.large_communities,
+        });
+        for prefix in update.announced {
+            rib.insert(prefix, route.clone());
+        }
+    }
+
+    /// Longest-prefix match across all peers; on equal lengths post-policy
+    /// routes win over pre-policy ones, then the first peer wins.
+    pub fn lookup(&self, addr: &IpAddr) -> Option<(Prefix, Arc<Route>)> {
+        let mut best: Option<(Prefix, Arc<Route>, bool)> = None;
+        for (peer, rib) in self.peers.iter() {
+            if let Some((prefix, route)) = rib.lookup(addr) {
+                if best.as_ref().is_none_or(|x| {
+                    prefix.len > x.0.len || (prefix.len == x.0.len && peer.post_policy && !x.2)
+                }) {
+                    best = Some((*prefix, route.clone(), peer.post_policy));
+                }
+            }
+        }
+        best.map(|(prefix, route, _)| (prefix, route))
+    }
+
+    pub fn peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn routes(&self) -> usize {
+        self.peers.values().map(|x| x.len()).sum()
+    }
+}
+
+/// Accepts BMP (RFC 7854) sessions from routers and applies route
+/// monitoring, peer up and peer down messages to the shared RIB.
+#[derive(Debug, Clone)]
+pub struct BmpServer {
+    rib: Arc<RwLock<Rib>>,
+}
+
+impl BmpServer {
+    pub fn new(rib: Arc<RwLock<Rib>>) -> BmpServer {
+        BmpServer { rib }
+    }
+
+    pub async fn run(self, addr: String) -> Result<()> {
+        let listener = TcpListener::bind(&addr).await?;
+        loop {
+            match listener.accept().await {
+                Ok((stream, router)) => {
+                    let rib = self.rib.clone();
+                    tokio::spawn(async move {
+                        let router = router.ip();
+                        eprintln!("bmp: {} connected", router);
+                        if let Err(e) = session(stream, router, &rib).await {
+                            eprintln!("bmp: {}: {}", router, e);
+                        }
+                        rib.write().unwrap().router_down(&router);
+                        eprintln!("bmp: {} disconnected", router);
+                    });
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn session<R: AsyncRead + Unpin>(
+    mut stream: R,
+    router: IpAddr,
+    rib: &Arc<RwLock<Rib>>,
+) -> Result<()> {
+    let mut header = [0u8; HEADER_LENGTH];
+    loop {
+        if let Err(e) = stream.read_exact(&mut header).await {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+        let version = header[0];
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let type_ = header[5];
+        if version != VERSION {
+            return Err(anyhow!("unsupported version {}", version));
+        }
+        if !(HEADER_LENGTH..=MAX_MESSAGE_LENGTH).contains(&length) {
+            return Err(anyhow!("invalid message length {}", length));
+        }
+        let mut message = vec![0u8; length - HEADER_LENGTH];
+        stream.read_exact(&mut message).await?;
+        if let Err(e) = handle(&message, type_, router, rib) {
+            eprintln!("bmp: {}: {}", router, e);
+        }
+    }
+}
+
+fn handle(message: &[u8], type_: u8, router: IpAddr, rib: &Arc<RwLock<Rib>>) -> Result<()> {
+    match type_ {
+        ROUTE_MONITORING | STATISTICS_REPORT | PEER_DOWN | PEER_UP | ROUTE_MIRRORING => {}
+        _ => return Ok(()),
+    }
+    if message.len() < PER_PEER_HEADER_LENGTH {
+        return Err(anyhow!("per-peer header too short"));
+    }
+    let flags = message[1];
+    let distinguisher = BigEndian::read_u64(&message[2..10]);
+    let addr = BigEndian::read_u128(&message[10..26]);
+    let addr = if flags & PEER_FLAG_IPV6 != 0 {
+        IpAddr::V6(Ipv6Addr::from(addr))
+    } else {
+        IpAddr::V4(Ipv4Addr::from(addr as u32))
+    };
+    let peer = PeerKey {
+        router,
+        distinguisher,
+        addr,
+        post_policy: flags & PEER_FLAG_POST_POLICY != 0,
+    };
+    let body = &message[PER_PEER_HEADER_LENGTH..];
+    match type_ {
+        ROUTE_MONITORING => {
+            let update = Update::parse(body, flags & PEER_FLAG_LEGACY_AS_PATH == 0)?;
+            rib.write().unwrap().update(peer, update);
+        }
+        PEER_UP => rib.write().unwrap().peer_up(peer),
+        PEER_DOWN => rib.write().unwrap().peer_down(&peer),
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn replay_keeps_policies_apart() {
+        let buf = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/bmp.bin"
+        ))
+        .unwrap();
+        let router = IpAddr::from_str("10.0.0.1").unwrap();
+        let rib = Arc::new(RwLock::new(Rib::new()));
+        session(buf.as_slice(), router, &rib).await.unwrap();
+
+        let mut rib = rib.write().unwrap();
+        assert_eq!(rib.peers(), 2);
+        assert_eq!(rib.routes(), 2);
+        // The pre-policy withdrawal leaves the post-policy route in place.
+        let (prefix, route) = rib.lookup(&IpAddr::from_str("192.0.2.1").unwrap()).unwrap();
+        assert_eq!(prefix, Prefix::from_str("192.0.2.0/24").unwrap());
+        assert_eq!(route.origin_as(), Some(64499));
+        let (_, route) = rib
+            .lookup(&IpAddr::from_str("198.51.100.1").unwrap())
+            .unwrap();
+        assert_eq!(route.as_path_string(), "64500 64497");
+
+        rib.peer_down(&PeerKey {
+            router,
+            distinguisher: 0,
+            addr: IpAddr::from_str("10.0.0.2").unwrap(),
+            post_policy: false,
+        });
+        assert_eq!(rib.peers(), 0);
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const PROTOCOL_ICMPV6: u8 = 58;
+pub const PROTOCOL_SCTP: u8 = 132;
+
+const PROTOCOLS: [(u8, &str); 26] = [
+    (0, "HOPOPT"),
+    (1, "ICMP"),
+    (2, "IGMP"),
+    (4, "IPv4"),
+    (6, "TCP"),
+    (8, "EGP"),
+    (17, "UDP"),
+    (41, "IPv6"),
+    (43, "IPv6-Route"),
+    (44, "IPv6-Frag"),
+    (46, "RSVP"),
+    (47, "GRE"),
+    (50, "ESP"),
+    (51, "AH"),
+    (58, "IPv6-ICMP"),
+    (59, "IPv6-NoNxt"),
+    (60, "IPv6-Opts"),
+    (88, "EIGRP"),
+    (89, "OSPF"),
+    (94, "IPIP"),
+    (103, "PIM"),
+    (112, "VRRP"),
+    (115, "L2TP"),
+    (132, "SCTP"),
+    (136, "UDPLite"),
+    (137, "MPLS-in-IP"),
+];
+
+const TCP_FLAGS: [&str; 8] = ["FIN", "SYN", "RST", "PSH", "ACK", "URG", "ECE", "CWR"];
+
+/// Well-known services used when no IANA table is loaded.
+const SERVICES: [(u16, &str); 46] = [
+    (20, "ftp-data"),
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (53, "domain"),
+    (67, "bootps"),
+    (68, "bootpc"),
+    (69, "tftp"),
+    (80, "http"),
+    (88, "kerberos"),
+    (110, "pop3"),
+    (119, "nntp"),
+    (123, "ntp"),
+    (137, "netbios-ns"),
+    (138, "netbios-dgm"),
+    (139, "netbios-ssn"),
+    (143, "imap"),
+    (161, "snmp"),
+    (162, "snmptrap"),
+    (179, "bgp"),
+    (389, "ldap"),
+    (443, "https"),
+    (445, "microsoft-ds"),
+    (500, "isakmp"),
+    (514, "syslog"),
+    (587, "submission"),
+    (636, "ldaps"),
+    (853, "domain-s"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (1194, "openvpn"),
+    (1812, "radius"),
+    (1813, "radius-acct"),
+    (1900, "ssdp"),
+    (3306, "mysql"),
+    (3389, "ms-wbt-server"),
+    (4500, "ipsec-nat-t"),
+    (4739, "ipfix"),
+    (5060, "sip"),
+    (5061, "sips"),
+    (5432, "postgresql"),
+    (6379, "redis"),
+    (8080, "http-alt"),
+    (11211, "memcache"),
+    (27017, "mongodb"),
+];
+
+/// The IANA keyword of a protocol number, or the number itself.
+pub fn protocol_name(protocol: u8) -> String {
+    PROTOCOLS
+        .iter()
+        .find(|(x, _)| *x == protocol)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| protocol.to_string())
+}
+
+/// TCP flags as a comma separated list such as `SYN,ACK`.
+pub fn tcp_flags_name(tcp_flags: u8) -> String {
+    TCP_FLAGS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| tcp_flags & (1 << i) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+/// A MAC address in the low 48 bits, formatted as `00:11:22:33:44:55`.
+pub fn mac_string(mac: u64) -> String {
+    mac.to_be_bytes()[2..]
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Splits an ICMP type and code packed as `type * 256 + code`.
+pub fn icmp_type_code(icmp_type: u16) -> (u8, u8) {
+    ((icmp_type >> 8) as u8, icmp_type as u8)
+}
+
+/// Service names by transport protocol and port.
+#[derive(Debug, Clone)]
+pub struct Services {
+    names: HashMap<(u8, u16), String>,
+}
+
+impl Services {
+    pub fn new() -> Services {
+        let mut names = HashMap::new();
+        for protocol in [PROTOCOL_TCP, PROTOCOL_UDP, PROTOCOL_SCTP].iter() {
+            for (port, name) in SERVICES.iter() {
+                names.insert((*protocol, *port), name.to_string());
+            }
+        }
+        Services { names }
+    }
+
+    /// Loads the IANA `service-names-port-numbers.csv` registry, replacing
+    /// the built-in names. Port ranges are expanded, and the first name
+    /// registered for a port wins.
+    pub fn load(path: &str) -> Result<Services> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_path(path)
+            .map_err(|e| anyhow!("{}: {}", path, e))?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|x| x == name)
+                .ok_or_else(|| anyhow!("{}: no {} column", path, name))
+        };
+        let name_index = column("Service Name")?;
+        let port_index = column("Port Number")?;
+        let protocol_index = column("Transport Protocol")?;
+
+        let mut names = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let name = record.get(name_index).unwrap_or_default();
+            let protocol = match record.get(protocol_index).unwrap_or_default() {
+                "tcp" => PROTOCOL_TCP,
+                "udp" => PROTOCOL_UDP,
+                "sctp" => PROTOCOL_SCTP,
+                _ => continue,
+            };
+            let port = record.get(port_index).unwrap_or_default();
+            let (first, last) = port.split_once('-').unwrap_or((port, port));
+            let (first, last) = match (first.parse::<u16>(), last.parse::<u16>()) {
+                (Ok(first), Ok(last)) if !name.is_empty() => (first, last),
+                _ => continue,
+            };
+            for port in first..=last {
+                names
+                    .entry((protocol, port))
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+        Ok(Services { names })
+    }
+
+    pub fn get(&self, protocol: u8, port: u16) -> Option<&String> {
+        self.names.get(&(protocol, port))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl Default for Services {
+    fn default() -> Services {
+        Services::new()
+    }
+}
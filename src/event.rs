@@ -0,0 +1,58 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+
+/// Records derived from the flow stream, published separately from `FlowMessage`s.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    #[serde(rename = "topn")]
+    TopN(TopNReport),
+    Alert(Alert),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopNReport {
+    pub datetime: String,
+    pub window_start: u64,
+    pub window_end: u64,
+    pub key: String,
+    pub metric: String,
+    pub entries: Vec<TopNEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopNEntry {
+    pub rank: usize,
+    pub key: Map<String, Value>,
+    pub value: u64,
+    /// Upper bound of the overestimation of `value` by the sketch.
+    pub error: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Start,
+    Ongoing,
+    End,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub datetime: String,
+    pub status: AlertStatus,
+    pub attack: String,
+    pub dst_addr: IpAddr,
+    pub prefix: String,
+    pub started_at: u64,
+    pub pps: u64,
+    pub bps: u64,
+    pub fps: u64,
+    pub peak_pps: u64,
+    pub peak_bps: u64,
+    pub peak_fps: u64,
+    pub threshold_pps: Option<u64>,
+    pub threshold_bps: Option<u64>,
+    pub threshold_fps: Option<u64>,
+}
@@ -0,0 +1,508 @@
+use super::flowmessage::{FieldValue, Float, FlowMessage};
+use super::prefix::Prefix;
+use super::util::parse_number;
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Literal {
+    Uint(u64),
+    Float(Float),
+    Ip(IpAddr),
+    Prefix(Prefix),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(String, Op, Literal),
+    In(String, Vec<Literal>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn new(source: &str) -> Result<Filter> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("filter: unexpected token {:?}", token));
+        }
+        Ok(Filter {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// A filter applied before the processors, which rejects the fields
+    /// only processors fill in since they would never match.
+    pub fn global(source: &str) -> Result<Filter> {
+        let filter = Filter::new(source)?;
+        if let Some(field) = fields(&filter.expr)
+            .into_iter()
+            .find(|x| FlowMessage::is_processor_field(x))
+        {
+            return Err(anyhow!(
+                "filter: {} is set by processors and can't be used in the global filter, use a publisher filter instead",
+                field
+            ));
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, flowmessage: &FlowMessage) -> bool {
+        eval(&self.expr, flowmessage)
+    }
+
+    pub fn apply(&self, flowmessages: Vec<FlowMessage>) -> Vec<FlowMessage> {
+        flowmessages
+            .into_iter()
+            .filter(|x| self.matches(x))
+            .collect()
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval(expr: &Expr, flowmessage: &FlowMessage) -> bool {
+    match expr {
+        Expr::And(a, b) => eval(a, flowmessage) && eval(b, flowmessage),
+        Expr::Or(a, b) => eval(a, flowmessage) || eval(b, flowmessage),
+        Expr::Not(a) => !eval(a, flowmessage),
+        Expr::Cmp(field, op, literal) => match flowmessage.field(field) {
+            Some(value) => compare(&value, *op, literal),
+            None => false,
+        },
+        Expr::In(field, literals) => match flowmessage.field(field) {
+            Some(value) => literals.iter().any(|x| compare(&value, Op::Eq, x)),
+            None => false,
+        },
+    }
+}
+
+fn fields(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            let mut fields = self::fields(a);
+            fields.extend(self::fields(b));
+            fields
+        }
+        Expr::Not(a) => fields(a),
+        Expr::Cmp(field, _, _) | Expr::In(field, _) => vec![field.as_str()],
+    }
+}
+
+fn compare(value: &FieldValue, op: Op, literal: &Literal) -> bool {
+    match (value, literal) {
+        (FieldValue::Uint(a), Literal::Uint(b)) => ordering(a, op, b),
+        (FieldValue::Float(a), Literal::Float(b)) => ordering(&a.0, op, &b.0),
+        (FieldValue::Float(a), Literal::Uint(b)) => ordering(&a.0, op, &(*b as f64)),
+        (FieldValue::Uint(a), Literal::Float(b)) => ordering(&(*a as f64), op, &b.0),
+        // Addresses of different families are unequal and unordered.
+        (FieldValue::Ip(a), Literal::Ip(b)) if a.is_ipv4() != b.is_ipv4() => op == Op::Ne,
+        (FieldValue::Ip(a), Literal::Ip(b)) => ordering(a, op, b),
+        (FieldValue::Ip(a), Literal::Prefix(b)) => match op {
+            Op::Eq => b.contains(a),
+            Op::Ne => !b.contains(a),
+            _ => false,
+        },
+        (FieldValue::SocketAddr(a), Literal::Ip(_))
+        | (FieldValue::SocketAddr(a), Literal::Prefix(_)) => {
+            compare(&FieldValue::Ip(a.ip()), op, literal)
+        }
+        (FieldValue::Str(a), Literal::Str(b)) => ordering(a, op, b),
+        (value, Literal::Str(b)) => ordering(&value.to_string(), op, b),
+        (FieldValue::Str(a), literal) => ordering(a, op, &literal_to_string(literal)),
+        _ => false,
+    }
+}
+
+fn ordering<T: PartialOrd>(a: &T, op: Op, b: &T) -> bool {
+    match op {
+        Op::Eq => a == b,
+        Op::Ne => a != b,
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+    }
+}
+
+fn literal_to_string(literal: &Literal) -> String {
+    match literal {
+        Literal::Uint(x) => x.to_string(),
+        Literal::Float(x) => x.0.to_string(),
+        Literal::Ip(x) => x.to_string(),
+        Literal::Prefix(x) => x.to_string(),
+        Literal::Str(x) => x.clone(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    In,
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                i += 2;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                i += 2;
+            }
+            '=' => {
+                tokens.push(Token::Op(Op::Eq));
+                i += if next == Some('=') { 2 } else { 1 };
+            }
+            '!' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Ne));
+                i += 2;
+            }
+            '!' => {
+                tokens.push(Token::Not);
+                i += 1;
+            }
+            '<' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Le));
+                i += 2;
+            }
+            '<' => {
+                tokens.push(Token::Op(Op::Lt));
+                i += 1;
+            }
+            '>' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Ge));
+                i += 2;
+            }
+            '>' => {
+                tokens.push(Token::Op(Op::Gt));
+                i += 1;
+            }
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&x| x == c)
+                    .ok_or_else(|| anyhow!("filter: unterminated string"))?;
+                tokens.push(Token::Quoted(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            c if is_word_char(c) => {
+                let start = i;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.to_lowercase().as_str() {
+                    "and" => tokens.push(Token::And),
+                    "or" => tokens.push(Token::Or),
+                    "not" => tokens.push(Token::Not),
+                    "in" => tokens.push(Token::In),
+                    _ => tokens.push(Token::Word(word)),
+                }
+            }
+            c => return Err(anyhow!("filter: unexpected character {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '/' || c == '-'
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("filter: unexpected end of expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(anyhow!(
+                "filter: expected {:?}, found {:?}",
+                expected,
+                token
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Token::Word(field) => {
+                if !FlowMessage::has_field(&field) {
+                    return Err(anyhow!("filter: unknown field {}", field));
+                }
+                match self.next()? {
+                    Token::Op(op) => Ok(Expr::Cmp(field, op, self.parse_literal()?)),
+                    Token::In => {
+                        if self.peek() != Some(&Token::LParen) {
+                            return Ok(Expr::In(field, vec![self.parse_literal()?]));
+                        }
+                        self.pos += 1;
+                        let mut literals = vec![self.parse_literal()?];
+                        while self.peek() == Some(&Token::Comma) {
+                            self.pos += 1;
+                            literals.push(self.parse_literal()?);
+                        }
+                        self.expect(Token::RParen)?;
+                        Ok(Expr::In(field, literals))
+                    }
+                    token => Err(anyhow!("filter: expected operator, found {:?}", token)),
+                }
+            }
+            token => Err(anyhow!("filter: expected field, found {:?}", token)),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal> {
+        match self.next()? {
+            Token::Quoted(x) => Ok(Literal::Str(x)),
+            Token::Word(x) => Ok(parse_word(&x)),
+            token => Err(anyhow!("filter: expected value, found {:?}", token)),
+        }
+    }
+}
+
+fn parse_word(word: &str) -> Literal {
+    if let Some(x) = parse_number(word) {
+        return Literal::Uint(x);
+    }
+    // Plain decimals only, so that words like `inf` and `nan` stay strings.
+    if word.bytes().all(|x| x.is_ascii_digit() || x == b'.') {
+        if let Ok(x) = f64::from_str(word) {
+            return Literal::Float(Float(x));
+        }
+    }
+    if let Ok(x) = IpAddr::from_str(word) {
+        return Literal::Ip(x);
+    }
+    if let Ok(x) = Prefix::from_str(word) {
+        return Literal::Prefix(x);
+    }
+    Literal::Str(word.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    fn flow(src_addr: &str, proto: u64, bytes: u64) -> FlowMessage {
+        let mut flowmessage = FlowMessageBuilder::default().build().unwrap();
+        let src_addr = FieldValue::Ip(src_addr.parse().unwrap());
+        flowmessage.set_field("src_addr", Some(src_addr)).unwrap();
+        flowmessage
+            .set_field("proto", Some(FieldValue::Uint(proto)))
+            .unwrap();
+        flowmessage
+            .set_field("bytes", Some(FieldValue::Uint(bytes)))
+            .unwrap();
+        flowmessage
+    }
+
+    fn matches(source: &str, flowmessage: &FlowMessage) -> bool {
+        Filter::new(source).unwrap().matches(flowmessage)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = Filter::new("proto == 6 or proto == 17 and bytes > 1K")
+            .unwrap()
+            .expr;
+        assert_eq!(
+            expr,
+            Expr::Or(
+                Box::new(Expr::Cmp("proto".to_string(), Op::Eq, Literal::Uint(6))),
+                Box::new(Expr::And(
+                    Box::new(Expr::Cmp("proto".to_string(), Op::Eq, Literal::Uint(17))),
+                    Box::new(Expr::Cmp("bytes".to_string(), Op::Gt, Literal::Uint(1000))),
+                )),
+            )
+        );
+        let tcp = flow("10.0.0.1", 6, 10);
+        assert!(matches("proto == 6 or proto == 17 and bytes > 1K", &tcp));
+        assert!(!matches("(proto == 6 or proto == 17) and bytes > 1K", &tcp));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let tcp = flow("10.0.0.1", 6, 10);
+        assert!(!matches("not proto == 6 and bytes < 100", &tcp));
+        assert!(matches("not (proto == 6 and bytes > 100)", &tcp));
+        assert!(matches("! ! proto == 6", &tcp));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Filter::new("proto == ").is_err());
+        assert!(Filter::new("(proto == 6").is_err());
+        assert!(Filter::new("proto == 6 6").is_err());
+        assert!(Filter::new("no_such_field == 6").is_err());
+        assert!(Filter::new("src_service == 'https").is_err());
+    }
+
+    #[test]
+    fn prefixes() {
+        let v4 = flow("10.1.2.3", 6, 10);
+        let v6 = flow("2001:db8::1", 6, 10);
+        assert!(matches("src_addr in 10.0.0.0/8", &v4));
+        assert!(matches("src_addr == 10.1.0.0/16", &v4));
+        assert!(!matches("src_addr in 10.2.0.0/16", &v4));
+        assert!(matches("src_addr != 10.2.0.0/16", &v4));
+        assert!(matches("src_addr in (192.0.2.0/24, 2001:db8::/32)", &v6));
+        assert!(!matches("src_addr in 2001:db9::/32", &v6));
+    }
+
+    #[test]
+    fn mixed_family_addresses() {
+        let v4 = flow("10.1.2.3", 6, 10);
+        let v6 = flow("2001:db8::1", 6, 10);
+        assert!(!matches("src_addr == ::ffff:10.1.2.3", &v4));
+        assert!(matches("src_addr != ::1", &v4));
+        assert!(!matches("src_addr < ::1", &v4));
+        assert!(!matches("src_addr > 10.0.0.0", &v6));
+        assert!(!matches("src_addr in 0.0.0.0/0", &v6));
+        assert!(matches("src_addr in (0.0.0.0/0, ::/0)", &v6));
+        assert!(matches("src_addr > 10.1.2.2 and src_addr <= 10.1.2.3", &v4));
+    }
+
+    #[test]
+    fn float_fields() {
+        let mut flowmessage = flow("10.1.2.3", 6, 10);
+        flowmessage
+            .set_field("in_if_utilization", Some(FieldValue::Float(Float(12.5))))
+            .unwrap();
+        assert!(matches("in_if_utilization > 12.25", &flowmessage));
+        assert!(matches("in_if_utilization > 12", &flowmessage));
+        assert!(matches("in_if_utilization < 13", &flowmessage));
+        assert!(!matches("in_if_utilization >= 100", &flowmessage));
+        assert!(matches("in_if_utilization == 12.50", &flowmessage));
+        assert!(matches("bytes < 10.5", &flowmessage));
+        assert_eq!(
+            serde_json::Value::from(flowmessage.field("in_if_utilization").unwrap()),
+            serde_json::json!(12.5)
+        );
+        // CSV and table output are not rounded.
+        flowmessage
+            .set_field("in_if_utilization", Some(FieldValue::Float(Float(12.345))))
+            .unwrap();
+        assert_eq!(
+            flowmessage.field("in_if_utilization").unwrap().to_string(),
+            "12.345"
+        );
+    }
+
+    #[test]
+    fn global_rejects_processor_fields() {
+        assert!(Filter::global("proto == 6 and src_addr in 10.0.0.0/8").is_ok());
+        assert!(Filter::global("proto == 6 and not window_start > 0").is_err());
+        assert!(Filter::global("proto == 6 and not src_country == NL").is_err());
+        assert!(Filter::new("window_start > 0").is_ok());
+        assert!(Filter::global("src_tag.customer == acme").is_err());
+        assert!(Filter::new("src_tag.customer == acme").is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+use super::flowmessage::{FieldValue, FlowMessage};
+use super::prefix::mask;
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyField {
+    pub field: String,
+    pub v4_len: Option<u8>,
+    pub v6_len: Option<u8>,
+}
+
+impl KeyField {
+    fn value(&self, flowmessage: &FlowMessage) -> Option<FieldValue> {
+        match flowmessage.field(&self.field)? {
+            FieldValue::Ip(addr) => {
+                let len = match addr {
+                    IpAddr::V4(_) => self.v4_len,
+                    IpAddr::V6(_) => self.v6_len,
+                };
+                match len {
+                    Some(len) => Some(FieldValue::Ip(mask(&addr, len))),
+                    None => Some(FieldValue::Ip(addr)),
+                }
+            }
+            x => Some(x),
+        }
+    }
+}
+
+impl Display for KeyField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.field)?;
+        if let Some(v4_len) = self.v4_len {
+            write!(f, "/{}", v4_len)?;
+        }
+        if let Some(v6_len) = self.v6_len {
+            write!(f, "/{}", v6_len)?;
+        }
+        Ok(())
+    }
+}
+
+/// A tuple of flow fields used to group flows, e.g. `exporter,src_addr/24/64,dst_port,proto`.
+/// Address fields take an optional IPv4 and IPv6 prefix length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowKey {
+    pub fields: Vec<KeyField>,
+}
+
+impl FlowKey {
+    pub fn new(spec: &str) -> Result<FlowKey> {
+        let mut fields = Vec::new();
+        for item in spec.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let mut parts = item.split('/');
+            let field = parts.next().unwrap_or_default();
+            if !FlowMessage::has_field(field) {
+                return Err(anyhow!("key: unknown field {}", field));
+            }
+            let v4_len = parts.next().map(|x| parse_len(x, 32)).transpose()?;
+            let v6_len = parts.next().map(|x| parse_len(x, 128)).transpose()?;
+            if parts.next().is_some() {
+                return Err(anyhow!("key: invalid field {}", item));
+            }
+            fields.push(KeyField {
+                field: field.to_string(),
+                v4_len,
+                v6_len,
+            });
+        }
+        if fields.is_empty() {
+            return Err(anyhow!("key: no fields"));
+        }
+        Ok(FlowKey { fields })
+    }
+
+    pub fn values(&self, flowmessage: &FlowMessage) -> Vec<Option<FieldValue>> {
+        self.fields.iter().map(|x| x.value(flowmessage)).collect()
+    }
+
+    /// Writes key values back into a flow message, setting the matching mask
+    /// field for aggregated source and destination addresses.
+    pub fn apply(
+        &self,
+        flowmessage: &mut FlowMessage,
+        values: &[Option<FieldValue>],
+    ) -> Result<()> {
+        for (field, value) in self.fields.iter().zip(values) {
+            flowmessage.set_field(&field.field, value.clone())?;
+            let len = match value {
+                Some(FieldValue::Ip(IpAddr::V4(_))) => field.v4_len,
+                Some(FieldValue::Ip(IpAddr::V6(_))) => field.v6_len,
+                _ => None,
+            };
+            if let (Some(len), Some(FieldValue::Ip(addr))) = (len, value) {
+                let mask_field = match (field.field.as_str(), addr) {
+                    ("src_addr", IpAddr::V4(_)) | ("ipv4_src_addr", _) => "src_mask",
+                    ("dst_addr", IpAddr::V4(_)) | ("ipv4_dst_addr", _) => "dst_mask",
+                    ("src_addr", IpAddr::V6(_)) | ("ipv6_src_addr", _) => "ipv6_src_mask",
+                    ("dst_addr", IpAddr::V6(_)) | ("ipv6_dst_addr", _) => "ipv6_dst_mask",
+                    _ => continue,
+                };
+                flowmessage.set_field(mask_field, Some(FieldValue::Uint(len as u64)))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn format(&self, values: &[Option<FieldValue>]) -> String {
+        values
+            .iter()
+            .map(|x| x.as_ref().map(|x| x.to_string()).unwrap_or_default())
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+impl Display for FlowKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fields = self
+            .fields
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{}", fields.join(","))
+    }
+}
+
+fn parse_len(v: &str, max_len: u8) -> Result<u8> {
+    let len = v
+        .parse::<u8>()
+        .map_err(|_| anyhow!("key: invalid prefix length {}", v))?;
+    if len > max_len {
+        return Err(anyhow!("key: prefix length {} is too long", len));
+    }
+    Ok(len)
+}
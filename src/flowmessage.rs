@@ -1,240 +1,1127 @@
 use serde::{Deserialize, Serialize};
 
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
 use derive_builder::Builder;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use field_types::FieldName;
 
-#[derive(Builder, Debug, Serialize, Deserialize, FieldName)]
+#[derive(Builder, Debug, Clone, Serialize, Deserialize, FieldName)]
 pub struct FlowMessage {
     #[builder(setter(into, strip_option), default)]
-    datetime: Option<String>,
+    pub datetime: Option<String>,
 
     #[builder(setter(into, strip_option), default)]
-    exporter_addr: Option<SocketAddr>,
+    pub exporter_addr: Option<SocketAddr>,
 
     #[builder(setter(into, strip_option), default)]
-    version: Option<u16>,
+    pub version: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    sys_up_time: Option<u32>,
+    pub sys_up_time: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    unix_secs: Option<u32>,
+    pub unix_secs: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    unix_nsecs: Option<u32>,
+    pub unix_nsecs: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_sequence: Option<u32>,
+    pub flow_sequence: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    engine_type: Option<u8>,
+    pub engine_type: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    engine_id: Option<u8>,
+    pub engine_id: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    sampling_interval: Option<u32>,
+    pub sampling_interval: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv4_src_addr: Option<Ipv4Addr>,
+    pub ipv4_src_addr: Option<Ipv4Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv4_dst_addr: Option<Ipv4Addr>,
+    pub ipv4_dst_addr: Option<Ipv4Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv4_next_hop: Option<Ipv4Addr>,
+    pub ipv4_next_hop: Option<Ipv4Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    input: Option<u16>,
+    pub input: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    output: Option<u16>,
+    pub output: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    dpkts: Option<u32>,
+    pub dpkts: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    d0ctets: Option<u32>,
+    pub d0ctets: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    first: Option<u32>,
+    pub first: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    last: Option<u32>,
+    pub last: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    src_port: Option<u16>,
+    pub src_port: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_port: Option<u16>,
+    pub dst_port: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    tcp_flags: Option<u8>,
+    pub tcp_flags: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    tos: Option<u8>,
+    pub tos: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    src_as: Option<u32>,
+    pub src_as: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_as: Option<u32>,
+    pub dst_as: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    src_mask: Option<u8>,
+    pub src_mask: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_mask: Option<u8>,
+    pub dst_mask: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    in_bytes: Option<usize>,
+    pub in_bytes: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    in_pkts: Option<usize>,
+    pub in_pkts: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    flows: Option<usize>,
+    pub flows: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    protocol: Option<u8>,
+    pub protocol: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    input_snmp: Option<usize>,
+    pub input_snmp: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    output_snmp: Option<usize>,
+    pub output_snmp: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    bgp_ipv4_next_hop: Option<Ipv4Addr>,
+    pub bgp_ipv4_next_hop: Option<Ipv4Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    mul_dst_pkts: Option<usize>,
+    pub mul_dst_pkts: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    mul_dst_bytes: Option<usize>,
+    pub mul_dst_bytes: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    last_switched: Option<u32>,
+    pub last_switched: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    first_switched: Option<u32>,
+    pub first_switched: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    out_bytes: Option<usize>,
+    pub out_bytes: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    out_pkts: Option<usize>,
+    pub out_pkts: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_src_addr: Option<Ipv6Addr>,
+    pub ipv6_src_addr: Option<Ipv6Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_dst_addr: Option<Ipv6Addr>,
+    pub ipv6_dst_addr: Option<Ipv6Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_src_mask: Option<u8>,
+    pub ipv6_src_mask: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_dst_mask: Option<u8>,
+    pub ipv6_dst_mask: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_flow_label: Option<usize>,
+    pub ipv6_flow_label: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    icmp_type: Option<u16>,
+    pub icmp_type: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    mul_igmp_type: Option<u8>,
+    pub mul_igmp_type: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    sampling_algorithm: Option<u8>,
+    pub sampling_algorithm: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_active_timeout: Option<u16>,
+    pub flow_active_timeout: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_inactive_timeout: Option<u16>,
+    pub flow_inactive_timeout: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    total_bytes_exp: Option<usize>,
+    pub total_bytes_exp: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    total_pkts_exp: Option<usize>,
+    pub total_pkts_exp: Option<usize>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_top_label: Option<u8>,
+    pub mpls_top_label: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_top_label_ip_addr: Option<u32>,
+    pub mpls_top_label_ip_addr: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_sampler_id: Option<u8>,
+    pub flow_sampler_id: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_sampler_mode: Option<u8>,
+    pub flow_sampler_mode: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    flow_sampler_random_interval: Option<u32>,
+    pub flow_sampler_random_interval: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_tos: Option<u8>,
+    pub dst_tos: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    src_mac: Option<u64>,
+    pub src_mac: Option<u64>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_mac: Option<u64>,
+    pub dst_mac: Option<u64>,
 
     #[builder(setter(into, strip_option), default)]
-    src_vlan: Option<u16>,
+    pub src_vlan: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    dst_vlan: Option<u16>,
+    pub dst_vlan: Option<u16>,
 
     #[builder(setter(into, strip_option), default)]
-    ip_protocol_version: Option<u8>,
+    pub ip_protocol_version: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    direction: Option<u8>,
+    pub direction: Option<u8>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_next_hop: Option<Ipv6Addr>,
+    pub ipv6_next_hop: Option<Ipv6Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    bgp_ipv6_next_hop: Option<Ipv6Addr>,
+    pub bgp_ipv6_next_hop: Option<Ipv6Addr>,
 
     #[builder(setter(into, strip_option), default)]
-    ipv6_option_headers: Option<u32>,
+    pub ipv6_option_headers: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_1: Option<u32>,
+    pub mpls_label_1: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_2: Option<u32>,
+    pub mpls_label_2: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_3: Option<u32>,
+    pub mpls_label_3: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_4: Option<u32>,
+    pub mpls_label_4: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_5: Option<u32>,
+    pub mpls_label_5: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_6: Option<u32>,
+    pub mpls_label_6: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_7: Option<u32>,
+    pub mpls_label_7: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_8: Option<u32>,
+    pub mpls_label_8: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_9: Option<u32>,
+    pub mpls_label_9: Option<u32>,
 
     #[builder(setter(into, strip_option), default)]
-    mpls_label_10: Option<u32>,
+    pub mpls_label_10: Option<u32>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub application_engine_id: Option<u8>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub application_selector_id: Option<usize>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub application_name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub application_description: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub window_start: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub window_end: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_country: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_country: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_city: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_city: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_asn_org: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_asn_org: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_tags: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_tags: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_as_path: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_as_path: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_communities: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_communities: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_origin_as: Option<u32>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_origin_as: Option<u32>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub in_if_name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub out_if_name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub in_if_description: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub out_if_description: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub in_if_speed: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub out_if_speed: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub in_if_utilization: Option<f64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub out_if_utilization: Option<f64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub protocol_name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_service: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_service: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub tcp_flags_name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub icmp_msg_type: Option<u8>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub icmp_msg_code: Option<u8>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub src_mac_addr: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub dst_mac_addr: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub fwd_bytes: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub fwd_packets: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub fwd_tcp_flags: Option<u8>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub rev_bytes: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub rev_packets: Option<u64>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub rev_tcp_flags: Option<u8>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub duplicate_of: Option<IpAddr>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub sequence_lost: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Uint32,
+    Uint64,
+    Str,
+    Ip,
+    SocketAddr,
+    Double,
+}
+
+/// The type of every field in declaration order. Serialized schemas number
+/// fields by their position here, so fields are only ever appended.
+pub const FIELD_TYPES: [(&str, FieldType); 121] = [
+    ("datetime", FieldType::Str),
+    ("exporter_addr", FieldType::SocketAddr),
+    ("version", FieldType::Uint32),
+    ("sys_up_time", FieldType::Uint32),
+    ("unix_secs", FieldType::Uint32),
+    ("unix_nsecs", FieldType::Uint32),
+    ("flow_sequence", FieldType::Uint32),
+    ("engine_type", FieldType::Uint32),
+    ("engine_id", FieldType::Uint32),
+    ("sampling_interval", FieldType::Uint32),
+    ("ipv4_src_addr", FieldType::Ip),
+    ("ipv4_dst_addr", FieldType::Ip),
+    ("ipv4_next_hop", FieldType::Ip),
+    ("input", FieldType::Uint32),
+    ("output", FieldType::Uint32),
+    ("dpkts", FieldType::Uint32),
+    ("d0ctets", FieldType::Uint32),
+    ("first", FieldType::Uint32),
+    ("last", FieldType::Uint32),
+    ("src_port", FieldType::Uint32),
+    ("dst_port", FieldType::Uint32),
+    ("tcp_flags", FieldType::Uint32),
+    ("tos", FieldType::Uint32),
+    ("src_as", FieldType::Uint32),
+    ("dst_as", FieldType::Uint32),
+    ("src_mask", FieldType::Uint32),
+    ("dst_mask", FieldType::Uint32),
+    ("in_bytes", FieldType::Uint64),
+    ("in_pkts", FieldType::Uint64),
+    ("flows", FieldType::Uint64),
+    ("protocol", FieldType::Uint32),
+    ("input_snmp", FieldType::Uint64),
+    ("output_snmp", FieldType::Uint64),
+    ("bgp_ipv4_next_hop", FieldType::Ip),
+    ("mul_dst_pkts", FieldType::Uint64),
+    ("mul_dst_bytes", FieldType::Uint64),
+    ("last_switched", FieldType::Uint32),
+    ("first_switched", FieldType::Uint32),
+    ("out_bytes", FieldType::Uint64),
+    ("out_pkts", FieldType::Uint64),
+    ("ipv6_src_addr", FieldType::Ip),
+    ("ipv6_dst_addr", FieldType::Ip),
+    ("ipv6_src_mask", FieldType::Uint32),
+    ("ipv6_dst_mask", FieldType::Uint32),
+    ("ipv6_flow_label", FieldType::Uint64),
+    ("icmp_type", FieldType::Uint32),
+    ("mul_igmp_type", FieldType::Uint32),
+    ("sampling_algorithm", FieldType::Uint32),
+    ("flow_active_timeout", FieldType::Uint32),
+    ("flow_inactive_timeout", FieldType::Uint32),
+    ("total_bytes_exp", FieldType::Uint64),
+    ("total_pkts_exp", FieldType::Uint64),
+    ("mpls_top_label", FieldType::Uint32),
+    ("mpls_top_label_ip_addr", FieldType::Uint32),
+    ("flow_sampler_id", FieldType::Uint32),
+    ("flow_sampler_mode", FieldType::Uint32),
+    ("flow_sampler_random_interval", FieldType::Uint32),
+    ("dst_tos", FieldType::Uint32),
+    ("src_mac", FieldType::Uint64),
+    ("dst_mac", FieldType::Uint64),
+    ("src_vlan", FieldType::Uint32),
+    ("dst_vlan", FieldType::Uint32),
+    ("ip_protocol_version", FieldType::Uint32),
+    ("direction", FieldType::Uint32),
+    ("ipv6_next_hop", FieldType::Ip),
+    ("bgp_ipv6_next_hop", FieldType::Ip),
+    ("ipv6_option_headers", FieldType::Uint32),
+    ("mpls_label_1", FieldType::Uint32),
+    ("mpls_label_2", FieldType::Uint32),
+    ("mpls_label_3", FieldType::Uint32),
+    ("mpls_label_4", FieldType::Uint32),
+    ("mpls_label_5", FieldType::Uint32),
+    ("mpls_label_6", FieldType::Uint32),
+    ("mpls_label_7", FieldType::Uint32),
+    ("mpls_label_8", FieldType::Uint32),
+    ("mpls_label_9", FieldType::Uint32),
+    ("mpls_label_10", FieldType::Uint32),
+    ("application_engine_id", FieldType::Uint32),
+    ("application_selector_id", FieldType::Uint64),
+    ("application_name", FieldType::Str),
+    ("application_description", FieldType::Str),
+    ("window_start", FieldType::Uint64),
+    ("window_end", FieldType::Uint64),
+    ("src_country", FieldType::Str),
+    ("dst_country", FieldType::Str),
+    ("src_city", FieldType::Str),
+    ("dst_city", FieldType::Str),
+    ("src_asn_org", FieldType::Str),
+    ("dst_asn_org", FieldType::Str),
+    ("src_tags", FieldType::Str),
+    ("dst_tags", FieldType::Str),
+    ("src_as_path", FieldType::Str),
+    ("dst_as_path", FieldType::Str),
+    ("src_communities", FieldType::Str),
+    ("dst_communities", FieldType::Str),
+    ("src_origin_as", FieldType::Uint32),
+    ("dst_origin_as", FieldType::Uint32),
+    ("in_if_name", FieldType::Str),
+    ("out_if_name", FieldType::Str),
+    ("in_if_description", FieldType::Str),
+    ("out_if_description", FieldType::Str),
+    ("in_if_speed", FieldType::Uint64),
+    ("out_if_speed", FieldType::Uint64),
+    ("in_if_utilization", FieldType::Double),
+    ("out_if_utilization", FieldType::Double),
+    ("protocol_name", FieldType::Str),
+    ("src_service", FieldType::Str),
+    ("dst_service", FieldType::Str),
+    ("tcp_flags_name", FieldType::Str),
+    ("icmp_msg_type", FieldType::Uint32),
+    ("icmp_msg_code", FieldType::Uint32),
+    ("src_mac_addr", FieldType::Str),
+    ("dst_mac_addr", FieldType::Str),
+    ("fwd_bytes", FieldType::Uint64),
+    ("fwd_packets", FieldType::Uint64),
+    ("fwd_tcp_flags", FieldType::Uint32),
+    ("rev_bytes", FieldType::Uint64),
+    ("rev_packets", FieldType::Uint64),
+    ("rev_tcp_flags", FieldType::Uint32),
+    ("duplicate_of", FieldType::Ip),
+    ("sequence_lost", FieldType::Uint32),
+];
+
+/// Fields that only processors fill in, unset before the processor chain.
+pub const PROCESSOR_FIELDS: [&str; 39] = [
+    "window_start",
+    "window_end",
+    "src_country",
+    "dst_country",
+    "src_city",
+    "dst_city",
+    "src_asn_org",
+    "dst_asn_org",
+    "src_tags",
+    "dst_tags",
+    "src_as_path",
+    "dst_as_path",
+    "src_communities",
+    "dst_communities",
+    "src_origin_as",
+    "dst_origin_as",
+    "in_if_name",
+    "out_if_name",
+    "in_if_description",
+    "out_if_description",
+    "in_if_speed",
+    "out_if_speed",
+    "in_if_utilization",
+    "out_if_utilization",
+    "protocol_name",
+    "src_service",
+    "dst_service",
+    "tcp_flags_name",
+    "icmp_msg_type",
+    "icmp_msg_code",
+    "src_mac_addr",
+    "dst_mac_addr",
+    "fwd_bytes",
+    "fwd_packets",
+    "fwd_tcp_flags",
+    "rev_bytes",
+    "rev_packets",
+    "rev_tcp_flags",
+    "duplicate_of",
+];
+
+pub const FIELD_ALIASES: [&str; 9] = [
+    "exporter", "src_addr", "dst_addr", "next_hop", "bytes", "packets", "proto", "in_if", "out_if",
+];
+
+/// An `f64` compared and hashed by its total order, so that it can be part
+/// of a key.
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Float) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Float) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Float) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FieldValue {
+    Uint(u64),
+    Float(Float),
+    Str(String),
+    Ip(IpAddr),
+    SocketAddr(SocketAddr),
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FieldValue::Uint(x) => write!(f, "{}", x),
+            FieldValue::Float(x) => write!(f, "{}", x.0),
+            FieldValue::Str(x) => write!(f, "{}", x),
+            FieldValue::Ip(x) => write!(f, "{}", x),
+            FieldValue::SocketAddr(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl From<FieldValue> for serde_json::Value {
+    fn from(value: FieldValue) -> serde_json::Value {
+        match value {
+            FieldValue::Uint(x) => serde_json::Value::from(x),
+            FieldValue::Float(x) => serde_json::Value::from(x.0),
+            x => serde_json::Value::String(x.to_string()),
+        }
+    }
+}
+
+impl FieldValue {
+    pub fn to_uint<T: TryFrom<u64>>(&self) -> Result<T> {
+        match self {
+            FieldValue::Uint(x) => T::try_from(*x).map_err(|_| anyhow!("{} is out of range", x)),
+            x => Err(anyhow!("{} is not a number", x)),
+        }
+    }
+
+    pub fn to_float(&self) -> Result<f64> {
+        match self {
+            FieldValue::Float(x) => Ok(x.0),
+            FieldValue::Uint(x) => Ok(*x as f64),
+            FieldValue::Str(x) => x.parse().map_err(|_| anyhow!("{} is not a number", x)),
+            x => Err(anyhow!("{} is not a number", x)),
+        }
+    }
+
+    pub fn to_ip(&self) -> Result<IpAddr> {
+        match self {
+            FieldValue::Ip(x) => Ok(*x),
+            FieldValue::SocketAddr(x) => Ok(x.ip()),
+            x => Err(anyhow!("{} is not an address", x)),
+        }
+    }
+
+    pub fn to_ipv4(&self) -> Result<Ipv4Addr> {
+        match self.to_ip()? {
+            IpAddr::V4(x) => Ok(x),
+            x => Err(anyhow!("{} is not an IPv4 address", x)),
+        }
+    }
+
+    pub fn to_ipv6(&self) -> Result<Ipv6Addr> {
+        match self.to_ip()? {
+            IpAddr::V6(x) => Ok(x),
+            x => Err(anyhow!("{} is not an IPv6 address", x)),
+        }
+    }
+
+    pub fn to_socket_addr(&self) -> Result<SocketAddr> {
+        match self {
+            FieldValue::SocketAddr(x) => Ok(*x),
+            x => Ok(SocketAddr::new(x.to_ip()?, 0)),
+        }
+    }
+}
+
+impl FlowMessage {
+    pub fn has_field(name: &str) -> bool {
+        FIELD_ALIASES.contains(&name)
+            || tag_field(name).is_some()
+            || FlowMessage::as_field_name_array()
+                .iter()
+                .any(|x| x.name() == name)
+    }
+
+    /// The type of a field, aliases and tags included. An alias has the
+    /// widest type of the fields it stands for.
+    pub fn field_type(name: &str) -> Option<FieldType> {
+        match name {
+            "exporter" | "src_addr" | "dst_addr" | "next_hop" => Some(FieldType::Ip),
+            "bytes" | "packets" | "in_if" | "out_if" => Some(FieldType::Uint64),
+            "proto" => Some(FieldType::Uint32),
+            _ if tag_field(name).is_some() => Some(FieldType::Str),
+            _ => FIELD_TYPES
+                .iter()
+                .find(|(x, _)| *x == name)
+                .map(|(_, x)| *x),
+        }
+    }
+
+    /// One in how many packets the exporter sampled, 1 when unsampled. The
+    /// top two bits of the NetFlow v5 header field are the sampling mode.
+    pub fn sampling_rate(&self) -> u64 {
+        let interval = match self.version {
+            Some(5) => self.sampling_interval.map(|x| x & 0x3fff),
+            _ => self.sampling_interval,
+        };
+        interval.unwrap_or(1).max(1) as u64
+    }
+
+    /// Whether a field, alias or tag is only filled in by processors.
+    pub fn is_processor_field(name: &str) -> bool {
+        PROCESSOR_FIELDS.contains(&name) || tag_field(name).is_some()
+    }
+
+    /// Flow start and end in Unix milliseconds, derived from the exporter
+    /// uptime stamps, or the window bounds of an aggregated record.
+    pub fn start_end_millis(&self) -> Option<(u64, u64)> {
+        if let (Some(first), Some(last), Some(sys_up_time), Some(unix_secs)) = (
+            self.first.or(self.first_switched),
+            self.last.or(self.last_switched),
+            self.sys_up_time,
+            self.unix_secs,
+        ) {
+            let export = unix_secs as u64 * 1000 + self.unix_nsecs.unwrap_or(0) as u64 / 1_000_000;
+            let start = export.checked_sub(sys_up_time.wrapping_sub(first) as u64)?;
+            let end = export.checked_sub(sys_up_time.wrapping_sub(last) as u64)?;
+            return Some((start, end.max(start)));
+        }
+        match (self.window_start, self.window_end) {
+            (Some(start), Some(end)) => Some((start * 1000, end * 1000)),
+            _ => None,
+        }
+    }
+
+    /// The time the flow was received, parsed from `datetime`, in Unix nanoseconds.
+    pub fn received_nanos(&self) -> Option<i64> {
+        let datetime = self.datetime.as_ref()?;
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S%.f UTC")
+            .ok()?
+            .and_utc()
+            .timestamp_nanos_opt()
+    }
+
+    pub fn uint(&self, name: &str) -> Option<u64> {
+        match self.field(name) {
+            Some(FieldValue::Uint(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<FieldValue> {
+        match name {
+            "exporter" => self.exporter_addr.map(|x| FieldValue::Ip(x.ip())),
+            "src_addr" => self
+                .field("ipv4_src_addr")
+                .or_else(|| self.field("ipv6_src_addr")),
+            "dst_addr" => self
+                .field("ipv4_dst_addr")
+                .or_else(|| self.field("ipv6_dst_addr")),
+            "next_hop" => self
+                .field("ipv4_next_hop")
+                .or_else(|| self.field("ipv6_next_hop")),
+            "bytes" => self.field("in_bytes").or_else(|| self.field("d0ctets")),
+            "packets" => self.field("in_pkts").or_else(|| self.field("dpkts")),
+            "proto" => self.field("protocol"),
+            "in_if" => self.field("input").or_else(|| self.field("input_snmp")),
+            "out_if" => self.field("output").or_else(|| self.field("output_snmp")),
+            "datetime" => self.datetime.clone().map(FieldValue::Str),
+            "exporter_addr" => self.exporter_addr.map(FieldValue::SocketAddr),
+            "version" => self.version.map(|x| FieldValue::Uint(x as u64)),
+            "sys_up_time" => self.sys_up_time.map(|x| FieldValue::Uint(x as u64)),
+            "unix_secs" => self.unix_secs.map(|x| FieldValue::Uint(x as u64)),
+            "unix_nsecs" => self.unix_nsecs.map(|x| FieldValue::Uint(x as u64)),
+            "flow_sequence" => self.flow_sequence.map(|x| FieldValue::Uint(x as u64)),
+            "engine_type" => self.engine_type.map(|x| FieldValue::Uint(x as u64)),
+            "engine_id" => self.engine_id.map(|x| FieldValue::Uint(x as u64)),
+            "sampling_interval" => self.sampling_interval.map(|x| FieldValue::Uint(x as u64)),
+            "ipv4_src_addr" => self.ipv4_src_addr.map(|x| FieldValue::Ip(IpAddr::V4(x))),
+            "ipv4_dst_addr" => self.ipv4_dst_addr.map(|x| FieldValue::Ip(IpAddr::V4(x))),
+            "ipv4_next_hop" => self.ipv4_next_hop.map(|x| FieldValue::Ip(IpAddr::V4(x))),
+            "input" => self.input.map(|x| FieldValue::Uint(x as u64)),
+            "output" => self.output.map(|x| FieldValue::Uint(x as u64)),
+            "dpkts" => self.dpkts.map(|x| FieldValue::Uint(x as u64)),
+            "d0ctets" => self.d0ctets.map(|x| FieldValue::Uint(x as u64)),
+            "first" => self.first.map(|x| FieldValue::Uint(x as u64)),
+            "last" => self.last.map(|x| FieldValue::Uint(x as u64)),
+            "src_port" => self.src_port.map(|x| FieldValue::Uint(x as u64)),
+            "dst_port" => self.dst_port.map(|x| FieldValue::Uint(x as u64)),
+            "tcp_flags" => self.tcp_flags.map(|x| FieldValue::Uint(x as u64)),
+            "tos" => self.tos.map(|x| FieldValue::Uint(x as u64)),
+            "src_as" => self.src_as.map(|x| FieldValue::Uint(x as u64)),
+            "dst_as" => self.dst_as.map(|x| FieldValue::Uint(x as u64)),
+            "src_mask" => self.src_mask.map(|x| FieldValue::Uint(x as u64)),
+            "dst_mask" => self.dst_mask.map(|x| FieldValue::Uint(x as u64)),
+            "in_bytes" => self.in_bytes.map(|x| FieldValue::Uint(x as u64)),
+            "in_pkts" => self.in_pkts.map(|x| FieldValue::Uint(x as u64)),
+            "flows" => self.flows.map(|x| FieldValue::Uint(x as u64)),
+            "protocol" => self.protocol.map(|x| FieldValue::Uint(x as u64)),
+            "input_snmp" => self.input_snmp.map(|x| FieldValue::Uint(x as u64)),
+            "output_snmp" => self.output_snmp.map(|x| FieldValue::Uint(x as u64)),
+            "bgp_ipv4_next_hop" => self
+                .bgp_ipv4_next_hop
+                .map(|x| FieldValue::Ip(IpAddr::V4(x))),
+            "mul_dst_pkts" => self.mul_dst_pkts.map(|x| FieldValue::Uint(x as u64)),
+            "mul_dst_bytes" => self.mul_dst_bytes.map(|x| FieldValue::Uint(x as u64)),
+            "last_switched" => self.last_switched.map(|x| FieldValue::Uint(x as u64)),
+            "first_switched" => self.first_switched.map(|x| FieldValue::Uint(x as u64)),
+            "out_bytes" => self.out_bytes.map(|x| FieldValue::Uint(x as u64)),
+            "out_pkts" => self.out_pkts.map(|x| FieldValue::Uint(x as u64)),
+            "ipv6_src_addr" => self.ipv6_src_addr.map(|x| FieldValue::Ip(IpAddr::V6(x))),
+            "ipv6_dst_addr" => self.ipv6_dst_addr.map(|x| FieldValue::Ip(IpAddr::V6(x))),
+            "ipv6_src_mask" => self.ipv6_src_mask.map(|x| FieldValue::Uint(x as u64)),
+            "ipv6_dst_mask" => self.ipv6_dst_mask.map(|x| FieldValue::Uint(x as u64)),
+            "ipv6_flow_label" => self.ipv6_flow_label.map(|x| FieldValue::Uint(x as u64)),
+            "icmp_type" => self.icmp_type.map(|x| FieldValue::Uint(x as u64)),
+            "mul_igmp_type" => self.mul_igmp_type.map(|x| FieldValue::Uint(x as u64)),
+            "sampling_algorithm" => self.sampling_algorithm.map(|x| FieldValue::Uint(x as u64)),
+            "flow_active_timeout" => self.flow_active_timeout.map(|x| FieldValue::Uint(x as u64)),
+            "flow_inactive_timeout" => self
+                .flow_inactive_timeout
+                .map(|x| FieldValue::Uint(x as u64)),
+            "total_bytes_exp" => self.total_bytes_exp.map(|x| FieldValue::Uint(x as u64)),
+            "total_pkts_exp" => self.total_pkts_exp.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_top_label" => self.mpls_top_label.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_top_label_ip_addr" => self
+                .mpls_top_label_ip_addr
+                .map(|x| FieldValue::Uint(x as u64)),
+            "flow_sampler_id" => self.flow_sampler_id.map(|x| FieldValue::Uint(x as u64)),
+            "flow_sampler_mode" => self.flow_sampler_mode.map(|x| FieldValue::Uint(x as u64)),
+            "flow_sampler_random_interval" => self
+                .flow_sampler_random_interval
+                .map(|x| FieldValue::Uint(x as u64)),
+            "dst_tos" => self.dst_tos.map(|x| FieldValue::Uint(x as u64)),
+            "src_mac" => self.src_mac.map(FieldValue::Uint),
+            "dst_mac" => self.dst_mac.map(FieldValue::Uint),
+            "src_vlan" => self.src_vlan.map(|x| FieldValue::Uint(x as u64)),
+            "dst_vlan" => self.dst_vlan.map(|x| FieldValue::Uint(x as u64)),
+            "ip_protocol_version" => self.ip_protocol_version.map(|x| FieldValue::Uint(x as u64)),
+            "direction" => self.direction.map(|x| FieldValue::Uint(x as u64)),
+            "ipv6_next_hop" => self.ipv6_next_hop.map(|x| FieldValue::Ip(IpAddr::V6(x))),
+            "bgp_ipv6_next_hop" => self
+                .bgp_ipv6_next_hop
+                .map(|x| FieldValue::Ip(IpAddr::V6(x))),
+            "ipv6_option_headers" => self.ipv6_option_headers.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_1" => self.mpls_label_1.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_2" => self.mpls_label_2.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_3" => self.mpls_label_3.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_4" => self.mpls_label_4.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_5" => self.mpls_label_5.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_6" => self.mpls_label_6.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_7" => self.mpls_label_7.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_8" => self.mpls_label_8.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_9" => self.mpls_label_9.map(|x| FieldValue::Uint(x as u64)),
+            "mpls_label_10" => self.mpls_label_10.map(|x| FieldValue::Uint(x as u64)),
+            "application_engine_id" => self
+                .application_engine_id
+                .map(|x| FieldValue::Uint(x as u64)),
+            "application_selector_id" => self
+                .application_selector_id
+                .map(|x| FieldValue::Uint(x as u64)),
+            "application_name" => self.application_name.clone().map(FieldValue::Str),
+            "application_description" => self.application_description.clone().map(FieldValue::Str),
+            "window_start" => self.window_start.map(FieldValue::Uint),
+            "window_end" => self.window_end.map(FieldValue::Uint),
+            "src_country" => self.src_country.clone().map(FieldValue::Str),
+            "dst_country" => self.dst_country.clone().map(FieldValue::Str),
+            "src_city" => self.src_city.clone().map(FieldValue::Str),
+            "dst_city" => self.dst_city.clone().map(FieldValue::Str),
+            "src_asn_org" => self.src_asn_org.clone().map(FieldValue::Str),
+            "dst_asn_org" => self.dst_asn_org.clone().map(FieldValue::Str),
+            "src_tags" => self.src_tags.clone().map(FieldValue::Str),
+            "dst_tags" => self.dst_tags.clone().map(FieldValue::Str),
+            "src_as_path" => self.src_as_path.clone().map(FieldValue::Str),
+            "dst_as_path" => self.dst_as_path.clone().map(FieldValue::Str),
+            "src_communities" => self.src_communities.clone().map(FieldValue::Str),
+            "dst_communities" => self.dst_communities.clone().map(FieldValue::Str),
+            "src_origin_as" => self.src_origin_as.map(|x| FieldValue::Uint(x as u64)),
+            "dst_origin_as" => self.dst_origin_as.map(|x| FieldValue::Uint(x as u64)),
+            "in_if_name" => self.in_if_name.clone().map(FieldValue::Str),
+            "out_if_name" => self.out_if_name.clone().map(FieldValue::Str),
+            "in_if_description" => self.in_if_description.clone().map(FieldValue::Str),
+            "out_if_description" => self.out_if_description.clone().map(FieldValue::Str),
+            "in_if_speed" => self.in_if_speed.map(FieldValue::Uint),
+            "out_if_speed" => self.out_if_speed.map(FieldValue::Uint),
+            "in_if_utilization" => self.in_if_utilization.map(|x| FieldValue::Float(Float(x))),
+            "out_if_utilization" => self.out_if_utilization.map(|x| FieldValue::Float(Float(x))),
+            "protocol_name" => self.protocol_name.clone().map(FieldValue::Str),
+            "src_service" => self.src_service.clone().map(FieldValue::Str),
+            "dst_service" => self.dst_service.clone().map(FieldValue::Str),
+            "tcp_flags_name" => self.tcp_flags_name.clone().map(FieldValue::Str),
+            "icmp_msg_type" => self.icmp_msg_type.map(|x| FieldValue::Uint(x as u64)),
+            "icmp_msg_code" => self.icmp_msg_code.map(|x| FieldValue::Uint(x as u64)),
+            "src_mac_addr" => self.src_mac_addr.clone().map(FieldValue::Str),
+            "dst_mac_addr" => self.dst_mac_addr.clone().map(FieldValue::Str),
+            "fwd_bytes" => self.fwd_bytes.map(FieldValue::Uint),
+            "fwd_packets" => self.fwd_packets.map(FieldValue::Uint),
+            "fwd_tcp_flags" => self.fwd_tcp_flags.map(|x| FieldValue::Uint(x as u64)),
+            "rev_bytes" => self.rev_bytes.map(FieldValue::Uint),
+            "rev_packets" => self.rev_packets.map(FieldValue::Uint),
+            "rev_tcp_flags" => self.rev_tcp_flags.map(|x| FieldValue::Uint(x as u64)),
+            "duplicate_of" => self.duplicate_of.map(FieldValue::Ip),
+            "sequence_lost" => self.sequence_lost.map(|x| FieldValue::Uint(x as u64)),
+            _ => match tag_field(name) {
+                Some(("src_tags", key)) => tag(&self.src_tags, key).map(FieldValue::Str),
+                Some(("dst_tags", key)) => tag(&self.dst_tags, key).map(FieldValue::Str),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn set_field(&mut self, name: &str, value: Option<FieldValue>) -> Result<()> {
+        match name {
+            "exporter" => self.set_field("exporter_addr", value)?,
+            "src_addr" => self.set_addr_field("ipv4_src_addr", "ipv6_src_addr", value)?,
+            "dst_addr" => self.set_addr_field("ipv4_dst_addr", "ipv6_dst_addr", value)?,
+            "next_hop" => self.set_addr_field("ipv4_next_hop", "ipv6_next_hop", value)?,
+            "bytes" => self.set_field("in_bytes", value)?,
+            "packets" => self.set_field("in_pkts", value)?,
+            "proto" => self.set_field("protocol", value)?,
+            "in_if" => self.set_field("input_snmp", value)?,
+            "out_if" => self.set_field("output_snmp", value)?,
+            "datetime" => self.datetime = value.map(|x| x.to_string()),
+            "exporter_addr" => {
+                self.exporter_addr = value.map(|x| x.to_socket_addr()).transpose()?
+            }
+            "version" => self.version = value.map(|x| x.to_uint()).transpose()?,
+            "sys_up_time" => self.sys_up_time = value.map(|x| x.to_uint()).transpose()?,
+            "unix_secs" => self.unix_secs = value.map(|x| x.to_uint()).transpose()?,
+            "unix_nsecs" => self.unix_nsecs = value.map(|x| x.to_uint()).transpose()?,
+            "flow_sequence" => self.flow_sequence = value.map(|x| x.to_uint()).transpose()?,
+            "engine_type" => self.engine_type = value.map(|x| x.to_uint()).transpose()?,
+            "engine_id" => self.engine_id = value.map(|x| x.to_uint()).transpose()?,
+            "sampling_interval" => {
+                self.sampling_interval = value.map(|x| x.to_uint()).transpose()?
+            }
+            "ipv4_src_addr" => self.ipv4_src_addr = value.map(|x| x.to_ipv4()).transpose()?,
+            "ipv4_dst_addr" => self.ipv4_dst_addr = value.map(|x| x.to_ipv4()).transpose()?,
+            "ipv4_next_hop" => self.ipv4_next_hop = value.map(|x| x.to_ipv4()).transpose()?,
+            "input" => self.input = value.map(|x| x.to_uint()).transpose()?,
+            "output" => self.output = value.map(|x| x.to_uint()).transpose()?,
+            "dpkts" => self.dpkts = value.map(|x| x.to_uint()).transpose()?,
+            "d0ctets" => self.d0ctets = value.map(|x| x.to_uint()).transpose()?,
+            "first" => self.first = value.map(|x| x.to_uint()).transpose()?,
+            "last" => self.last = value.map(|x| x.to_uint()).transpose()?,
+            "src_port" => self.src_port = value.map(|x| x.to_uint()).transpose()?,
+            "dst_port" => self.dst_port = value.map(|x| x.to_uint()).transpose()?,
+            "tcp_flags" => self.tcp_flags = value.map(|x| x.to_uint()).transpose()?,
+            "tos" => self.tos = value.map(|x| x.to_uint()).transpose()?,
+            "src_as" => self.src_as = value.map(|x| x.to_uint()).transpose()?,
+            "dst_as" => self.dst_as = value.map(|x| x.to_uint()).transpose()?,
+            "src_mask" => self.src_mask = value.map(|x| x.to_uint()).transpose()?,
+            "dst_mask" => self.dst_mask = value.map(|x| x.to_uint()).transpose()?,
+            "in_bytes" => self.in_bytes = value.map(|x| x.to_uint()).transpose()?,
+            "in_pkts" => self.in_pkts = value.map(|x| x.to_uint()).transpose()?,
+            "flows" => self.flows = value.map(|x| x.to_uint()).transpose()?,
+            "protocol" => self.protocol = value.map(|x| x.to_uint()).transpose()?,
+            "input_snmp" => self.input_snmp = value.map(|x| x.to_uint()).transpose()?,
+            "output_snmp" => self.output_snmp = value.map(|x| x.to_uint()).transpose()?,
+            "bgp_ipv4_next_hop" => {
+                self.bgp_ipv4_next_hop = value.map(|x| x.to_ipv4()).transpose()?
+            }
+            "mul_dst_pkts" => self.mul_dst_pkts = value.map(|x| x.to_uint()).transpose()?,
+            "mul_dst_bytes" => self.mul_dst_bytes = value.map(|x| x.to_uint()).transpose()?,
+            "last_switched" => self.last_switched = value.map(|x| x.to_uint()).transpose()?,
+            "first_switched" => self.first_switched = value.map(|x| x.to_uint()).transpose()?,
+            "out_bytes" => self.out_bytes = value.map(|x| x.to_uint()).transpose()?,
+            "out_pkts" => self.out_pkts = value.map(|x| x.to_uint()).transpose()?,
+            "ipv6_src_addr" => self.ipv6_src_addr = value.map(|x| x.to_ipv6()).transpose()?,
+            "ipv6_dst_addr" => self.ipv6_dst_addr = value.map(|x| x.to_ipv6()).transpose()?,
+            "ipv6_src_mask" => self.ipv6_src_mask = value.map(|x| x.to_uint()).transpose()?,
+            "ipv6_dst_mask" => self.ipv6_dst_mask = value.map(|x| x.to_uint()).transpose()?,
+            "ipv6_flow_label" => self.ipv6_flow_label = value.map(|x| x.to_uint()).transpose()?,
+            "icmp_type" => self.icmp_type = value.map(|x| x.to_uint()).transpose()?,
+            "mul_igmp_type" => self.mul_igmp_type = value.map(|x| x.to_uint()).transpose()?,
+            "sampling_algorithm" => {
+                self.sampling_algorithm = value.map(|x| x.to_uint()).transpose()?
+            }
+            "flow_active_timeout" => {
+                self.flow_active_timeout = value.map(|x| x.to_uint()).transpose()?
+            }
+            "flow_inactive_timeout" => {
+                self.flow_inactive_timeout = value.map(|x| x.to_uint()).transpose()?
+            }
+            "total_bytes_exp" => self.total_bytes_exp = value.map(|x| x.to_uint()).transpose()?,
+            "total_pkts_exp" => self.total_pkts_exp = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_top_label" => self.mpls_top_label = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_top_label_ip_addr" => {
+                self.mpls_top_label_ip_addr = value.map(|x| x.to_uint()).transpose()?
+            }
+            "flow_sampler_id" => self.flow_sampler_id = value.map(|x| x.to_uint()).transpose()?,
+            "flow_sampler_mode" => {
+                self.flow_sampler_mode = value.map(|x| x.to_uint()).transpose()?
+            }
+            "flow_sampler_random_interval" => {
+                self.flow_sampler_random_interval = value.map(|x| x.to_uint()).transpose()?
+            }
+            "dst_tos" => self.dst_tos = value.map(|x| x.to_uint()).transpose()?,
+            "src_mac" => self.src_mac = value.map(|x| x.to_uint()).transpose()?,
+            "dst_mac" => self.dst_mac = value.map(|x| x.to_uint()).transpose()?,
+            "src_vlan" => self.src_vlan = value.map(|x| x.to_uint()).transpose()?,
+            "dst_vlan" => self.dst_vlan = value.map(|x| x.to_uint()).transpose()?,
+            "ip_protocol_version" => {
+                self.ip_protocol_version = value.map(|x| x.to_uint()).transpose()?
+            }
+            "direction" => self.direction = value.map(|x| x.to_uint()).transpose()?,
+            "ipv6_next_hop" => self.ipv6_next_hop = value.map(|x| x.to_ipv6()).transpose()?,
+            "bgp_ipv6_next_hop" => {
+                self.bgp_ipv6_next_hop = value.map(|x| x.to_ipv6()).transpose()?
+            }
+            "ipv6_option_headers" => {
+                self.ipv6_option_headers = value.map(|x| x.to_uint()).transpose()?
+            }
+            "mpls_label_1" => self.mpls_label_1 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_2" => self.mpls_label_2 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_3" => self.mpls_label_3 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_4" => self.mpls_label_4 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_5" => self.mpls_label_5 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_6" => self.mpls_label_6 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_7" => self.mpls_label_7 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_8" => self.mpls_label_8 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_9" => self.mpls_label_9 = value.map(|x| x.to_uint()).transpose()?,
+            "mpls_label_10" => self.mpls_label_10 = value.map(|x| x.to_uint()).transpose()?,
+            "application_engine_id" => {
+                self.application_engine_id = value.map(|x| x.to_uint()).transpose()?
+            }
+            "application_selector_id" => {
+                self.application_selector_id = value.map(|x| x.to_uint()).transpose()?
+            }
+            "application_name" => self.application_name = value.map(|x| x.to_string()),
+            "application_description" => {
+                self.application_description = value.map(|x| x.to_string())
+            }
+            "window_start" => self.window_start = value.map(|x| x.to_uint()).transpose()?,
+            "window_end" => self.window_end = value.map(|x| x.to_uint()).transpose()?,
+            "src_country" => self.src_country = value.map(|x| x.to_string()),
+            "dst_country" => self.dst_country = value.map(|x| x.to_string()),
+            "src_city" => self.src_city = value.map(|x| x.to_string()),
+            "dst_city" => self.dst_city = value.map(|x| x.to_string()),
+            "src_asn_org" => self.src_asn_org = value.map(|x| x.to_string()),
+            "dst_asn_org" => self.dst_asn_org = value.map(|x| x.to_string()),
+            "src_tags" => self.src_tags = value.map(|x| x.to_string()),
+            "dst_tags" => self.dst_tags = value.map(|x| x.to_string()),
+            "src_as_path" => self.src_as_path = value.map(|x| x.to_string()),
+            "dst_as_path" => self.dst_as_path = value.map(|x| x.to_string()),
+            "src_communities" => self.src_communities = value.map(|x| x.to_string()),
+            "dst_communities" => self.dst_communities = value.map(|x| x.to_string()),
+            "src_origin_as" => self.src_origin_as = value.map(|x| x.to_uint()).transpose()?,
+            "dst_origin_as" => self.dst_origin_as = value.map(|x| x.to_uint()).transpose()?,
+            "in_if_name" => self.in_if_name = value.map(|x| x.to_string()),
+            "out_if_name" => self.out_if_name = value.map(|x| x.to_string()),
+            "in_if_description" => self.in_if_description = value.map(|x| x.to_string()),
+            "out_if_description" => self.out_if_description = value.map(|x| x.to_string()),
+            "in_if_speed" => self.in_if_speed = value.map(|x| x.to_uint()).transpose()?,
+            "out_if_speed" => self.out_if_speed = value.map(|x| x.to_uint()).transpose()?,
+            "in_if_utilization" => {
+                self.in_if_utilization = value.map(|x| x.to_float()).transpose()?
+            }
+            "out_if_utilization" => {
+                self.out_if_utilization = value.map(|x| x.to_float()).transpose()?
+            }
+            "protocol_name" => self.protocol_name = value.map(|x| x.to_string()),
+            "src_service" => self.src_service = value.map(|x| x.to_string()),
+            "dst_service" => self.dst_service = value.map(|x| x.to_string()),
+            "tcp_flags_name" => self.tcp_flags_name = value.map(|x| x.to_string()),
+            "icmp_msg_type" => self.icmp_msg_type = value.map(|x| x.to_uint()).transpose()?,
+            "icmp_msg_code" => self.icmp_msg_code = value.map(|x| x.to_uint()).transpose()?,
+            "src_mac_addr" => self.src_mac_addr = value.map(|x| x.to_string()),
+            "dst_mac_addr" => self.dst_mac_addr = value.map(|x| x.to_string()),
+            "fwd_bytes" => self.fwd_bytes = value.map(|x| x.to_uint()).transpose()?,
+            "fwd_packets" => self.fwd_packets = value.map(|x| x.to_uint()).transpose()?,
+            "fwd_tcp_flags" => self.fwd_tcp_flags = value.map(|x| x.to_uint()).transpose()?,
+            "rev_bytes" => self.rev_bytes = value.map(|x| x.to_uint()).transpose()?,
+            "rev_packets" => self.rev_packets = value.map(|x| x.to_uint()).transpose()?,
+            "rev_tcp_flags" => self.rev_tcp_flags = value.map(|x| x.to_uint()).transpose()?,
+            "duplicate_of" => self.duplicate_of = value.map(|x| x.to_ip()).transpose()?,
+            "sequence_lost" => self.sequence_lost = value.map(|x| x.to_uint()).transpose()?,
+            _ => match tag_field(name) {
+                Some(("src_tags", key)) => set_tag(&mut self.src_tags, key, value),
+                Some(("dst_tags", key)) => set_tag(&mut self.dst_tags, key, value),
+                _ => return Err(anyhow!("unknown field {}", name)),
+            },
+        }
+        Ok(())
+    }
+
+    fn set_addr_field(&mut self, v4: &str, v6: &str, value: Option<FieldValue>) -> Result<()> {
+        match value.as_ref().map(|x| x.to_ip()).transpose()? {
+            Some(IpAddr::V4(_)) => self.set_field(v4, value),
+            Some(IpAddr::V6(_)) => self.set_field(v6, value),
+            None => {
+                self.set_field(v4, None)?;
+                self.set_field(v6, None)
+            }
+        }
+    }
+}
+
+/// Splits `src_tag.customer` into the tags field and the tag key.
+fn tag_field(name: &str) -> Option<(&'static str, &str)> {
+    match name.split_once('.') {
+        Some(("src_tag", key)) if !key.is_empty() => Some(("src_tags", key)),
+        Some(("dst_tag", key)) if !key.is_empty() => Some(("dst_tags", key)),
+        _ => None,
+    }
+}
+
+/// Tags are stored as `key=value;key=value`.
+pub fn tag(tags: &Option<String>, key: &str) -> Option<String> {
+    tags.as_ref()?
+        .split(';')
+        .filter_map(|x| x.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string())
+}
+
+fn set_tag(tags: &mut Option<String>, key: &str, value: Option<FieldValue>) {
+    let mut items = tags
+        .iter()
+        .flat_map(|x| x.split(';'))
+        .filter(|x| x.split_once('=').is_some_and(|(k, _)| k != key))
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>();
+    if let Some(value) = value {
+        items.push(format!("{}={}", key, value));
+    }
+    *tags = if items.is_empty() {
+        None
+    } else {
+        Some(items.join(";"))
+    };
 }
@@ -8,9 +8,51 @@ use super::flowmessage::FlowMessage;
 use anyhow::Result;
 use std::{fmt::Display, net::SocketAddr};
 
+/// Why a handler could not decode a packet. Handlers return these wrapped
+/// in `anyhow::Error`; truncated packets surface as `std::io::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnsupportedVersion(&'static str, u16),
+    InvalidTemplate,
+    UnknownTemplate(u16),
+}
+
+impl DecodeError {
+    /// The reason label of the decode error metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            DecodeError::UnsupportedVersion(..) => "unsupported_version",
+            DecodeError::InvalidTemplate => "invalid_template",
+            DecodeError::UnknownTemplate(_) => "unknown_template",
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(handler, version) => {
+                write!(f, "{} does not support version {}", handler, version)
+            }
+            DecodeError::InvalidTemplate => write!(f, "invalid template field length"),
+            DecodeError::UnknownTemplate(id) => {
+                write!(f, "not found template template_id = {}", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 pub trait Handler: Send + Display {
     fn box_clone(&self) -> Box<dyn Handler>;
-    fn handle(&self, buf: &Vec<u8>, size: usize, addr: SocketAddr) -> Result<Vec<FlowMessage>>;
+    /// The export version the handler decodes.
+    fn version(&self) -> u16;
+    /// Follows the header sequence number of a packet of the handler's
+    /// version. Called in arrival order, before packets are decoded
+    /// concurrently, and returns the `sequence_lost` tag of its flows.
+    fn observe(&self, buf: &[u8], addr: SocketAddr) -> Option<u32>;
+    fn handle(&self, buf: &[u8], size: usize, addr: SocketAddr) -> Result<Vec<FlowMessage>>;
 }
 
 impl Clone for Box<dyn Handler> {
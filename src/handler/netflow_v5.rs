@@ -1,20 +1,29 @@
 use super::super::flowmessage::FlowMessage;
 use super::super::flowmessage::FlowMessageBuilder;
-use super::Handler;
+use super::super::sequence::{SequenceKey, SequenceTracker};
+use super::{DecodeError, Handler};
 use byteorder::{BigEndian, ReadBytesExt};
 use chrono::Utc;
 use std::net::Ipv4Addr;
 use std::{fmt::Display, io::Cursor};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use std::net::SocketAddr;
 
 #[derive(Debug, Clone)]
-pub struct NetflowV5Handler {}
+pub struct NetflowV5Handler {
+    pub sequence_tracker: SequenceTracker,
+}
 
 impl NetflowV5Handler {
-    pub fn new() -> NetflowV5Handler {
-        NetflowV5Handler {}
+    pub fn new(sequence_tracker: SequenceTracker) -> NetflowV5Handler {
+        NetflowV5Handler { sequence_tracker }
+    }
+}
+
+impl Default for NetflowV5Handler {
+    fn default() -> NetflowV5Handler {
+        NetflowV5Handler::new(SequenceTracker::default())
     }
 }
 
@@ -29,20 +38,41 @@ impl Handler for NetflowV5Handler {
         Box::new(self.clone())
     }
 
+    fn version(&self) -> u16 {
+        5
+    }
+
+    fn observe(&self, buf: &[u8], exporter_addr: SocketAddr) -> Option<u32> {
+        let mut rdr = Cursor::new(buf);
+        if rdr.read_u16::<BigEndian>().ok()? != 5 {
+            return None;
+        }
+        let count = rdr.read_u16::<BigEndian>().ok()?;
+        let sys_up_time = rdr.read_u32::<BigEndian>().ok()?;
+        let unix_secs = rdr.read_u32::<BigEndian>().ok()?;
+        let _unix_nsecs = rdr.read_u32::<BigEndian>().ok()?;
+        let flow_sequence = rdr.read_u32::<BigEndian>().ok()?;
+        let engine_type = rdr.read_u8().ok()?;
+        let engine_id = rdr.read_u8().ok()?;
+        self.sequence_tracker.observe(
+            SequenceKey::v5(exporter_addr.ip(), engine_type, engine_id),
+            flow_sequence,
+            count as u32,
+            unix_secs as i64 - (sys_up_time / 1000) as i64,
+        )
+    }
+
     fn handle(
         &self,
-        buf: &Vec<u8>,
+        buf: &[u8],
         _size: usize,
         exporter_addr: SocketAddr,
     ) -> Result<Vec<FlowMessage>> {
-        let mut rdr = Cursor::new(buf.as_slice());
+        let mut rdr = Cursor::new(buf);
         let datetime = Utc::now();
         let version = rdr.read_u16::<BigEndian>()?;
         if version != 5 {
-            return Err(anyhow!(
-                "NetflowV5Handler does not support version {}",
-                version
-            ));
+            return Err(DecodeError::UnsupportedVersion("NetflowV5Handler", version).into());
         }
         let count = rdr.read_u16::<BigEndian>()?;
         let sys_up_time = rdr.read_u32::<BigEndian>()?;
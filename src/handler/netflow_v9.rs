@@ -1,10 +1,17 @@
-use super::super::util::bytes_to_usize;
+use super::super::util::{bytes_to_string, bytes_to_usize};
+
+use super::super::application_cache::{
+    ApplicationCache, ApplicationCacheKey, ApplicationCacheValue,
+};
+
+use super::super::metrics;
+use super::super::sequence::{SequenceKey, SequenceTracker};
 
 use super::super::template_cache::{Field, TemplateCache, TemplateCacheKey, TemplateCacheValue};
 
 use super::super::option_cache::{FlowDatas, OptionCache, OptionCacheKey};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use chrono::Utc;
 use std::io::prelude::*;
@@ -16,23 +23,38 @@ use std::{fmt::Display, io::Cursor};
 
 use super::super::flowmessage::FlowMessage;
 use super::super::flowmessage::FlowMessageBuilder;
-use super::Handler;
+use super::{DecodeError, Handler};
 
 #[derive(Debug, Clone)]
 pub struct NetflowV9Handler {
     pub template_cache: Arc<RwLock<TemplateCache>>,
     pub option_cache: Arc<RwLock<OptionCache>>,
+    pub application_cache: Arc<RwLock<ApplicationCache>>,
+    pub sequence_tracker: SequenceTracker,
 }
 
 impl NetflowV9Handler {
-    pub fn new() -> NetflowV9Handler {
+    pub fn new(sequence_tracker: SequenceTracker) -> NetflowV9Handler {
+        let template_cache = Arc::new(RwLock::new(TemplateCache::new()));
+        let template_cache_c = template_cache.clone();
+        metrics::register("ferrisflow_templates_cached", &[], move || {
+            template_cache_c.read().unwrap().len() as f64
+        });
         NetflowV9Handler {
-            template_cache: Arc::new(RwLock::new(TemplateCache::new())),
+            template_cache,
             option_cache: Arc::new(RwLock::new(OptionCache::new())),
+            application_cache: Arc::new(RwLock::new(ApplicationCache::new())),
+            sequence_tracker,
         }
     }
 }
 
+impl Default for NetflowV9Handler {
+    fn default() -> NetflowV9Handler {
+        NetflowV9Handler::new(SequenceTracker::default())
+    }
+}
+
 impl Display for NetflowV9Handler {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "NetflowV9Handler")
@@ -44,20 +66,39 @@ impl Handler for NetflowV9Handler {
         Box::new(self.clone())
     }
 
+    fn version(&self) -> u16 {
+        9
+    }
+
+    fn observe(&self, buf: &[u8], exporter_addr: SocketAddr) -> Option<u32> {
+        let mut rdr = Cursor::new(buf);
+        if rdr.read_u16::<BigEndian>().ok()? != 9 {
+            return None;
+        }
+        let _count = rdr.read_u16::<BigEndian>().ok()?;
+        let sys_up_time = rdr.read_u32::<BigEndian>().ok()?;
+        let unix_secs = rdr.read_u32::<BigEndian>().ok()?;
+        let seq_number = rdr.read_u32::<BigEndian>().ok()?;
+        let source_id = rdr.read_u32::<BigEndian>().ok()?;
+        self.sequence_tracker.observe(
+            SequenceKey::v9(exporter_addr.ip(), source_id),
+            seq_number,
+            1,
+            unix_secs as i64 - (sys_up_time / 1000) as i64,
+        )
+    }
+
     fn handle(
         &self,
-        buf: &Vec<u8>,
+        buf: &[u8],
         size: usize,
         exporter_addr: SocketAddr,
     ) -> Result<Vec<FlowMessage>> {
-        let mut rdr = Cursor::new(buf.as_slice());
+        let mut rdr = Cursor::new(buf);
         let datetime = Utc::now();
         let version = rdr.read_u16::<BigEndian>()?;
         if version != 9 {
-            return Err(anyhow!(
-                "NetflowV9Handler does not support version {}",
-                version
-            ));
+            return Err(DecodeError::UnsupportedVersion("NetflowV9Handler", version).into());
         }
         let _count = rdr.read_u16::<BigEndian>()?;
         let sys_up_time = rdr.read_u32::<BigEndian>()?;
@@ -83,10 +124,7 @@ impl Handler for NetflowV9Handler {
                     for _ in 0..field_count {
                         let field_type = rdr_data.read_u16::<BigEndian>()?;
                         let field_length = rdr_data.read_u16::<BigEndian>()?;
-                        if field_length == 0 {
-                            return Err(anyhow!("field length 0 error"));
-                        }
-                        fields.push(Field::new(field_type, field_length))
+                        fields.push(template_field(field_type, field_length)?)
                     }
                     let k = TemplateCacheKey::new(
                         exporter_addr.ip().to_string(),
@@ -118,19 +156,13 @@ impl Handler for NetflowV9Handler {
                     for _ in 0..option_scope_cnt {
                         let field_type = rdr_data.read_u16::<BigEndian>()?;
                         let field_length = rdr_data.read_u16::<BigEndian>()?;
-                        if field_length == 0 {
-                            return Err(anyhow!("field length 0 error"));
-                        }
-                        scope_fields.push(Field::new(field_type, field_length))
+                        scope_fields.push(template_field(field_type, field_length)?)
                     }
 
                     for _ in 0..option_cnt {
                         let field_type = rdr_data.read_u16::<BigEndian>()?;
                         let field_length = rdr_data.read_u16::<BigEndian>()?;
-                        if field_length == 0 {
-                            return Err(anyhow!("field length 0 error"));
-                        }
-                        fields.push(Field::new(field_type, field_length))
+                        fields.push(template_field(field_type, field_length)?)
                     }
 
                     let k = TemplateCacheKey::new(
@@ -152,7 +184,7 @@ impl Handler for NetflowV9Handler {
                 );
                 let template_cache = self.template_cache.read().unwrap();
                 if !template_cache.contains_key(&k) {
-                    return Err(anyhow!("not found template template_id = {}", flowset_id));
+                    return Err(DecodeError::UnknownTemplate(flowset_id).into());
                 }
                 let v = template_cache.get(&k).unwrap();
 
@@ -179,9 +211,27 @@ impl Handler for NetflowV9Handler {
                     }
 
                     if v.is_option {
-                        let mut option_cache = self.option_cache.write().unwrap();
-                        let k = OptionCacheKey::new(exporter_addr.ip().to_string());
-                        option_cache.insert(k, datas);
+                        let application_id = datas
+                            .get(&APPLICATION_ID)
+                            .or_else(|| scope_datas.get(&APPLICATION_ID));
+                        if let Some(application_id) = application_id {
+                            let mut application_cache = self.application_cache.write().unwrap();
+                            let k = ApplicationCacheKey::new(
+                                exporter_addr.ip().to_string(),
+                                application_id.clone(),
+                            );
+                            let v = ApplicationCacheValue::new(
+                                datas.get(&APPLICATION_NAME).map(|x| bytes_to_string(x)),
+                                datas
+                                    .get(&APPLICATION_DESCRIPTION)
+                                    .map(|x| bytes_to_string(x)),
+                            );
+                            application_cache.insert(k, v);
+                        } else {
+                            let mut option_cache = self.option_cache.write().unwrap();
+                            let k = OptionCacheKey::new(exporter_addr.ip().to_string());
+                            option_cache.insert(k, datas);
+                        }
                     } else {
                         let mut builder = FlowMessageBuilder::default();
                         builder
@@ -200,6 +250,22 @@ impl Handler for NetflowV9Handler {
                             builder = add_builder(builder, option_datas);
                         }
 
+                        if let Some(application_id) = datas.get(&APPLICATION_ID) {
+                            let application_cache = self.application_cache.read().unwrap();
+                            let k = ApplicationCacheKey::new(
+                                exporter_addr.ip().to_string(),
+                                application_id.clone(),
+                            );
+                            if let Some(application) = application_cache.get(&k) {
+                                if let Some(name) = &application.name {
+                                    builder.application_name(name.clone());
+                                }
+                                if let Some(description) = &application.description {
+                                    builder.application_description(description.clone());
+                                }
+                            }
+                        }
+
                         flowmessages.push(builder.build().unwrap());
                     }
                 }
@@ -209,21 +275,38 @@ impl Handler for NetflowV9Handler {
     }
 }
 
+const APPLICATION_DESCRIPTION: u16 = 94;
+const APPLICATION_ID: u16 = 95;
+const APPLICATION_NAME: u16 = 96;
+
+/// The classification engine id and a selector id of up to 8 bytes.
+const MAX_APPLICATION_ID_LENGTH: u16 = 9;
+
+/// Checks the length of a template field, so that its data can be decoded.
+fn template_field(field_type: u16, field_length: u16) -> Result<Field> {
+    if field_length == 0
+        || (field_type == APPLICATION_ID && field_length > MAX_APPLICATION_ID_LENGTH)
+    {
+        return Err(DecodeError::InvalidTemplate.into());
+    }
+    Ok(Field::new(field_type, field_length))
+}
+
 fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessageBuilder {
     for (type_, data) in datas {
         match type_ {
             1u16 => {
-                if let Ok(in_bytes) = bytes_to_usize(&data) {
+                if let Ok(in_bytes) = bytes_to_usize(data) {
                     builder.in_bytes(in_bytes);
                 }
             }
             2u16 => {
-                if let Ok(in_pkts) = bytes_to_usize(&data) {
+                if let Ok(in_pkts) = bytes_to_usize(data) {
                     builder.in_pkts(in_pkts);
                 }
             }
             3u16 => {
-                if let Ok(flows) = bytes_to_usize(&data) {
+                if let Ok(flows) = bytes_to_usize(data) {
                     builder.flows(flows);
                 }
             }
@@ -246,7 +329,7 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.src_mask(data[0]);
             }
             10u16 => {
-                if let Ok(input_snmp) = bytes_to_usize(&data) {
+                if let Ok(input_snmp) = bytes_to_usize(data) {
                     builder.input_snmp(input_snmp);
                 }
             }
@@ -260,7 +343,7 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.dst_mask(data[0]);
             }
             14u16 => {
-                if let Ok(output_snmp) = bytes_to_usize(&data) {
+                if let Ok(output_snmp) = bytes_to_usize(data) {
                     builder.output_snmp(output_snmp);
                 }
             }
@@ -285,12 +368,12 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.bgp_ipv4_next_hop(Ipv4Addr::from(BigEndian::read_u32(data.as_slice())));
             }
             19u16 => {
-                if let Ok(mul_dst_pkts) = bytes_to_usize(&data) {
+                if let Ok(mul_dst_pkts) = bytes_to_usize(data) {
                     builder.mul_dst_pkts(mul_dst_pkts);
                 }
             }
             20u16 => {
-                if let Ok(mul_dst_bytes) = bytes_to_usize(&data) {
+                if let Ok(mul_dst_bytes) = bytes_to_usize(data) {
                     builder.mul_dst_bytes(mul_dst_bytes);
                 }
             }
@@ -301,12 +384,12 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.first_switched(BigEndian::read_u32(data.as_slice()));
             }
             23u16 => {
-                if let Ok(out_bytes) = bytes_to_usize(&data) {
+                if let Ok(out_bytes) = bytes_to_usize(data) {
                     builder.out_bytes(out_bytes);
                 }
             }
             24u16 => {
-                if let Ok(out_pkts) = bytes_to_usize(&data) {
+                if let Ok(out_pkts) = bytes_to_usize(data) {
                     builder.out_pkts(out_pkts);
                 }
             }
@@ -323,7 +406,7 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.ipv6_dst_mask(data[0]);
             }
             31u16 => {
-                if let Ok(ipv6_flow_label) = bytes_to_usize(&data) {
+                if let Ok(ipv6_flow_label) = bytes_to_usize(data) {
                     builder.ipv6_flow_label(ipv6_flow_label);
                 }
             }
@@ -355,12 +438,12 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
                 builder.engine_id(data[0]);
             }
             41u16 => {
-                if let Ok(total_bytes_exp) = bytes_to_usize(&data) {
+                if let Ok(total_bytes_exp) = bytes_to_usize(data) {
                     builder.total_bytes_exp(total_bytes_exp);
                 }
             }
             42u16 => {
-                if let Ok(total_pkts_exp) = bytes_to_usize(&data) {
+                if let Ok(total_pkts_exp) = bytes_to_usize(data) {
                     builder.total_pkts_exp(total_pkts_exp);
                 }
             }
@@ -439,8 +522,106 @@ fn add_builder(mut builder: FlowMessageBuilder, datas: &FlowDatas) -> FlowMessag
             79u16 => {
                 builder.mpls_label_10(BigEndian::read_u24(data.as_slice()));
             }
+            94u16 => {
+                builder.application_description(bytes_to_string(data));
+            }
+            95u16 => {
+                // Templates limit the length to MAX_APPLICATION_ID_LENGTH.
+                if let Some((engine_id, selector_id)) = data.split_first() {
+                    builder.application_engine_id(*engine_id);
+                    builder.application_selector_id(
+                        selector_id.iter().fold(0, |acc, x| acc << 8 | *x as usize),
+                    );
+                }
+            }
+            96u16 => {
+                builder.application_name(bytes_to_string(data));
+            }
             _ => {}
         }
     }
     builder
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flowset(id: u16, body: &[u8]) -> Vec<u8> {
+        let mut flowset = id.to_be_bytes().to_vec();
+        flowset.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        flowset.extend_from_slice(body);
+        flowset
+    }
+
+    fn packet(flowsets: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        for x in [9u16, flowsets.len() as u16] {
+            packet.extend_from_slice(&x.to_be_bytes());
+        }
+        for x in [1000u32, 1_600_000_000, 1, 0] {
+            packet.extend_from_slice(&x.to_be_bytes());
+        }
+        packet.extend(flowsets.concat());
+        packet
+    }
+
+    fn fields(fields: &[(u16, u16)]) -> Vec<u8> {
+        fields
+            .iter()
+            .flat_map(|(type_, length)| [type_.to_be_bytes(), length.to_be_bytes()].concat())
+            .collect()
+    }
+
+    #[test]
+    fn application_names() {
+        let handler = NetflowV9Handler::default();
+        let addr = "192.0.2.1:2055".parse().unwrap();
+        // An application id of 5 bytes: engine 3 and selector 443.
+        let application_id = [3, 0, 0, 1, 187];
+
+        let mut options_template = [256u16, 4, 8]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<u8>>();
+        options_template.extend(fields(&[
+            (1, 4),
+            (APPLICATION_ID, 5),
+            (APPLICATION_NAME, 8),
+        ]));
+        let mut options = vec![0, 0, 0, 1];
+        options.extend_from_slice(&application_id);
+        options.extend_from_slice(b"https\0\0\0");
+        let mut template = [257u16, 2]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<u8>>();
+        template.extend(fields(&[(8, 4), (APPLICATION_ID, 5)]));
+        let mut data = vec![198, 51, 100, 1];
+        data.extend_from_slice(&application_id);
+
+        let buf = packet(&[
+            flowset(1, &options_template),
+            flowset(256, &options),
+            flowset(0, &template),
+            flowset(257, &data),
+        ]);
+        let flowmessages = handler.handle(&buf, buf.len(), addr).unwrap();
+        assert_eq!(flowmessages.len(), 1);
+        let flowmessage = serde_json::to_value(&flowmessages[0]).unwrap();
+        assert_eq!(flowmessage["application_name"], "https");
+        assert_eq!(flowmessage["application_engine_id"], 3);
+        assert_eq!(flowmessage["application_selector_id"], 443);
+
+        // Application ids longer than the engine id and 8 selector bytes are
+        // rejected with their template.
+        let mut template = [258u16, 1]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<u8>>();
+        template.extend(fields(&[(APPLICATION_ID, 10)]));
+        let buf = packet(&[flowset(0, &template)]);
+        let e = handler.handle(&buf, buf.len(), addr).unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&DecodeError::InvalidTemplate));
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+/// How long a client has to send its request, and to take the response.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn text(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    fn error(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Handles a GET request; the argument is the raw query string.
+pub type Route = Arc<dyn Fn(&str) -> Result<Response> + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct HttpServer {
+    routes: HashMap<String, Route>,
+}
+
+impl HttpServer {
+    pub fn new() -> HttpServer {
+        HttpServer {
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn route(&mut self, path: &str, route: Route) {
+        self.routes.insert(path.to_string(), route);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = self.routes.keys().cloned().collect::<Vec<String>>();
+        paths.sort();
+        paths
+    }
+
+    pub async fn run(self, addr: String) -> Result<()> {
+        let listener = TcpListener::bind(&addr).await?;
+        let routes = Arc::new(self.routes);
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let routes_c = routes.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &routes_c).await {
+                            eprintln!("{}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if buf[..len].windows(4).any(|x| x == b"\r\n\r\n") {
+            break;
+        }
+    }
+    Ok(len)
+}
+
+async fn handle(mut stream: TcpStream, routes: &HashMap<String, Route>) -> Result<()> {
+    let mut buf = vec![0u8; MAX_REQUEST_SIZE];
+    let len = time::timeout(IO_TIMEOUT, read_request(&mut stream, &mut buf))
+        .await
+        .map_err(|_| anyhow!("http: timed out reading request"))??;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let response = if method != "GET" {
+        Response::error(405, "method not allowed")
+    } else {
+        match routes.get(path) {
+            Some(route) => route(query).unwrap_or_else(|e| Response::error(500, &e.to_string())),
+            None => Response::error(404, "not found"),
+        }
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    );
+    time::timeout(IO_TIMEOUT, async {
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(response.body.as_bytes()).await
+    })
+    .await
+    .map_err(|_| anyhow!("http: timed out writing response"))??;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+/// An `http://host:port/path` URL; TLS is not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url> {
+        if url.starts_with("https://") {
+            return Err(anyhow!(
+                "{}: https is not supported, use http:// or a TLS-terminating proxy",
+                url
+            ));
+        }
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("unsupported url {}, only http:// is supported", url))?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("invalid url {}", url));
+        }
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Url {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Sends a blocking HTTP/1.1 request on a new connection and returns the
+/// status and body of the response.
+pub fn request(
+    method: &str,
+    url: &Url,
+    target: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<(u16, Vec<u8>)> {
+    let mut stream =
+        std::net::TcpStream::connect(&url.host).map_err(|e| anyhow!("{}: {}", url.host, e))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut request = format!(
+        "{} {}{} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method,
+        url.path,
+        target,
+        url.host,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let end = response
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("{}: invalid response", url.host))?;
+    let header = String::from_utf8_lossy(&response[..end]).to_lowercase();
+    let status = header
+        .split(' ')
+        .nth(1)
+        .and_then(|x| x.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("{}: invalid response", url.host))?;
+    let body = &response[end + 4..];
+    let body = if header.contains("transfer-encoding: chunked") {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok((status, body))
+}
+
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = data
+            .windows(2)
+            .position(|x| x == b"\r\n")
+            .ok_or_else(|| anyhow!("invalid chunked response"))?;
+        let line = String::from_utf8_lossy(&data[..end]);
+        let size = usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16)?;
+        data = &data[end + 2..];
+        if size == 0 || data.len() < size {
+            break;
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[(size + 2).min(data.len())..];
+    }
+    Ok(body)
+}
+
+/// Percent-encodes a query string value.
+pub fn encode_query(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// The value of an `Authorization` header for HTTP basic authentication.
+pub fn basic_auth(user: &str, password: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let data = format!("{}:{}", user, password).into_bytes();
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4 + 6);
+    encoded.push_str("Basic ");
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        let url = Url::parse("http://localhost:8123/").unwrap();
+        assert_eq!(url.host, "localhost:8123");
+        assert_eq!(url.path, "");
+        let url = Url::parse("http://es.example.com/prefix").unwrap();
+        assert_eq!(url.host, "es.example.com:80");
+        assert_eq!(url.path, "/prefix");
+        let e = Url::parse("https://es.example.com:9200").unwrap_err();
+        assert!(e.to_string().contains("https is not supported"));
+        assert!(Url::parse("ftp://example.com").is_err());
+        assert!(Url::parse("http:///path").is_err());
+    }
+}
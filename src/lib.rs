@@ -3,16 +3,31 @@ extern crate byteorder;
 extern crate csv;
 extern crate derive_builder;
 extern crate field_types;
+extern crate flate2;
 extern crate once_cell;
 extern crate serde;
 extern crate serde_json;
 extern crate structopt;
 
+pub mod application_cache;
+pub mod bgp;
+pub mod bmp;
+pub mod decode;
+pub mod event;
+pub mod filter;
+pub mod flowkey;
 pub mod flowmessage;
 pub mod handler;
+pub mod http;
+pub mod metrics;
+pub mod nfcapd;
 pub mod opt;
 pub mod option_cache;
+pub mod prefix;
+pub mod processor;
+pub mod projection;
 pub mod publisher;
+pub mod sequence;
 pub mod server;
 pub mod template_cache;
 pub mod util;
@@ -1,9 +1,43 @@
 use std::error::Error;
+use std::sync::{Arc, RwLock};
 use tokio::net::UdpSocket;
 
+use ferrisflow::bmp::{BmpServer, Rib};
+use ferrisflow::decode::Services;
+use ferrisflow::filter::Filter;
+use ferrisflow::flowkey::FlowKey;
 use ferrisflow::handler::{Handler, NetflowV5Handler, NetflowV9Handler};
-use ferrisflow::publisher::{CsvPublisher, JsonPublisher, PrintPublisher, Publisher};
-use ferrisflow::server::Server;
+use ferrisflow::http::HttpServer;
+use ferrisflow::metrics;
+use ferrisflow::nfcapd;
+use ferrisflow::prefix::Prefix;
+use ferrisflow::processor::anonymize::{AnonymizeMode, CryptoPan};
+use ferrisflow::processor::ddos::{Threshold, Thresholds};
+use ferrisflow::processor::dedup::DedupMode;
+use ferrisflow::processor::{
+    AggregateProcessor, AnonymizeProcessor, BiflowProcessor, BmpProcessor, DdosProcessor,
+    DecodeProcessor, DedupProcessor, GeoIpProcessor, InterfaceProcessor, PrefixTableProcessor,
+    Processor, TopNProcessor,
+};
+use ferrisflow::projection::Projection;
+use ferrisflow::publisher::clickhouse::{self, ClickHouseConfig};
+use ferrisflow::publisher::elasticsearch::ElasticsearchConfig;
+use ferrisflow::publisher::encoder::{Encoder, Format};
+use ferrisflow::publisher::json::JsonEncoder;
+use ferrisflow::publisher::kafka::{Compression, KafkaConfig};
+use ferrisflow::publisher::parquet;
+use ferrisflow::publisher::print::Table;
+use ferrisflow::publisher::prometheus::{LabelSet, DEFAULT_LABEL_SETS};
+use ferrisflow::publisher::protobuf::{ProtobufEncoder, ProtobufMode};
+use ferrisflow::publisher::rotate::{FileCompression, RotatingFile, Rotation};
+use ferrisflow::publisher::{
+    ClickHousePublisher, CsvPublisher, ElasticsearchPublisher, FilteredPublisher, JsonPublisher,
+    KafkaPublisher, NfcapdPublisher, ParquetPublisher, PrintPublisher, PrometheusPublisher,
+    ProtobufPublisher, Publisher,
+};
+use ferrisflow::sequence::SequenceTracker;
+use ferrisflow::server::{Replay, Server};
+use ferrisflow::util::{parse_duration, parse_number};
 
 use ferrisflow::opt::Opt;
 use structopt::StructOpt;
@@ -11,17 +45,34 @@ use structopt::StructOpt;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let opt = Opt::from_args();
-    let addr = format!("{}{}", "0.0.0.0:", opt.port);
-    let socket = UdpSocket::bind(&addr).await?;
-    eprintln!("Listening on: {}", socket.local_addr()?);
 
+    // Windowed processors follow the wall clock, so a replay would end up in
+    // one window instead of the windows of the recorded flows.
+    if !opt.replay.is_empty() {
+        let windowed = [
+            ("--dedup-window", opt.dedup_window.is_some()),
+            ("--biflow-window", opt.biflow_window.is_some()),
+            ("--ddos-window", opt.ddos_window.is_some()),
+            ("--topn-window", opt.topn_window.is_some()),
+            ("--aggregate-window", opt.aggregate_window.is_some()),
+        ];
+        if let Some((option, _)) = windowed.iter().find(|x| x.1) {
+            return Err(format!("replay: {} cannot be used with --replay", option).into());
+        }
+    }
+
+    let sequence_loss_tag = match &opt.sequence_loss_tag {
+        Some(period) => Some(parse_duration(period)?),
+        None => None,
+    };
+    let sequence_tracker = SequenceTracker::new(sequence_loss_tag);
     let mut handlers: Vec<Box<dyn Handler>> = Vec::new();
     if opt.netflow_v5 {
-        let netflow_v5_handler = Box::new(NetflowV5Handler::new());
+        let netflow_v5_handler = Box::new(NetflowV5Handler::new(sequence_tracker.clone()));
         handlers.push(netflow_v5_handler);
     }
     if opt.netflow_v9 {
-        let netflow_v9_handler = Box::new(NetflowV9Handler::new());
+        let netflow_v9_handler = Box::new(NetflowV9Handler::new(sequence_tracker));
         handlers.push(netflow_v9_handler);
     }
     eprintln!(
@@ -33,18 +84,273 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .join(", ")
     );
 
+    let filter = match &opt.filter {
+        Some(filter) => Some(Filter::global(filter)?),
+        None => None,
+    };
+    if let Some(filter) = &filter {
+        eprintln!("filter: {}", filter);
+    }
+
+    let mut http_server = HttpServer::new();
+
+    let processors = processors(&opt, &mut http_server)?;
+    eprintln!(
+        "processors: [{}]",
+        processors
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
     let mut publishers: Vec<Box<dyn Publisher>> = Vec::new();
     if opt.print {
-        let print_publisher = Box::new(PrintPublisher::new());
-        publishers.push(print_publisher);
+        let table = if opt.print_table || opt.print_top.is_some() {
+            Some(Table::new(
+                &opt.print_columns,
+                opt.print_top,
+                parse_duration(&opt.print_top_window)?,
+            )?)
+        } else {
+            None
+        };
+        let print_publisher: Box<dyn Publisher> =
+            Box::new(PrintPublisher::new(projection(&opt.print_fields)?, table));
+        match &opt.print_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, print_publisher)));
+            }
+            None => publishers.push(print_publisher),
+        }
+    }
+    let rotation = Rotation {
+        interval: Some(parse_duration(&opt.rotate_interval)?),
+        max_size: match &opt.rotate_max_size {
+            Some(x) => Some(parse_number(x).ok_or("invalid rotate max size")?),
+            None => None,
+        },
+    };
+    let rotating_file = |path: &str| {
+        RotatingFile::new(
+            path,
+            rotation,
+            opt.rotate_compression.parse::<FileCompression>()?,
+            opt.post_rotate.clone(),
+        )
+    };
+    if opt.json || opt.json_path.is_some() {
+        let json_publisher: Box<dyn Publisher> = match &opt.json_path {
+            Some(path) => Box::new(JsonPublisher::to_file(
+                projection(&opt.json_fields)?,
+                opt.json_omit_null,
+                rotating_file(path)?,
+            )),
+            None => Box::new(JsonPublisher::new(
+                projection(&opt.json_fields)?,
+                opt.json_omit_null,
+            )),
+        };
+        match &opt.json_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, json_publisher)));
+            }
+            None => publishers.push(json_publisher),
+        }
+    }
+    if opt.csv || opt.csv_path.is_some() {
+        let csv_publisher: Box<dyn Publisher> = match &opt.csv_path {
+            Some(path) => Box::new(CsvPublisher::to_file(
+                opt.header_none,
+                projection(&opt.csv_fields)?,
+                rotating_file(path)?,
+            )),
+            None => Box::new(CsvPublisher::new(
+                opt.header_none,
+                projection(&opt.csv_fields)?,
+            )),
+        };
+        match &opt.csv_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, csv_publisher)));
+            }
+            None => publishers.push(csv_publisher),
+        }
+    }
+    if let Some(path) = &opt.parquet_path {
+        let max_size = match &opt.parquet_max_size {
+            Some(x) => Some(parse_number(x).ok_or("parquet: invalid max size")?),
+            None => None,
+        };
+        let rotation = Rotation {
+            interval: Some(parse_duration(&opt.parquet_rotate_interval)?),
+            max_size,
+        };
+        let parquet_publisher: Box<dyn Publisher> = Box::new(ParquetPublisher::new(
+            path,
+            opt.parquet_fields.as_deref().map(split_list),
+            parquet::parse_compression(&opt.parquet_compression)?,
+            rotation,
+            opt.parquet_row_group_size,
+            opt.post_rotate.clone(),
+        )?);
+        match &opt.parquet_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, parquet_publisher)));
+            }
+            None => publishers.push(parquet_publisher),
+        }
+    }
+    if let Some(path) = &opt.nfcapd_path {
+        let rotation = Rotation {
+            interval: Some(parse_duration(&opt.nfcapd_rotate_interval)?),
+            max_size: None,
+        };
+        let nfcapd_publisher: Box<dyn Publisher> = Box::new(NfcapdPublisher::new(
+            path,
+            rotation,
+            opt.nfcapd_compression.parse::<nfcapd::Compression>()?,
+            &opt.nfcapd_ident,
+            opt.post_rotate.clone(),
+        )?);
+        match &opt.nfcapd_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, nfcapd_publisher)));
+            }
+            None => publishers.push(nfcapd_publisher),
+        }
+    }
+    let protobuf_mode = opt.protobuf_mode.parse::<ProtobufMode>()?;
+    if let Some(target) = &opt.protobuf_output {
+        let protobuf_publisher: Box<dyn Publisher> = Box::new(ProtobufPublisher::new(
+            target,
+            ProtobufEncoder::new(protobuf_mode),
+        )?);
+        match &opt.protobuf_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, protobuf_publisher)));
+            }
+            None => publishers.push(protobuf_publisher),
+        }
     }
-    if opt.json {
-        let json_publisher = Box::new(JsonPublisher::new());
-        publishers.push(json_publisher);
+    if let Some(brokers) = &opt.kafka_brokers {
+        let config = KafkaConfig {
+            brokers: split_list(brokers),
+            topic: opt.kafka_topic.clone(),
+            key: opt.kafka_key.as_deref().map(split_list).unwrap_or_default(),
+            acks: opt.kafka_acks,
+            timeout: parse_duration(&opt.kafka_timeout)?,
+            compression: opt.kafka_compression.parse::<Compression>()?,
+            retries: opt.kafka_retries,
+            batch_size: opt.kafka_batch_size,
+            linger: parse_duration(&opt.kafka_linger)?,
+        };
+        let encoder = match opt.kafka_format.parse::<Format>()? {
+            Format::Json => Encoder::Json(JsonEncoder::new(projection(&opt.kafka_fields)?, true)),
+            Format::Protobuf => {
+                if opt.kafka_fields.is_some() {
+                    return Err("kafka: --kafka-fields only applies to json".into());
+                }
+                Encoder::Protobuf(ProtobufEncoder::new(protobuf_mode))
+            }
+        };
+        let kafka_publisher: Box<dyn Publisher> = Box::new(KafkaPublisher::new(config, encoder)?);
+        match &opt.kafka_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(filter, kafka_publisher)));
+            }
+            None => publishers.push(kafka_publisher),
+        }
     }
-    if opt.csv {
-        let csv_publisher = Box::new(CsvPublisher::new(opt.header_none));
-        publishers.push(csv_publisher);
+    if let Some(url) = &opt.clickhouse_url {
+        let config = ClickHouseConfig {
+            url: url.clone(),
+            database: opt.clickhouse_database.clone(),
+            table: opt.clickhouse_table.clone(),
+            user: opt.clickhouse_user.clone(),
+            password: opt.clickhouse_password.clone(),
+            format: opt.clickhouse_format.parse::<clickhouse::Format>()?,
+            fields: opt.clickhouse_fields.as_deref().map(split_list),
+            create_table: opt.clickhouse_create_table,
+            batch_size: opt.clickhouse_batch_size,
+            linger: parse_duration(&opt.clickhouse_linger)?,
+            retries: opt.clickhouse_retries,
+            timeout: parse_duration(&opt.clickhouse_timeout)?,
+            spool: opt.clickhouse_spool.clone(),
+            spool_max_size: match &opt.clickhouse_spool_max_size {
+                Some(x) => Some(parse_number(x).ok_or("clickhouse: invalid spool max size")?),
+                None => None,
+            },
+        };
+        let clickhouse_publisher: Box<dyn Publisher> = Box::new(ClickHousePublisher::new(config)?);
+        match &opt.clickhouse_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(
+                    filter,
+                    clickhouse_publisher,
+                )));
+            }
+            None => publishers.push(clickhouse_publisher),
+        }
+    }
+    if let Some(url) = &opt.elasticsearch_url {
+        let config = ElasticsearchConfig {
+            url: url.clone(),
+            index: opt.elasticsearch_index.clone(),
+            template: opt.elasticsearch_template,
+            user: opt.elasticsearch_user.clone(),
+            password: opt.elasticsearch_password.clone(),
+            batch_size: opt.elasticsearch_batch_size,
+            batch_bytes: parse_number(&opt.elasticsearch_batch_bytes)
+                .ok_or("elasticsearch: invalid batch bytes")? as usize,
+            linger: parse_duration(&opt.elasticsearch_linger)?,
+            retries: opt.elasticsearch_retries,
+            timeout: parse_duration(&opt.elasticsearch_timeout)?,
+        };
+        let elasticsearch_publisher: Box<dyn Publisher> = Box::new(ElasticsearchPublisher::new(
+            config,
+            projection(&opt.elasticsearch_fields)?,
+        )?);
+        match &opt.elasticsearch_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(
+                    filter,
+                    elasticsearch_publisher,
+                )));
+            }
+            None => publishers.push(elasticsearch_publisher),
+        }
+    }
+    if opt.prometheus {
+        let label_sets = DEFAULT_LABEL_SETS
+            .iter()
+            .map(|x| x.to_string())
+            .chain(opt.prometheus_labels.iter().cloned())
+            .map(|x| x.parse::<LabelSet>())
+            .collect::<anyhow::Result<Vec<LabelSet>>>()?;
+        let prometheus_publisher = PrometheusPublisher::new(label_sets, opt.prometheus_max_series)?;
+        let prometheus_publisher_c = prometheus_publisher.clone();
+        metrics::collector(move || prometheus_publisher_c.render());
+        let prometheus_publisher: Box<dyn Publisher> = Box::new(prometheus_publisher);
+        match &opt.prometheus_filter {
+            Some(filter) => {
+                let filter = Filter::new(filter)?;
+                publishers.push(Box::new(FilteredPublisher::new(
+                    filter,
+                    prometheus_publisher,
+                )));
+            }
+            None => publishers.push(prometheus_publisher),
+        }
     }
     eprintln!(
         "publishers: [{}]",
@@ -55,14 +361,246 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .join(", ")
     );
 
+    if let Some(http_port) = &opt.http_port {
+        http_server.route("/metrics", metrics::route());
+        if !http_server.is_empty() {
+            eprintln!("http: [{}]", http_server.paths().join(", "));
+            let http_addr = format!("{}{}", "0.0.0.0:", http_port);
+            tokio::spawn(async move {
+                if let Err(e) = http_server.run(http_addr).await {
+                    eprintln!("{}", e);
+                }
+            });
+        }
+    }
+
+    if !opt.replay.is_empty() {
+        let replay = Replay {
+            paths: opt.replay.clone(),
+            filter,
+            processors,
+            publishers,
+        };
+        replay.run()?;
+        return Ok(());
+    }
+
+    let addr = format!("{}{}", "0.0.0.0:", opt.port);
+    let socket = UdpSocket::bind(&addr).await?;
+    eprintln!("Listening on: {}", socket.local_addr()?);
+
     let server = Server {
-        socket: socket,
+        socket,
         buf: vec![0u8; 4096],
-        handlers: handlers,
-        publishers: publishers,
+        handlers,
+        filter,
+        processors,
+        publishers,
     };
 
     server.run().await?;
 
     Ok(())
 }
+
+/// Builds the processor chain in the order flows go through it.
+fn processors(
+    opt: &Opt,
+    http_server: &mut HttpServer,
+) -> Result<Vec<Box<dyn Processor>>, Box<dyn Error>> {
+    let mut processors: Vec<Box<dyn Processor>> = Vec::new();
+    if let Some(bmp_port) = &opt.bmp_port {
+        let rib = Arc::new(RwLock::new(Rib::new()));
+        let bmp_server = BmpServer::new(rib.clone());
+        let bmp_addr = format!("{}{}", "0.0.0.0:", bmp_port);
+        eprintln!("bmp: listening on {}", bmp_addr);
+        tokio::spawn(async move {
+            if let Err(e) = bmp_server.run(bmp_addr).await {
+                eprintln!("{}", e);
+            }
+        });
+        processors.push(Box::new(BmpProcessor::new(rib)));
+    }
+    if !opt.prefix_table.is_empty() {
+        let prefix_table_processor = Box::new(PrefixTableProcessor::new(
+            opt.prefix_table.clone(),
+            parse_duration(&opt.prefix_table_reload_interval)?,
+        )?);
+        processors.push(prefix_table_processor);
+    }
+    if opt.geoip_city.is_some() || opt.geoip_asn.is_some() {
+        let geoip_processor = Box::new(GeoIpProcessor::new(
+            opt.geoip_city.as_deref(),
+            opt.geoip_asn.as_deref(),
+            parse_duration(&opt.geoip_reload_interval)?,
+        )?);
+        processors.push(geoip_processor);
+    }
+    if let Some(interfaces) = &opt.interfaces {
+        let interface_processor = Box::new(InterfaceProcessor::new(
+            interfaces,
+            parse_duration(&opt.interfaces_reload_interval)?,
+        )?);
+        processors.push(interface_processor);
+    }
+    if let Some(window) = &opt.dedup_window {
+        let prefer = opt
+            .dedup_prefer
+            .iter()
+            .flat_map(|x| x.split(','))
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| x.trim().parse::<Prefix>())
+            .collect::<anyhow::Result<Vec<Prefix>>>()?;
+        let dedup_processor = Box::new(DedupProcessor::new(
+            parse_duration(window)?,
+            opt.dedup_mode.parse::<DedupMode>()?,
+            prefer,
+        ));
+        processors.push(dedup_processor);
+    }
+    if let Some(window) = &opt.biflow_window {
+        processors.push(Box::new(BiflowProcessor::new(parse_duration(window)?)));
+    }
+    if let Some(window) = &opt.ddos_window {
+        let threshold = match &opt.ddos_threshold {
+            Some(threshold) => threshold.parse::<Threshold>()?,
+            None => Threshold::default(),
+        };
+        let mut thresholds = Thresholds::new(threshold);
+        if let Some(path) = &opt.ddos_thresholds {
+            thresholds.load(path)?;
+        }
+        let ddos_processor = Box::new(DdosProcessor::new(
+            parse_duration(window)?,
+            parse_duration(&opt.ddos_cooldown)?,
+            thresholds,
+        )?);
+        processors.push(ddos_processor);
+    }
+    if let Some(window) = &opt.topn_window {
+        let topn_keys = if opt.topn_key.is_empty() {
+            vec![
+                "src_addr".to_string(),
+                "dst_addr".to_string(),
+                "dst_port".to_string(),
+            ]
+        } else {
+            opt.topn_key.clone()
+        };
+        let topn_processor = TopNProcessor::new(
+            parse_duration(window)?,
+            opt.topn,
+            opt.topn_capacity,
+            &opt.topn_metric,
+            topn_keys
+                .iter()
+                .map(|x| FlowKey::new(x))
+                .collect::<anyhow::Result<Vec<FlowKey>>>()?,
+        )?;
+        http_server.route("/topn", topn_processor.route());
+        processors.push(Box::new(topn_processor));
+    }
+    if let Some(window) = &opt.aggregate_window {
+        let key = FlowKey::new(&opt.aggregate_key)?;
+        let speeds = ["in_if_speed", "out_if_speed"];
+        if opt.interfaces.is_some()
+            && !key
+                .fields
+                .iter()
+                .any(|x| speeds.contains(&x.field.as_str()))
+        {
+            eprintln!(
+                "aggregate: add in_if_speed or out_if_speed to --aggregate-key for utilisation"
+            );
+        }
+        let aggregate_processor = Box::new(AggregateProcessor::new(parse_duration(window)?, key));
+        processors.push(aggregate_processor);
+    }
+    if opt.decode {
+        let services = match &opt.services {
+            Some(services) => Services::load(services)?,
+            None => Services::new(),
+        };
+        processors.push(Box::new(DecodeProcessor::new(services)));
+    }
+    // Last, so that thresholds, keys and reports see real addresses.
+    if opt.anonymize.is_some() || opt.anonymize_mac {
+        let mode = match &opt.anonymize {
+            Some(mode) => Some(mode.parse::<AnonymizeMode>()?),
+            None => None,
+        };
+        let cryptopan = match &opt.anonymize_key_file {
+            Some(path) => Some(CryptoPan::load(path)?),
+            None => None,
+        };
+        let anonymize_processor =
+            Box::new(AnonymizeProcessor::new(mode, cryptopan, opt.anonymize_mac)?);
+        processors.push(anonymize_processor);
+    }
+    Ok(processors)
+}
+
+fn projection(fields: &Option<String>) -> Result<Option<Projection>, Box<dyn Error>> {
+    match fields {
+        Some(fields) => Ok(Some(Projection::new(fields)?)),
+        None => Ok(None),
+    }
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisflow::event::Event;
+    use ferrisflow::flowmessage::{FieldValue, FlowMessageBuilder};
+    use std::fs;
+
+    #[test]
+    fn ddos_thresholds_see_real_addresses() {
+        let path = std::env::temp_dir().join("ferrisflow-ddos-thresholds");
+        fs::write(&path, "192.0.2.1/32 pps=100\n").unwrap();
+        let opt = Opt::from_iter([
+            "ferrisflow",
+            "--anonymize",
+            "truncate/24/48",
+            "--ddos-window",
+            "1s",
+            "--ddos-threshold",
+            "pps=1G",
+            "--ddos-thresholds",
+            path.to_str().unwrap(),
+        ]);
+        let processors = processors(&opt, &mut HttpServer::new()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut flowmessage = FlowMessageBuilder::default().version(9u16).build().unwrap();
+        let dst_addr = FieldValue::Ip("192.0.2.1".parse().unwrap());
+        flowmessage.set_field("dst_addr", Some(dst_addr)).unwrap();
+        flowmessage
+            .set_field("packets", Some(FieldValue::Uint(1000)))
+            .unwrap();
+        let mut flowmessages = vec![flowmessage];
+        for processor in processors.iter() {
+            flowmessages = processor.process(flowmessages).unwrap();
+        }
+        assert_eq!(
+            flowmessages[0].field("dst_addr"),
+            Some(FieldValue::Ip("192.0.2.0".parse().unwrap()))
+        );
+        let alerts = processors
+            .iter()
+            .flat_map(|x| x.events().unwrap())
+            .filter_map(|x| match x {
+                Event::Alert(alert) => Some(alert.prefix),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(alerts, ["192.0.2.1/32"]);
+    }
+}
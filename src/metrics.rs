@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::http::{Response, Route};
+use once_cell::sync::Lazy;
+
+/// The upper bounds, in seconds, of the duration histogram buckets.
+const BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// The metrics the collector reports about itself, in the order they are
+/// rendered.
+const METRICS: [(&str, Kind, &str); 20] = [
+    (
+        "ferrisflow_packets_received_total",
+        Kind::Counter,
+        "Packets received per exporter and version.",
+    ),
+    (
+        "ferrisflow_packets_dropped_total",
+        Kind::Counter,
+        "Packets no handler could decode per exporter and version.",
+    ),
+    (
+        "ferrisflow_receive_errors_total",
+        Kind::Counter,
+        "Errors receiving from the socket.",
+    ),
+    (
+        "ferrisflow_decode_errors_total",
+        Kind::Counter,
+        "Packets that failed to decode by reason.",
+    ),
+    (
+        "ferrisflow_template_misses_total",
+        Kind::Counter,
+        "Data flowsets whose template has not been received per exporter.",
+    ),
+    (
+        "ferrisflow_templates_cached",
+        Kind::Gauge,
+        "Templates in the template cache.",
+    ),
+    (
+        "ferrisflow_flows_received_total",
+        Kind::Counter,
+        "Flows decoded per exporter.",
+    ),
+    (
+        "ferrisflow_packets_in_flight",
+        Kind::Gauge,
+        "Packets received and not yet through the pipeline.",
+    ),
+    (
+        "ferrisflow_processor_errors_total",
+        Kind::Counter,
+        "Errors returned by processors.",
+    ),
+    (
+        "ferrisflow_publisher_publish_total",
+        Kind::Counter,
+        "Publish calls per publisher and result.",
+    ),
+    (
+        "ferrisflow_publisher_duration_seconds",
+        Kind::Histogram,
+        "Time spent in publish calls per publisher.",
+    ),
+    (
+        "ferrisflow_sink_records_total",
+        Kind::Counter,
+        "Records written by background sinks per result.",
+    ),
+    (
+        "ferrisflow_queue_depth",
+        Kind::Gauge,
+        "Records waiting in a background queue.",
+    ),
+    (
+        "ferrisflow_queue_capacity",
+        Kind::Gauge,
+        "Records a background queue holds before dropping.",
+    ),
+    (
+        "ferrisflow_queue_dropped_total",
+        Kind::Counter,
+        "Records dropped because a background queue was full.",
+    ),
+    (
+        "ferrisflow_sequence_received_total",
+        Kind::Counter,
+        "Sequence numbers received per observation domain, flows for v5 and packets for v9.",
+    ),
+    (
+        "ferrisflow_sequence_lost_total",
+        Kind::Counter,
+        "Sequence numbers skipped per observation domain.",
+    ),
+    (
+        "ferrisflow_sequence_reordered_total",
+        Kind::Counter,
+        "Sequence numbers received late per observation domain.",
+    ),
+    (
+        "ferrisflow_sequence_resets_total",
+        Kind::Counter,
+        "Sequence restarts and jumps per observation domain.",
+    ),
+    (
+        "ferrisflow_sequence_loss_ratio",
+        Kind::Gauge,
+        "Share of sequence numbers lost over the last minute per observation domain.",
+    ),
+];
+
+/// A counter handle, updated without taking the registry lock.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// A gauge handle, updated without taking the registry lock.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    /// Moves the gauge up or down.
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: [AtomicU64; BUCKETS.len()],
+    /// The bits of the f64 sum.
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+/// A duration histogram handle, updated without taking the registry lock.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram(Arc<Buckets>);
+
+impl Histogram {
+    /// Records a duration in seconds.
+    pub fn observe(&self, seconds: f64) {
+        for (bucket, bound) in self.0.buckets.iter().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let _ = self
+            .0
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                Some((f64::from_bits(x) + seconds).to_bits())
+            });
+        self.0.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+type Read = Arc<dyn Fn() -> f64 + Send + Sync>;
+type Collector = Arc<dyn Fn() -> String + Send + Sync>;
+
+#[derive(Default)]
+struct Registry {
+    /// Series by metric name and rendered labels.
+    series: BTreeMap<&'static str, BTreeMap<String, Series>>,
+    /// Series whose value is read when scraped.
+    reads: BTreeMap<&'static str, BTreeMap<String, Read>>,
+    collectors: Vec<Collector>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<String>>();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// Escapes a label value.
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Returns the series of `name` with `labels`, registering it with
+/// `new` the first time.
+fn series(name: &'static str, labels: &[(&str, &str)], new: fn() -> Series) -> Series {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry
+        .series
+        .entry(name)
+        .or_default()
+        .entry(self::labels(labels))
+        .or_insert_with(new)
+        .clone()
+}
+
+/// Registers a counter and returns its handle, for series updated on the
+/// packet path.
+pub fn counter(name: &'static str, labels: &[(&str, &str)]) -> Counter {
+    match series(name, labels, || Series::Counter(Counter::default())) {
+        Series::Counter(x) => x,
+        _ => Counter::default(),
+    }
+}
+
+/// Registers a gauge and returns its handle.
+pub fn gauge(name: &'static str, labels: &[(&str, &str)]) -> Gauge {
+    match series(name, labels, || Series::Gauge(Gauge::default())) {
+        Series::Gauge(x) => x,
+        _ => Gauge::default(),
+    }
+}
+
+/// Registers a duration histogram and returns its handle.
+pub fn histogram(name: &'static str, labels: &[(&str, &str)]) -> Histogram {
+    match series(name, labels, || Series::Histogram(Histogram::default())) {
+        Series::Histogram(x) => x,
+        _ => Histogram::default(),
+    }
+}
+
+pub fn inc(name: &'static str, labels: &[(&str, &str)]) {
+    counter(name, labels).inc();
+}
+
+pub fn add(name: &'static str, labels: &[(&str, &str)], n: u64) {
+    counter(name, labels).add(n);
+}
+
+/// Reports the value `read` returns when scraped, e.g. the length of a
+/// queue, replacing what was registered under the same labels before.
+pub fn register<F>(name: &'static str, labels: &[(&str, &str)], read: F)
+where
+    F: Fn() -> f64 + Send + Sync + 'static,
+{
+    let mut registry = REGISTRY.lock().unwrap();
+    registry
+        .reads
+        .entry(name)
+        .or_default()
+        .insert(self::labels(labels), Arc::new(read));
+}
+
+/// Stops reporting what was registered under `labels`.
+pub fn unregister(name: &'static str, labels: &[(&str, &str)]) {
+    let mut registry = REGISTRY.lock().unwrap();
+    if let Some(reads) = registry.reads.get_mut(name) {
+        reads.remove(&self::labels(labels));
+    }
+}
+
+/// Appends the text `collect` returns to every scrape, for metrics kept
+/// elsewhere such as the flow counters of the Prometheus publisher.
+pub fn collector<F>(collect: F)
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    REGISTRY.lock().unwrap().collectors.push(Arc::new(collect));
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render() -> String {
+    let (series, reads, collectors) = {
+        let registry = REGISTRY.lock().unwrap();
+        (
+            registry.series.clone(),
+            registry.reads.clone(),
+            registry.collectors.clone(),
+        )
+    };
+    let mut body = String::new();
+    for (name, kind, help) in METRICS.iter() {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind.as_str());
+        for (labels, series) in series.get(name).into_iter().flatten() {
+            match series {
+                Series::Counter(Counter(x)) => {
+                    let _ = writeln!(body, "{}{} {}", name, labels, x.load(Ordering::Relaxed));
+                }
+                Series::Gauge(Gauge(x)) => {
+                    let _ = writeln!(body, "{}{} {}", name, labels, x.load(Ordering::Relaxed));
+                }
+                Series::Histogram(Histogram(x)) => {
+                    let inner = labels.trim_start_matches('{').trim_end_matches('}');
+                    let separator = if inner.is_empty() { "" } else { "," };
+                    for (bucket, bound) in x.buckets.iter().zip(BUCKETS.iter()) {
+                        let _ = writeln!(
+                            body,
+                            "{}_bucket{{{}{}le=\"{}\"}} {}",
+                            name,
+                            inner,
+                            separator,
+                            bound,
+                            bucket.load(Ordering::Relaxed)
+                        );
+                    }
+                    let count = x.count.load(Ordering::Relaxed);
+                    let sum = f64::from_bits(x.sum.load(Ordering::Relaxed));
+                    let _ = writeln!(
+                        body,
+                        "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+                        name, inner, separator, count
+                    );
+                    let _ = writeln!(body, "{}_sum{} {}", name, labels, sum);
+                    let _ = writeln!(body, "{}_count{} {}", name, labels, count);
+                }
+            }
+        }
+        for (labels, read) in reads.get(name).into_iter().flatten() {
+            let _ = writeln!(body, "{}{} {}", name, labels, read());
+        }
+    }
+    for collect in collectors.iter() {
+        body.push_str(&collect());
+    }
+    body
+}
+
+pub fn route() -> Route {
+    Arc::new(|_| Ok(Response::text(render())))
+}
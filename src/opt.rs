@@ -12,6 +12,9 @@ pub struct Opt {
     #[structopt(long)]
     pub netflow_v9: bool,
 
+    #[structopt(long)]
+    pub sequence_loss_tag: Option<String>,
+
     #[structopt(long)]
     pub print: bool,
 
@@ -23,4 +26,328 @@ pub struct Opt {
 
     #[structopt(short, long)]
     pub header_none: bool,
+
+    #[structopt(long)]
+    pub filter: Option<String>,
+
+    #[structopt(long)]
+    pub print_filter: Option<String>,
+
+    #[structopt(long)]
+    pub json_filter: Option<String>,
+
+    #[structopt(long)]
+    pub csv_filter: Option<String>,
+
+    #[structopt(long)]
+    pub print_fields: Option<String>,
+
+    #[structopt(long)]
+    pub print_table: bool,
+
+    #[structopt(
+        long,
+        default_value = "time,duration,proto,src,dst,packets,bytes,flags"
+    )]
+    pub print_columns: String,
+
+    #[structopt(long)]
+    pub print_top: Option<usize>,
+
+    #[structopt(long, default_value = "10s")]
+    pub print_top_window: String,
+
+    #[structopt(long)]
+    pub json_fields: Option<String>,
+
+    #[structopt(long)]
+    pub csv_fields: Option<String>,
+
+    #[structopt(long)]
+    pub json_omit_null: bool,
+
+    #[structopt(long)]
+    pub json_path: Option<String>,
+
+    #[structopt(long)]
+    pub csv_path: Option<String>,
+
+    #[structopt(long, default_value = "1h")]
+    pub rotate_interval: String,
+
+    #[structopt(long)]
+    pub rotate_max_size: Option<String>,
+
+    #[structopt(long, default_value = "none")]
+    pub rotate_compression: String,
+
+    #[structopt(long)]
+    pub post_rotate: Option<String>,
+
+    #[structopt(long)]
+    pub parquet_path: Option<String>,
+
+    #[structopt(long)]
+    pub parquet_fields: Option<String>,
+
+    #[structopt(long)]
+    pub parquet_filter: Option<String>,
+
+    #[structopt(long, default_value = "zstd")]
+    pub parquet_compression: String,
+
+    #[structopt(long, default_value = "1h")]
+    pub parquet_rotate_interval: String,
+
+    #[structopt(long)]
+    pub parquet_max_size: Option<String>,
+
+    #[structopt(long, default_value = "10000")]
+    pub parquet_row_group_size: usize,
+
+    #[structopt(long)]
+    pub protobuf_output: Option<String>,
+
+    #[structopt(long)]
+    pub protobuf_filter: Option<String>,
+
+    #[structopt(long, default_value = "native")]
+    pub protobuf_mode: String,
+
+    #[structopt(long)]
+    pub kafka_brokers: Option<String>,
+
+    #[structopt(long, default_value = "ferrisflow")]
+    pub kafka_topic: String,
+
+    #[structopt(long, default_value = "json")]
+    pub kafka_format: String,
+
+    #[structopt(long)]
+    pub kafka_key: Option<String>,
+
+    #[structopt(long)]
+    pub kafka_filter: Option<String>,
+
+    #[structopt(long)]
+    pub kafka_fields: Option<String>,
+
+    #[structopt(long, default_value = "1000")]
+    pub kafka_batch_size: usize,
+
+    #[structopt(long, default_value = "100ms")]
+    pub kafka_linger: String,
+
+    #[structopt(long, default_value = "none")]
+    pub kafka_compression: String,
+
+    #[structopt(long, default_value = "-1")]
+    pub kafka_acks: i16,
+
+    #[structopt(long, default_value = "3")]
+    pub kafka_retries: usize,
+
+    #[structopt(long, default_value = "10s")]
+    pub kafka_timeout: String,
+
+    #[structopt(long)]
+    pub nfcapd_path: Option<String>,
+
+    #[structopt(long)]
+    pub nfcapd_filter: Option<String>,
+
+    #[structopt(long, default_value = "5m")]
+    pub nfcapd_rotate_interval: String,
+
+    #[structopt(long, default_value = "none")]
+    pub nfcapd_compression: String,
+
+    #[structopt(long, default_value = "ferrisflow")]
+    pub nfcapd_ident: String,
+
+    #[structopt(long)]
+    pub replay: Vec<String>,
+
+    #[structopt(long)]
+    pub clickhouse_url: Option<String>,
+
+    #[structopt(long, default_value = "default")]
+    pub clickhouse_database: String,
+
+    #[structopt(long, default_value = "flows")]
+    pub clickhouse_table: String,
+
+    #[structopt(long)]
+    pub clickhouse_user: Option<String>,
+
+    #[structopt(long)]
+    pub clickhouse_password: Option<String>,
+
+    #[structopt(long, default_value = "rowbinary")]
+    pub clickhouse_format: String,
+
+    #[structopt(long)]
+    pub clickhouse_fields: Option<String>,
+
+    #[structopt(long)]
+    pub clickhouse_filter: Option<String>,
+
+    #[structopt(long)]
+    pub clickhouse_create_table: bool,
+
+    #[structopt(long, default_value = "10000")]
+    pub clickhouse_batch_size: usize,
+
+    #[structopt(long, default_value = "1s")]
+    pub clickhouse_linger: String,
+
+    #[structopt(long, default_value = "3")]
+    pub clickhouse_retries: usize,
+
+    #[structopt(long, default_value = "10s")]
+    pub clickhouse_timeout: String,
+
+    #[structopt(long)]
+    pub clickhouse_spool: Option<String>,
+
+    #[structopt(long)]
+    pub clickhouse_spool_max_size: Option<String>,
+
+    #[structopt(long)]
+    pub elasticsearch_url: Option<String>,
+
+    #[structopt(long, default_value = "ferrisflow-%Y.%m.%d")]
+    pub elasticsearch_index: String,
+
+    #[structopt(long)]
+    pub elasticsearch_template: bool,
+
+    #[structopt(long)]
+    pub elasticsearch_user: Option<String>,
+
+    #[structopt(long)]
+    pub elasticsearch_password: Option<String>,
+
+    #[structopt(long)]
+    pub elasticsearch_fields: Option<String>,
+
+    #[structopt(long)]
+    pub elasticsearch_filter: Option<String>,
+
+    #[structopt(long, default_value = "5000")]
+    pub elasticsearch_batch_size: usize,
+
+    #[structopt(long, default_value = "5M")]
+    pub elasticsearch_batch_bytes: String,
+
+    #[structopt(long, default_value = "1s")]
+    pub elasticsearch_linger: String,
+
+    #[structopt(long, default_value = "3")]
+    pub elasticsearch_retries: usize,
+
+    #[structopt(long, default_value = "30s")]
+    pub elasticsearch_timeout: String,
+
+    #[structopt(long)]
+    pub prometheus: bool,
+
+    #[structopt(long)]
+    pub prometheus_labels: Vec<String>,
+
+    #[structopt(long, default_value = "10000")]
+    pub prometheus_max_series: usize,
+
+    #[structopt(long)]
+    pub prometheus_filter: Option<String>,
+
+    #[structopt(long)]
+    pub aggregate_window: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "exporter,src_addr,dst_addr,src_port,dst_port,proto"
+    )]
+    pub aggregate_key: String,
+
+    #[structopt(long)]
+    pub decode: bool,
+
+    #[structopt(long)]
+    pub services: Option<String>,
+
+    #[structopt(long)]
+    pub topn_window: Option<String>,
+
+    #[structopt(long, default_value = "20")]
+    pub topn: usize,
+
+    #[structopt(long, default_value = "1000")]
+    pub topn_capacity: usize,
+
+    #[structopt(long, default_value = "bytes")]
+    pub topn_metric: String,
+
+    #[structopt(long)]
+    pub topn_key: Vec<String>,
+
+    #[structopt(long)]
+    pub geoip_city: Option<String>,
+
+    #[structopt(long)]
+    pub geoip_asn: Option<String>,
+
+    #[structopt(long, default_value = "60s")]
+    pub geoip_reload_interval: String,
+
+    #[structopt(long)]
+    pub bmp_port: Option<String>,
+
+    #[structopt(long)]
+    pub prefix_table: Vec<String>,
+
+    #[structopt(long, default_value = "60s")]
+    pub prefix_table_reload_interval: String,
+
+    #[structopt(long)]
+    pub interfaces: Option<String>,
+
+    #[structopt(long, default_value = "60s")]
+    pub interfaces_reload_interval: String,
+
+    #[structopt(long)]
+    pub anonymize: Option<String>,
+
+    #[structopt(long)]
+    pub anonymize_key_file: Option<String>,
+
+    #[structopt(long)]
+    pub anonymize_mac: bool,
+
+    #[structopt(long)]
+    pub dedup_window: Option<String>,
+
+    #[structopt(long, default_value = "drop")]
+    pub dedup_mode: String,
+
+    #[structopt(long)]
+    pub dedup_prefer: Option<String>,
+
+    #[structopt(long)]
+    pub biflow_window: Option<String>,
+
+    #[structopt(long)]
+    pub ddos_window: Option<String>,
+
+    #[structopt(long, default_value = "60s")]
+    pub ddos_cooldown: String,
+
+    #[structopt(long)]
+    pub ddos_threshold: Option<String>,
+
+    #[structopt(long)]
+    pub ddos_thresholds: Option<String>,
+
+    #[structopt(long)]
+    pub http_port: Option<String>,
 }
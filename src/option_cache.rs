@@ -9,9 +9,7 @@ pub struct OptionCacheKey {
 
 impl OptionCacheKey {
     pub fn new(exporter_ip: String) -> OptionCacheKey {
-        OptionCacheKey {
-            exporter_ip: exporter_ip,
-        }
+        OptionCacheKey { exporter_ip }
     }
 }
 
@@ -39,3 +37,9 @@ impl OptionCache {
         self.map.contains_key(k)
     }
 }
+
+impl Default for OptionCache {
+    fn default() -> OptionCache {
+        OptionCache::new()
+    }
+}
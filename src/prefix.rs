@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Prefix {
+    pub addr: IpAddr,
+    pub len: u8,
+}
+
+impl Prefix {
+    pub fn new(addr: IpAddr, len: u8) -> Result<Prefix> {
+        let max_len = max_len(&addr);
+        if len > max_len {
+            return Err(anyhow!("prefix length {} is too long for {}", len, addr));
+        }
+        Ok(Prefix {
+            addr: mask(&addr, len),
+            len,
+        })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(addr, self.len) == self.addr
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Prefix> {
+        match s.split_once('/') {
+            Some((addr, len)) => {
+                let addr = IpAddr::from_str(addr)?;
+                let len = u8::from_str(len)?;
+                Prefix::new(addr, len)
+            }
+            None => {
+                let addr = IpAddr::from_str(s)?;
+                Prefix::new(addr, max_len(&addr))
+            }
+        }
+    }
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+pub fn max_len(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+pub fn mask(addr: &IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(x) => {
+            let bits = u32::from(*x);
+            let mask = u32::MAX.checked_shl(32 - len.min(32) as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(bits & mask))
+        }
+        IpAddr::V6(x) => {
+            let bits = u128::from(*x);
+            let mask = u128::MAX
+                .checked_shl(128 - len.min(128) as u32)
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        }
+    }
+}
+
+/// Longest-prefix-match table, kept as one hash map per prefix length.
+#[derive(Debug, Clone)]
+pub struct PrefixMap<T> {
+    map: HashMap<Prefix, T>,
+    v4_lens: BTreeMap<u8, usize>,
+    v6_lens: BTreeMap<u8, usize>,
+}
+
+impl<T> PrefixMap<T> {
+    pub fn new() -> PrefixMap<T> {
+        PrefixMap {
+            map: HashMap::new(),
+            v4_lens: BTreeMap::new(),
+            v6_lens: BTreeMap::new(),
+        }
+    }
+
+    fn lens(&mut self, prefix: &Prefix) -> &mut BTreeMap<u8, usize> {
+        match prefix.addr {
+            IpAddr::V4(_) => &mut self.v4_lens,
+            IpAddr::V6(_) => &mut self.v6_lens,
+        }
+    }
+
+    pub fn insert(&mut self, prefix: Prefix, v: T) -> Option<T> {
+        let old = self.map.insert(prefix, v);
+        if old.is_none() {
+            *self.lens(&prefix).entry(prefix.len).or_insert(0) += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, prefix: &Prefix) -> Option<T> {
+        let old = self.map.remove(prefix);
+        if old.is_some() {
+            let lens = self.lens(prefix);
+            if let Some(count) = lens.get_mut(&prefix.len) {
+                *count -= 1;
+                if *count == 0 {
+                    lens.remove(&prefix.len);
+                }
+            }
+        }
+        old
+    }
+
+    pub fn get(&self, prefix: &Prefix) -> Option<&T> {
+        self.map.get(prefix)
+    }
+
+    pub fn lookup(&self, addr: &IpAddr) -> Option<(&Prefix, &T)> {
+        let lens = match addr {
+            IpAddr::V4(_) => &self.v4_lens,
+            IpAddr::V6(_) => &self.v6_lens,
+        };
+        lens.keys().rev().find_map(|len| {
+            let prefix = Prefix {
+                addr: mask(addr, *len),
+                len: *len,
+            };
+            self.map.get_key_value(&prefix)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Prefix, &T)> {
+        self.map.iter()
+    }
+}
+
+impl<T> Default for PrefixMap<T> {
+    fn default() -> PrefixMap<T> {
+        PrefixMap::new()
+    }
+}
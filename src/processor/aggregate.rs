@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::flowkey::FlowKey;
+use super::super::flowmessage::{FieldValue, FlowMessage, FlowMessageBuilder};
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::Result;
+use chrono::Utc;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counter {
+    bytes: u64,
+    packets: u64,
+    flows: u64,
+}
+
+#[derive(Debug, Default)]
+struct AggregateState {
+    window_start: u64,
+    buckets: HashMap<Vec<Option<FieldValue>>, Counter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateProcessor {
+    window: u64,
+    key: FlowKey,
+    state: Arc<Mutex<AggregateState>>,
+}
+
+impl AggregateProcessor {
+    pub fn new(window: Duration, key: FlowKey) -> AggregateProcessor {
+        AggregateProcessor {
+            window: window.as_secs().max(1),
+            key,
+            state: Arc::new(Mutex::new(AggregateState::default())),
+        }
+    }
+
+    fn close_window(&self, state: &mut AggregateState, now: u64) -> Result<Vec<FlowMessage>> {
+        let window_end = state.window_start + self.window;
+        if now < window_end {
+            return Ok(Vec::new());
+        }
+        let window_start = state.window_start;
+        state.window_start = now - now % self.window;
+
+        let datetime = Utc::now();
+        let mut rollups = Vec::with_capacity(state.buckets.len());
+        for (values, counter) in state.buckets.drain() {
+            let mut rollup = FlowMessageBuilder::default()
+                .datetime(datetime.to_string())
+                .window_start(window_start)
+                .window_end(window_end)
+                .in_bytes(counter.bytes as usize)
+                .in_pkts(counter.packets as usize)
+                .flows(counter.flows as usize)
+                .build()
+                .unwrap();
+            self.key.apply(&mut rollup, &values)?;
+            // Interface speeds in the key give the average utilisation over
+            // the window; without them it stays empty.
+            let bps = (counter.bytes * 8) as f64 / self.window as f64;
+            rollup.in_if_utilization = rollup
+                .in_if_speed
+                .filter(|x| *x > 0)
+                .map(|x| bps * 100.0 / x as f64);
+            rollup.out_if_utilization = rollup
+                .out_if_speed
+                .filter(|x| *x > 0)
+                .map(|x| bps * 100.0 / x as f64);
+            rollups.push(rollup);
+        }
+        Ok(rollups)
+    }
+}
+
+impl Display for AggregateProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AggregateProcessor({}s: {})", self.window, self.key)
+    }
+}
+
+impl Processor for AggregateProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let mut state = self.state.lock().unwrap();
+        let rollups = self.close_window(&mut state, unix_now())?;
+        for flowmessage in flowmessages.iter() {
+            let counter = state
+                .buckets
+                .entry(self.key.values(flowmessage))
+                .or_default();
+            // Sampled exports carry one in `sampling_rate` packets.
+            let sampling_rate = flowmessage.sampling_rate();
+            counter.bytes += flowmessage.uint("bytes").unwrap_or(0) * sampling_rate;
+            counter.packets += flowmessage.uint("packets").unwrap_or(0) * sampling_rate;
+            counter.flows += flowmessage.uint("flows").unwrap_or(1);
+        }
+        Ok(rollups)
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        let mut state = self.state.lock().unwrap();
+        self.close_window(&mut state, unix_now())
+    }
+
+    fn close(&self) -> Result<Vec<FlowMessage>> {
+        let mut state = self.state.lock().unwrap();
+        let window_end = state.window_start + self.window;
+        self.close_window(&mut state, window_end.max(unix_now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::InterfaceProcessor;
+    use super::*;
+    use std::fs;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    fn flow(dst_port: u64, bytes: u64) -> FlowMessage {
+        let mut flowmessage = FlowMessageBuilder::default().build().unwrap();
+        flowmessage
+            .set_field("dst_port", Some(FieldValue::Uint(dst_port)))
+            .unwrap();
+        flowmessage
+            .set_field("bytes", Some(FieldValue::Uint(bytes)))
+            .unwrap();
+        flowmessage
+            .set_field("packets", Some(FieldValue::Uint(1)))
+            .unwrap();
+        flowmessage
+    }
+
+    #[test]
+    fn rollups_at_window_end() {
+        let processor = AggregateProcessor::new(DAY, FlowKey::new("dst_port").unwrap());
+        let flowmessages = vec![flow(53, 100), flow(80, 1000), flow(53, 200)];
+        assert!(processor.process(flowmessages).unwrap().is_empty());
+        assert!(processor.flush().unwrap().is_empty());
+
+        let mut rollups = processor.close().unwrap();
+        rollups.sort_by_key(|x| x.uint("dst_port"));
+        let summary = rollups
+            .iter()
+            .map(|x| {
+                (
+                    x.uint("dst_port").unwrap(),
+                    x.uint("bytes").unwrap(),
+                    x.uint("packets").unwrap(),
+                    x.uint("flows").unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [(53, 300, 2, 2), (80, 1000, 1, 1)]);
+        let window_start = rollups[0].uint("window_start").unwrap();
+        assert_eq!(window_start % DAY.as_secs(), 0);
+        assert_eq!(
+            rollups[0].uint("window_end").unwrap(),
+            window_start + DAY.as_secs()
+        );
+        assert!(processor.close().unwrap().is_empty());
+    }
+
+    #[test]
+    fn interface_utilization() {
+        let path = std::env::temp_dir().join("ferrisflow-aggregate-interfaces.csv");
+        fs::write(&path, "ifindex,name,speed\n1,xe-0/0/0,1M\n").unwrap();
+        let interfaces = InterfaceProcessor::new(path.to_str().unwrap(), DAY).unwrap();
+        fs::remove_file(&path).unwrap();
+        let processor = AggregateProcessor::new(
+            Duration::from_secs(60),
+            FlowKey::new("input_snmp,in_if_speed").unwrap(),
+        );
+
+        // 75 kB sampled 1 in 10 over a minute is 100 kbit/s on a 1 Mbit/s link.
+        let mut flowmessage = flow(53, 75_000);
+        flowmessage
+            .set_field("input_snmp", Some(FieldValue::Uint(1)))
+            .unwrap();
+        flowmessage
+            .set_field("sampling_interval", Some(FieldValue::Uint(10)))
+            .unwrap();
+        let flowmessages = interfaces.process(vec![flowmessage]).unwrap();
+        processor.process(flowmessages).unwrap();
+        let rollups = processor.close().unwrap();
+        assert_eq!(rollups.len(), 1);
+        assert_eq!(rollups[0].uint("bytes"), Some(750_000));
+        assert_eq!(rollups[0].uint("in_if_speed"), Some(1_000_000));
+        assert_eq!(rollups[0].in_if_utilization, Some(10.0));
+        assert_eq!(rollups[0].out_if_utilization, None);
+    }
+}
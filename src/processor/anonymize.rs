@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::prefix::mask;
+use super::Processor;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use anyhow::{anyhow, Result};
+
+const ADDR_FIELDS: [&str; 5] = [
+    "src_addr",
+    "dst_addr",
+    "next_hop",
+    "bgp_ipv4_next_hop",
+    "bgp_ipv6_next_hop",
+];
+
+/// IPv4 addresses carried as integers.
+const UINT_ADDR_FIELDS: [&str; 1] = ["mpls_top_label_ip_addr"];
+
+const MAC_FIELDS: [&str; 2] = ["src_mac", "dst_mac"];
+
+const MAX_CACHE_SIZE: usize = 100_000;
+
+/// Prefix-preserving address anonymisation (Xu et al., "Crypto-PAn").
+/// Addresses sharing a k-bit prefix keep a common k-bit prefix after
+/// anonymisation.
+#[derive(Clone)]
+pub struct CryptoPan {
+    cipher: Aes128,
+    pad: [u8; 16],
+}
+
+impl CryptoPan {
+    /// The first 16 bytes of the key are the AES key and the last 16 bytes
+    /// are encrypted to form the pad.
+    pub fn new(key: &[u8; 32]) -> CryptoPan {
+        let cipher = Aes128::new(GenericArray::from_slice(&key[..16]));
+        let mut pad = GenericArray::clone_from_slice(&key[16..]);
+        cipher.encrypt_block(&mut pad);
+        let mut pad_bytes = [0u8; 16];
+        pad_bytes.copy_from_slice(&pad);
+        CryptoPan {
+            cipher,
+            pad: pad_bytes,
+        }
+    }
+
+    /// Loads a key file holding 64 hexadecimal characters or 32 raw bytes.
+    pub fn load(path: &str) -> Result<CryptoPan> {
+        let content = fs::read(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        let text = String::from_utf8_lossy(&content);
+        let text = text.trim();
+        let mut key = [0u8; 32];
+        if text.len() == 64 && text.chars().all(|x| x.is_ascii_hexdigit()) {
+            for (i, byte) in key.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16)?;
+            }
+        } else if content.len() == 32 {
+            key.copy_from_slice(&content);
+        } else {
+            return Err(anyhow!(
+                "{}: key must be 64 hex characters or 32 bytes",
+                path
+            ));
+        }
+        Ok(CryptoPan::new(&key))
+    }
+
+    fn encrypt(&self, block: [u8; 16]) -> [u8; 16] {
+        let mut block = GenericArray::from(block);
+        self.cipher.encrypt_block(&mut block);
+        block.into()
+    }
+
+    /// Anonymises the first `bits` bits of `addr`, which is left aligned in a u128.
+    fn anonymize_bits(&self, addr: u128, bits: u32) -> u128 {
+        let pad = u128::from_be_bytes(self.pad);
+        let mut result = 0u128;
+        for pos in 0..bits {
+            let prefix_mask = u128::MAX.checked_shl(128 - pos).unwrap_or(0);
+            let output = self.encrypt(((addr & prefix_mask) | (pad & !prefix_mask)).to_be_bytes());
+            result |= ((output[0] >> 7) as u128) << (127 - pos);
+        }
+        result ^ addr
+    }
+
+    pub fn anonymize(&self, addr: &IpAddr) -> IpAddr {
+        match addr {
+            IpAddr::V4(x) => {
+                let bits = (u32::from(*x) as u128) << 96;
+                IpAddr::V4(Ipv4Addr::from((self.anonymize_bits(bits, 32) >> 96) as u32))
+            }
+            IpAddr::V6(x) => IpAddr::V6(Ipv6Addr::from(self.anonymize_bits(u128::from(*x), 128))),
+        }
+    }
+
+    /// Replaces a MAC address by a keyed hash, keeping the multicast bit.
+    pub fn hash_mac(&self, mac: u64) -> u64 {
+        let mut block = self.pad;
+        block[..8].copy_from_slice(&mac.to_be_bytes());
+        let output = self.encrypt(block);
+        let mut hashed = [0u8; 8];
+        hashed[2..].copy_from_slice(&output[..6]);
+        let hashed = u64::from_be_bytes(hashed);
+        let multicast = 1 << 40;
+        (hashed & !multicast) | (mac & multicast)
+    }
+}
+
+impl std::fmt::Debug for CryptoPan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CryptoPan")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnonymizeMode {
+    CryptoPan,
+    Truncate { v4_len: u8, v6_len: u8 },
+}
+
+impl FromStr for AnonymizeMode {
+    type Err = anyhow::Error;
+
+    /// Parses `cryptopan`, or `truncate/V4_LEN/V6_LEN` such as `truncate/24/48`.
+    fn from_str(s: &str) -> Result<AnonymizeMode> {
+        let mut parts = s.split('/');
+        match parts.next() {
+            Some("cryptopan") if parts.next().is_none() => Ok(AnonymizeMode::CryptoPan),
+            Some("truncate") => {
+                let v4_len = parts.next().unwrap_or("24").parse::<u8>()?;
+                let v6_len = parts.next().unwrap_or("48").parse::<u8>()?;
+                if v4_len > 32 || v6_len > 128 || parts.next().is_some() {
+                    return Err(anyhow!("anonymize: invalid mode {}", s));
+                }
+                Ok(AnonymizeMode::Truncate { v4_len, v6_len })
+            }
+            _ => Err(anyhow!("anonymize: invalid mode {}", s)),
+        }
+    }
+}
+
+impl Display for AnonymizeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AnonymizeMode::CryptoPan => write!(f, "cryptopan"),
+            AnonymizeMode::Truncate { v4_len, v6_len } => {
+                write!(f, "truncate/{}/{}", v4_len, v6_len)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnonymizeProcessor {
+    mode: Option<AnonymizeMode>,
+    cryptopan: Option<CryptoPan>,
+    hash_mac: bool,
+    cache: Arc<Mutex<HashMap<IpAddr, IpAddr>>>,
+}
+
+impl AnonymizeProcessor {
+    /// Anonymises source, destination, next-hop and MPLS top label
+    /// addresses, and hashes MAC addresses when `hash_mac` is set.
+    /// Crypto-PAn and MAC hashing need a key.
+    pub fn new(
+        mode: Option<AnonymizeMode>,
+        cryptopan: Option<CryptoPan>,
+        hash_mac: bool,
+    ) -> Result<AnonymizeProcessor> {
+        if cryptopan.is_none() && (mode == Some(AnonymizeMode::CryptoPan) || hash_mac) {
+            return Err(anyhow!("anonymize: no key"));
+        }
+        Ok(AnonymizeProcessor {
+            mode,
+            cryptopan,
+            hash_mac,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn anonymize(&self, cache: &mut HashMap<IpAddr, IpAddr>, addr: &IpAddr) -> IpAddr {
+        match (self.mode, &self.cryptopan) {
+            (Some(AnonymizeMode::Truncate { v4_len, v6_len }), _) => match addr {
+                IpAddr::V4(_) => mask(addr, v4_len),
+                IpAddr::V6(_) => mask(addr, v6_len),
+            },
+            (Some(AnonymizeMode::CryptoPan), Some(cryptopan)) => {
+                if let Some(anonymized) = cache.get(addr) {
+                    return *anonymized;
+                }
+                if cache.len() >= MAX_CACHE_SIZE {
+                    cache.clear();
+                }
+                let anonymized = cryptopan.anonymize(addr);
+                cache.insert(*addr, anonymized);
+                anonymized
+            }
+            _ => *addr,
+        }
+    }
+}
+
+impl Display for AnonymizeProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut modes = self
+            .mode
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        if self.hash_mac {
+            modes.push("mac".to_string());
+        }
+        write!(f, "AnonymizeProcessor({})", modes.join(", "))
+    }
+}
+
+impl Processor for AnonymizeProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let mut cache = self.cache.lock().unwrap();
+        for flowmessage in flowmessages.iter_mut() {
+            for field in ADDR_FIELDS.iter() {
+                if let Some(FieldValue::Ip(addr)) = flowmessage.field(field) {
+                    let anonymized = self.anonymize(&mut cache, &addr);
+                    flowmessage.set_field(field, Some(FieldValue::Ip(anonymized)))?;
+                }
+            }
+            for field in UINT_ADDR_FIELDS.iter() {
+                if let Some(addr) = flowmessage.uint(field) {
+                    let addr = IpAddr::V4(Ipv4Addr::from(addr as u32));
+                    if let IpAddr::V4(anonymized) = self.anonymize(&mut cache, &addr) {
+                        let anonymized = u32::from(anonymized) as u64;
+                        flowmessage.set_field(field, Some(FieldValue::Uint(anonymized)))?;
+                    }
+                }
+            }
+            if let (true, Some(cryptopan)) = (self.hash_mac, &self.cryptopan) {
+                for field in MAC_FIELDS.iter() {
+                    if let Some(mac) = flowmessage.uint(field) {
+                        flowmessage
+                            .set_field(field, Some(FieldValue::Uint(cryptopan.hash_mac(mac))))?;
+                    }
+                }
+            }
+        }
+        Ok(flowmessages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    /// The sample key of the reference implementation.
+    const KEY: [u8; 32] = [
+        21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152, 143, 131,
+        121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+    ];
+
+    fn ipv4(addr: &str) -> IpAddr {
+        IpAddr::from_str(addr).unwrap()
+    }
+
+    #[test]
+    fn cryptopan_known_answers() {
+        let cryptopan = CryptoPan::new(&KEY);
+        for (addr, anonymized) in [
+            ("128.11.68.132", "135.242.180.132"),
+            ("192.102.249.13", "252.138.62.131"),
+        ] {
+            assert_eq!(cryptopan.anonymize(&ipv4(addr)), ipv4(anonymized));
+        }
+    }
+
+    #[test]
+    fn mpls_top_label_addr() {
+        let processor = AnonymizeProcessor::new(
+            Some(AnonymizeMode::CryptoPan),
+            Some(CryptoPan::new(&KEY)),
+            false,
+        )
+        .unwrap();
+        let mut flowmessage = FlowMessageBuilder::default().build().unwrap();
+        let addr = u32::from(Ipv4Addr::from_str("128.11.68.132").unwrap()) as u64;
+        flowmessage
+            .set_field("mpls_top_label_ip_addr", Some(FieldValue::Uint(addr)))
+            .unwrap();
+        let flowmessage = processor.process(vec![flowmessage]).unwrap().remove(0);
+        let anonymized = flowmessage.uint("mpls_top_label_ip_addr").unwrap() as u32;
+        assert_eq!(
+            IpAddr::V4(Ipv4Addr::from(anonymized)),
+            ipv4("135.242.180.132")
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::Result;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+type Endpoint = (Option<FieldValue>, Option<FieldValue>);
+type Key = (Option<FieldValue>, Option<FieldValue>, Endpoint, Endpoint);
+
+#[derive(Debug, Clone)]
+struct Side {
+    flowmessage: FlowMessage,
+    bytes: u64,
+    packets: u64,
+    flags: u8,
+    start: Option<u64>,
+    dst_port: Option<u64>,
+}
+
+impl Side {
+    fn new(flowmessage: FlowMessage) -> Side {
+        Side {
+            bytes: flowmessage.uint("bytes").unwrap_or(0),
+            packets: flowmessage.uint("packets").unwrap_or(0),
+            flags: flowmessage.tcp_flags.unwrap_or(0),
+            start: flowmessage.start_end_millis().map(|x| x.0),
+            dst_port: flowmessage.uint("dst_port"),
+            flowmessage,
+        }
+    }
+
+    fn merge(&mut self, other: Side) {
+        self.bytes += other.bytes;
+        self.packets += other.packets;
+        self.flags |= other.flags;
+        self.start = self.start.into_iter().chain(other.start).min();
+    }
+
+    fn is_syn_only(&self) -> bool {
+        self.flags & TCP_SYN != 0 && self.flags & TCP_ACK == 0
+    }
+}
+
+/// One direction seen so far; `forward` tells whether its source is the
+/// first endpoint of the key.
+#[derive(Debug)]
+struct Pending {
+    side: Side,
+    forward: bool,
+    arrived: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BiflowProcessor {
+    window: u64,
+    pending: Arc<Mutex<HashMap<Key, Pending>>>,
+}
+
+impl BiflowProcessor {
+    /// Pairs flows with the reverse 5-tuple from the same exporter seen within
+    /// `window`. Unpaired flows are emitted with empty reverse counters once
+    /// the window has passed.
+    pub fn new(window: Duration) -> BiflowProcessor {
+        BiflowProcessor {
+            window: window.as_secs().max(1),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn key(flowmessage: &FlowMessage) -> (Key, bool) {
+        let src = (flowmessage.field("src_addr"), flowmessage.field("src_port"));
+        let dst = (flowmessage.field("dst_addr"), flowmessage.field("dst_port"));
+        let exporter = flowmessage.field("exporter");
+        let protocol = flowmessage.field("protocol");
+        if src <= dst {
+            ((exporter, protocol, src, dst), true)
+        } else {
+            ((exporter, protocol, dst, src), false)
+        }
+    }
+
+    /// The initiator is the side that sent a SYN without ACK, or else the one
+    /// that started first, or else the one sending to the lower port, as
+    /// clients do to services.
+    fn stitch(a: Side, b: Option<Side>) -> Result<FlowMessage> {
+        let (fwd, rev) = match b {
+            Some(b) => {
+                let b_first = match (a.is_syn_only(), b.is_syn_only()) {
+                    (false, true) => true,
+                    (true, false) => false,
+                    _ => match (a.start, b.start) {
+                        (Some(x), Some(y)) if x != y => y < x,
+                        _ => matches!((a.dst_port, b.dst_port), (Some(x), Some(y)) if y < x),
+                    },
+                };
+                if b_first {
+                    (b, Some(a))
+                } else {
+                    (a, Some(b))
+                }
+            }
+            None => (a, None),
+        };
+        let mut flowmessage = fwd.flowmessage.clone();
+        let (rev_bytes, rev_packets, rev_flags) = rev
+            .as_ref()
+            .map_or((0, 0, 0), |x| (x.bytes, x.packets, x.flags));
+        flowmessage.fwd_bytes = Some(fwd.bytes);
+        flowmessage.fwd_packets = Some(fwd.packets);
+        flowmessage.fwd_tcp_flags = Some(fwd.flags);
+        flowmessage.rev_bytes = Some(rev_bytes);
+        flowmessage.rev_packets = Some(rev_packets);
+        flowmessage.rev_tcp_flags = Some(rev_flags);
+        set_total(
+            &mut flowmessage,
+            "in_bytes",
+            "d0ctets",
+            fwd.bytes + rev_bytes,
+        )?;
+        set_total(
+            &mut flowmessage,
+            "in_pkts",
+            "dpkts",
+            fwd.packets + rev_packets,
+        )?;
+        if flowmessage.tcp_flags.is_some() {
+            flowmessage.tcp_flags = Some(fwd.flags | rev_flags);
+        }
+        Ok(flowmessage)
+    }
+}
+
+/// Writes a biflow total to the field the flow counts in, `field` or the v5
+/// `v5_field`, so that the `bytes` and `packets` aliases and the raw fields
+/// agree. Totals too large for the 32-bit v5 fields move to `field`.
+fn set_total(flowmessage: &mut FlowMessage, field: &str, v5_field: &str, total: u64) -> Result<()> {
+    let total = FieldValue::Uint(total);
+    if flowmessage.field(v5_field).is_some()
+        && flowmessage
+            .set_field(v5_field, Some(total.clone()))
+            .is_err()
+    {
+        flowmessage.set_field(v5_field, None)?;
+    }
+    if flowmessage.field(v5_field).is_none() || flowmessage.field(field).is_some() {
+        flowmessage.set_field(field, Some(total))?;
+    }
+    Ok(())
+}
+
+impl Display for BiflowProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "BiflowProcessor({}s window)", self.window)
+    }
+}
+
+impl Processor for BiflowProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        let mut pending = self.pending.lock().unwrap();
+        let mut biflows = Vec::new();
+        for flowmessage in flowmessages {
+            let (key, forward) = BiflowProcessor::key(&flowmessage);
+            let side = Side::new(flowmessage);
+            match pending.remove(&key) {
+                Some(mut x) if x.forward == forward => {
+                    x.side.merge(side);
+                    pending.insert(key, x);
+                }
+                Some(x) => biflows.push(BiflowProcessor::stitch(x.side, Some(side))?),
+                None => {
+                    pending.insert(
+                        key,
+                        Pending {
+                            side,
+                            forward,
+                            arrived: now,
+                        },
+                    );
+                }
+            }
+        }
+        Ok(biflows)
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        self.expire(unix_now())
+    }
+
+    fn close(&self) -> Result<Vec<FlowMessage>> {
+        self.expire(u64::MAX)
+    }
+}
+
+impl BiflowProcessor {
+    /// Emits the flows that found no reverse direction within the window.
+    fn expire(&self, now: u64) -> Result<Vec<FlowMessage>> {
+        let mut pending = self.pending.lock().unwrap();
+        let expired = pending
+            .iter()
+            .filter(|(_, x)| x.arrived + self.window <= now)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<Key>>();
+        let mut biflows = Vec::with_capacity(expired.len());
+        for key in expired {
+            let x = pending.remove(&key).unwrap();
+            biflows.push(BiflowProcessor::stitch(x.side, None)?);
+        }
+        Ok(biflows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    /// A v5 flow from `src` to `dst`, started `first` ms into the exporter
+    /// uptime.
+    fn flow(src: (&str, u16), dst: (&str, u16), bytes: u32, flags: u8, first: u32) -> FlowMessage {
+        FlowMessageBuilder::default()
+            .version(5u16)
+            .sys_up_time(10_000u32)
+            .unix_secs(1_600_000_000u32)
+            .first(first)
+            .last(first)
+            .ipv4_src_addr(src.0.parse::<std::net::Ipv4Addr>().unwrap())
+            .src_port(src.1)
+            .ipv4_dst_addr(dst.0.parse::<std::net::Ipv4Addr>().unwrap())
+            .dst_port(dst.1)
+            .protocol(6u8)
+            .tcp_flags(flags)
+            .d0ctets(bytes)
+            .dpkts(1u32)
+            .build()
+            .unwrap()
+    }
+
+    fn initiator(a: FlowMessage, b: FlowMessage) -> FlowMessage {
+        let processor = BiflowProcessor::new(Duration::from_secs(60));
+        assert!(processor.process(vec![a]).unwrap().is_empty());
+        let mut biflows = processor.process(vec![b]).unwrap();
+        assert_eq!(biflows.len(), 1);
+        biflows.remove(0)
+    }
+
+    const CLIENT: (&str, u16) = ("192.0.2.1", 40000);
+    const SERVER: (&str, u16) = ("198.51.100.1", 443);
+
+    #[test]
+    fn initiator_detection() {
+        // The SYN side wins over the earlier start.
+        let biflow = initiator(
+            flow(SERVER, CLIENT, 5000, TCP_SYN | TCP_ACK, 1000),
+            flow(CLIENT, SERVER, 300, TCP_SYN, 2000),
+        );
+        assert_eq!(biflow.uint("src_port"), Some(40000));
+        assert_eq!(biflow.fwd_bytes, Some(300));
+        assert_eq!(biflow.rev_bytes, Some(5000));
+        assert_eq!(biflow.fwd_tcp_flags, Some(TCP_SYN));
+        assert_eq!(biflow.tcp_flags, Some(TCP_SYN | TCP_ACK));
+        // The v5 fields and the aliases carry the same totals.
+        assert_eq!(biflow.uint("bytes"), Some(5300));
+        assert_eq!(biflow.d0ctets, Some(5300));
+        assert_eq!(biflow.uint("packets"), Some(2));
+        assert_eq!(biflow.dpkts, Some(2));
+        assert_eq!(biflow.in_bytes, None);
+
+        // Without SYN flags the earlier start wins.
+        let biflow = initiator(
+            flow(SERVER, CLIENT, 5000, TCP_ACK, 2000),
+            flow(CLIENT, SERVER, 300, TCP_ACK, 1000),
+        );
+        assert_eq!(biflow.uint("src_port"), Some(40000));
+
+        // With equal starts the side sending to the lower port wins.
+        let biflow = initiator(
+            flow(SERVER, CLIENT, 5000, TCP_ACK, 1000),
+            flow(CLIENT, SERVER, 300, TCP_ACK, 1000),
+        );
+        assert_eq!(biflow.uint("src_port"), Some(40000));
+        assert_eq!(biflow.uint("dst_port"), Some(443));
+    }
+
+    #[test]
+    fn unmatched_flows_at_close() {
+        let processor = BiflowProcessor::new(Duration::from_secs(60));
+        let flows = vec![
+            flow(CLIENT, SERVER, 300, TCP_SYN, 1000),
+            flow(CLIENT, SERVER, 200, TCP_ACK, 1500),
+        ];
+        assert!(processor.process(flows).unwrap().is_empty());
+        assert!(processor.flush().unwrap().is_empty());
+
+        let biflows = processor.close().unwrap();
+        assert_eq!(biflows.len(), 1);
+        assert_eq!(biflows[0].uint("src_port"), Some(40000));
+        assert_eq!(biflows[0].fwd_bytes, Some(500));
+        assert_eq!(biflows[0].fwd_packets, Some(2));
+        assert_eq!(biflows[0].rev_bytes, Some(0));
+        assert_eq!(biflows[0].rev_packets, Some(0));
+        assert_eq!(biflows[0].uint("bytes"), Some(500));
+        assert!(processor.close().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use super::super::bmp::Rib;
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::Processor;
+use anyhow::Result;
+
+#[derive(Debug, Clone)]
+pub struct BmpProcessor {
+    rib: Arc<RwLock<Rib>>,
+}
+
+impl BmpProcessor {
+    /// Enriches flows from a RIB maintained by a `BmpServer`.
+    pub fn new(rib: Arc<RwLock<Rib>>) -> BmpProcessor {
+        BmpProcessor { rib }
+    }
+
+    fn enrich(
+        &self,
+        rib: &Rib,
+        flowmessage: &mut FlowMessage,
+        addr: IpAddr,
+        prefix: &str,
+    ) -> Result<()> {
+        let route = match rib.lookup(&addr) {
+            Some((_, route)) => route,
+            None => return Ok(()),
+        };
+        let origin_as = route.origin_as().map(|x| FieldValue::Uint(x as u64));
+        flowmessage.set_field(
+            &format!("{}_as_path", prefix),
+            Some(FieldValue::Str(route.as_path_string())),
+        )?;
+        flowmessage.set_field(
+            &format!("{}_communities", prefix),
+            Some(FieldValue::Str(route.communities_string())),
+        )?;
+        flowmessage.set_field(&format!("{}_origin_as", prefix), origin_as.clone())?;
+        let as_field = format!("{}_as", prefix);
+        if flowmessage.uint(&as_field).unwrap_or(0) == 0 && origin_as.is_some() {
+            flowmessage.set_field(&as_field, origin_as)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for BmpProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "BmpProcessor")
+    }
+}
+
+impl Processor for BmpProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let rib = self.rib.read().unwrap();
+        for flowmessage in flowmessages.iter_mut() {
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("src_addr") {
+                self.enrich(&rib, flowmessage, addr, "src")?;
+            }
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("dst_addr") {
+                self.enrich(&rib, flowmessage, addr, "dst")?;
+            }
+        }
+        Ok(flowmessages)
+    }
+}
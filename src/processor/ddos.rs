@@ -0,0 +1,398 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::event::{Alert, AlertStatus, Event};
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::prefix::Prefix;
+use super::super::util::{parse_number, unix_now};
+use super::Processor;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+
+pub const ATTACKS: [&str; 4] = ["total", "udp_amplification", "syn_flood", "icmp_flood"];
+
+const AMPLIFICATION_PORTS: [u16; 8] = [19, 53, 123, 161, 389, 1900, 11211, 27015];
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Threshold {
+    pub pps: Option<u64>,
+    pub bps: Option<u64>,
+    pub fps: Option<u64>,
+}
+
+impl Threshold {
+    pub fn is_empty(&self) -> bool {
+        self.pps.is_none() && self.bps.is_none() && self.fps.is_none()
+    }
+
+    fn exceeded(&self, rate: &Rate) -> bool {
+        self.pps.is_some_and(|x| rate.pps >= x)
+            || self.bps.is_some_and(|x| rate.bps >= x)
+            || self.fps.is_some_and(|x| rate.fps >= x)
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    /// Parses `pps=100k,bps=1G,fps=5k`; any subset of the metrics may be given.
+    fn from_str(s: &str) -> Result<Threshold> {
+        let mut threshold = Threshold::default();
+        for item in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+        {
+            let (metric, value) = item
+                .split_once('=')
+                .ok_or_else(|| anyhow!("ddos: invalid threshold {}", item))?;
+            let value =
+                parse_number(value).ok_or_else(|| anyhow!("ddos: invalid threshold {}", item))?;
+            match metric {
+                "pps" => threshold.pps = Some(value),
+                "bps" => threshold.bps = Some(value),
+                "fps" => threshold.fps = Some(value),
+                _ => return Err(anyhow!("ddos: unknown threshold metric {}", metric)),
+            }
+        }
+        Ok(threshold)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ThresholdEntry {
+    prefix: Prefix,
+    attack: Option<String>,
+    threshold: Threshold,
+}
+
+/// Thresholds by destination prefix. The most specific prefix wins, and an
+/// entry without an attack type applies to every attack type.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    default: Threshold,
+    entries: Vec<ThresholdEntry>,
+}
+
+impl Thresholds {
+    pub fn new(default: Threshold) -> Thresholds {
+        Thresholds {
+            default,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Loads lines of `PREFIX [ATTACK] pps=.. bps=.. fps=..`, ignoring blank
+    /// lines and `#` comments.
+    pub fn load(&mut self, path: &str) -> Result<()> {
+        let content = fs::read_to_string(path)?;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let prefix = Prefix::from_str(words.next().unwrap_or_default())
+                .map_err(|e| anyhow!("{}:{}: {}", path, i + 1, e))?;
+            let mut rest = words.collect::<Vec<&str>>();
+            let attack = match rest.first() {
+                Some(x) if !x.contains('=') => {
+                    if !ATTACKS.contains(x) {
+                        return Err(anyhow!("{}:{}: unknown attack {}", path, i + 1, x));
+                    }
+                    Some(rest.remove(0).to_string())
+                }
+                _ => None,
+            };
+            let threshold = Threshold::from_str(&rest.join(" "))
+                .map_err(|e| anyhow!("{}:{}: {}", path, i + 1, e))?;
+            self.entries.push(ThresholdEntry {
+                prefix,
+                attack,
+                threshold,
+            });
+        }
+        self.entries
+            .sort_by_key(|x| std::cmp::Reverse(x.prefix.len));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_empty() && self.entries.iter().all(|x| x.threshold.is_empty())
+    }
+
+    fn lookup(&self, addr: &IpAddr, attack: &str) -> (String, Threshold) {
+        self.entries
+            .iter()
+            .find(|x| x.prefix.contains(addr) && x.attack.as_ref().is_none_or(|x| x == attack))
+            .map(|x| (x.prefix.to_string(), x.threshold))
+            .unwrap_or_else(|| ("default".to_string(), self.default))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Rate {
+    pps: u64,
+    bps: u64,
+    fps: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    sec: u64,
+    bytes: u64,
+    packets: u64,
+    flows: u64,
+}
+
+#[derive(Debug)]
+struct Incident {
+    prefix: String,
+    threshold: Threshold,
+    started_at: u64,
+    last_exceeded: u64,
+    last_report: u64,
+    rate: Rate,
+    peak: Rate,
+}
+
+type Target = (IpAddr, &'static str);
+
+#[derive(Debug, Default)]
+struct DdosState {
+    counters: HashMap<Target, VecDeque<Bucket>>,
+    incidents: HashMap<Target, Incident>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DdosProcessor {
+    window: u64,
+    cooldown: u64,
+    thresholds: Thresholds,
+    state: Arc<Mutex<DdosState>>,
+}
+
+impl DdosProcessor {
+    pub fn new(
+        window: Duration,
+        cooldown: Duration,
+        thresholds: Thresholds,
+    ) -> Result<DdosProcessor> {
+        if thresholds.is_empty() {
+            return Err(anyhow!("ddos: no thresholds"));
+        }
+        Ok(DdosProcessor {
+            window: window.as_secs().max(1),
+            cooldown: cooldown.as_secs(),
+            thresholds,
+            state: Arc::new(Mutex::new(DdosState::default())),
+        })
+    }
+
+    fn alert(&self, status: AlertStatus, target: &Target, incident: &Incident) -> Event {
+        Event::Alert(Alert {
+            datetime: Utc::now().to_string(),
+            status,
+            attack: target.1.to_string(),
+            dst_addr: target.0,
+            prefix: incident.prefix.clone(),
+            started_at: incident.started_at,
+            pps: incident.rate.pps,
+            bps: incident.rate.bps,
+            fps: incident.rate.fps,
+            peak_pps: incident.peak.pps,
+            peak_bps: incident.peak.bps,
+            peak_fps: incident.peak.fps,
+            threshold_pps: incident.threshold.pps,
+            threshold_bps: incident.threshold.bps,
+            threshold_fps: incident.threshold.fps,
+        })
+    }
+}
+
+fn attacks(flowmessage: &FlowMessage) -> Vec<&'static str> {
+    let mut attacks = vec![ATTACKS[0]];
+    let protocol = flowmessage.uint("protocol");
+    let src_port = flowmessage.uint("src_port").unwrap_or(0);
+    let tcp_flags = flowmessage.uint("tcp_flags").unwrap_or(0) as u8;
+    match protocol {
+        Some(17) if AMPLIFICATION_PORTS.contains(&(src_port as u16)) => {
+            attacks.push(ATTACKS[1]);
+        }
+        Some(6) if tcp_flags & TCP_SYN != 0 && tcp_flags & TCP_ACK == 0 => {
+            attacks.push(ATTACKS[2]);
+        }
+        Some(1) | Some(58) => {
+            attacks.push(ATTACKS[3]);
+        }
+        _ => {}
+    }
+    attacks
+}
+
+impl Display for DdosProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "DdosProcessor({}s window, {} prefixes)",
+            self.window,
+            self.thresholds.entries.len()
+        )
+    }
+}
+
+impl Processor for DdosProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        let mut state = self.state.lock().unwrap();
+        for flowmessage in flowmessages.iter() {
+            let dst_addr = match flowmessage.field("dst_addr") {
+                Some(FieldValue::Ip(x)) => x,
+                _ => continue,
+            };
+            // Sampled exports carry one in `sampling_rate` packets.
+            let sampling_rate = flowmessage.sampling_rate();
+            let bytes = flowmessage.uint("bytes").unwrap_or(0) * sampling_rate;
+            let packets = flowmessage.uint("packets").unwrap_or(0) * sampling_rate;
+            let flows = flowmessage.uint("flows").unwrap_or(1);
+            for attack in attacks(flowmessage) {
+                let buckets = state.counters.entry((dst_addr, attack)).or_default();
+                match buckets.back_mut() {
+                    Some(bucket) if bucket.sec == now => {
+                        bucket.bytes += bytes;
+                        bucket.packets += packets;
+                        bucket.flows += flows;
+                    }
+                    _ => buckets.push_back(Bucket {
+                        sec: now,
+                        bytes,
+                        packets,
+                        flows,
+                    }),
+                }
+            }
+        }
+        Ok(flowmessages)
+    }
+
+    fn events(&self) -> Result<Vec<Event>> {
+        let now = unix_now();
+        let mut state = self.state.lock().unwrap();
+        let DdosState {
+            counters,
+            incidents,
+        } = &mut *state;
+        let mut events = Vec::new();
+
+        for (target, buckets) in counters.iter_mut() {
+            while buckets.front().is_some_and(|x| x.sec + self.window <= now) {
+                buckets.pop_front();
+            }
+            let rate = Rate {
+                pps: buckets.iter().map(|x| x.packets).sum::<u64>() / self.window,
+                bps: buckets.iter().map(|x| x.bytes).sum::<u64>() * 8 / self.window,
+                fps: buckets.iter().map(|x| x.flows).sum::<u64>() / self.window,
+            };
+            if let Some(incident) = incidents.get_mut(target) {
+                incident.rate = rate;
+            }
+            let (prefix, threshold) = self.thresholds.lookup(&target.0, target.1);
+            if !threshold.exceeded(&rate) {
+                continue;
+            }
+            match incidents.get_mut(target) {
+                Some(incident) => {
+                    incident.last_exceeded = now;
+                    incident.peak.pps = incident.peak.pps.max(rate.pps);
+                    incident.peak.bps = incident.peak.bps.max(rate.bps);
+                    incident.peak.fps = incident.peak.fps.max(rate.fps);
+                    if incident.last_report + self.window <= now {
+                        incident.last_report = now;
+                        events.push(self.alert(AlertStatus::Ongoing, target, incident));
+                    }
+                }
+                None => {
+                    let incident = Incident {
+                        prefix,
+                        threshold,
+                        started_at: now,
+                        last_exceeded: now,
+                        last_report: now,
+                        rate,
+                        peak: rate,
+                    };
+                    events.push(self.alert(AlertStatus::Start, target, &incident));
+                    incidents.insert(*target, incident);
+                }
+            }
+        }
+
+        let ended = incidents
+            .iter()
+            .filter(|(_, x)| x.last_exceeded + self.cooldown <= now && x.last_exceeded < now)
+            .map(|(target, _)| *target)
+            .collect::<Vec<Target>>();
+        for target in ended {
+            let incident = incidents.remove(&target).unwrap();
+            events.push(self.alert(AlertStatus::End, &target, &incident));
+        }
+
+        counters.retain(|target, buckets| !buckets.is_empty() || incidents.contains_key(target));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    fn flow(version: u16, sampling_interval: u32, packets: u64) -> FlowMessage {
+        let mut flowmessage = FlowMessageBuilder::default()
+            .version(version)
+            .sampling_interval(sampling_interval)
+            .build()
+            .unwrap();
+        let dst_addr = FieldValue::Ip("192.0.2.1".parse().unwrap());
+        flowmessage.set_field("dst_addr", Some(dst_addr)).unwrap();
+        flowmessage
+            .set_field("packets", Some(FieldValue::Uint(packets)))
+            .unwrap();
+        flowmessage
+            .set_field("bytes", Some(FieldValue::Uint(packets * 100)))
+            .unwrap();
+        flowmessage
+    }
+
+    fn alerts(flowmessages: Vec<FlowMessage>) -> usize {
+        let threshold = Threshold::from_str("pps=100").unwrap();
+        let processor = DdosProcessor::new(
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            Thresholds::new(threshold),
+        )
+        .unwrap();
+        processor.process(flowmessages).unwrap();
+        processor.events().unwrap().len()
+    }
+
+    #[test]
+    fn sampled_flows_are_scaled() {
+        assert_eq!(alerts(vec![flow(9, 1, 500)]), 0);
+        assert_eq!(alerts(vec![flow(9, 100, 500)]), 1);
+        // Sampling mode 01 in the top bits of the v5 header field.
+        assert_eq!(alerts(vec![flow(5, 0x4000 | 100, 500)]), 1);
+        assert_eq!(alerts(vec![flow(5, 0x4000, 500)]), 0);
+    }
+}
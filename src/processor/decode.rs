@@ -0,0 +1,161 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use super::super::decode::{
+    icmp_type_code, mac_string, protocol_name, tcp_flags_name, Services, PROTOCOL_ICMP,
+    PROTOCOL_ICMPV6,
+};
+use super::super::flowmessage::FlowMessage;
+use super::Processor;
+use anyhow::Result;
+
+#[derive(Debug, Clone)]
+pub struct DecodeProcessor {
+    services: Arc<Services>,
+}
+
+impl DecodeProcessor {
+    /// Adds readable companions of the numeric fields: `protocol_name`,
+    /// `src_service`, `dst_service`, `tcp_flags_name`, `icmp_msg_type`,
+    /// `icmp_msg_code`, `src_mac_addr` and `dst_mac_addr`.
+    pub fn new(services: Services) -> DecodeProcessor {
+        DecodeProcessor {
+            services: Arc::new(services),
+        }
+    }
+
+    fn decode(&self, flowmessage: &mut FlowMessage) {
+        let protocol = flowmessage.protocol;
+        if let Some(protocol) = protocol {
+            flowmessage.protocol_name = Some(protocol_name(protocol));
+            flowmessage.src_service = flowmessage
+                .src_port
+                .and_then(|x| self.services.get(protocol, x))
+                .cloned();
+            flowmessage.dst_service = flowmessage
+                .dst_port
+                .and_then(|x| self.services.get(protocol, x))
+                .cloned();
+        }
+        if let Some(tcp_flags) = flowmessage.tcp_flags {
+            flowmessage.tcp_flags_name = Some(tcp_flags_name(tcp_flags));
+        }
+        // NetFlow v5 has no ICMP type field, so exporters put it in the destination port.
+        let icmp_type = match protocol {
+            Some(PROTOCOL_ICMP) | Some(PROTOCOL_ICMPV6) => {
+                flowmessage.icmp_type.or(flowmessage.dst_port)
+            }
+            _ => flowmessage.icmp_type,
+        };
+        if let Some(icmp_type) = icmp_type {
+            let (icmp_msg_type, icmp_msg_code) = icmp_type_code(icmp_type);
+            flowmessage.icmp_msg_type = Some(icmp_msg_type);
+            flowmessage.icmp_msg_code = Some(icmp_msg_code);
+        }
+        flowmessage.src_mac_addr = flowmessage.src_mac.map(mac_string);
+        flowmessage.dst_mac_addr = flowmessage.dst_mac.map(mac_string);
+    }
+}
+
+impl Display for DecodeProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DecodeProcessor({} services)", self.services.len())
+    }
+}
+
+impl Processor for DecodeProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        for flowmessage in flowmessages.iter_mut() {
+            self.decode(flowmessage);
+        }
+        Ok(flowmessages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    #[test]
+    fn companions() {
+        let processor = DecodeProcessor::new(Services::new());
+        let flowmessages = vec![
+            FlowMessageBuilder::default()
+                .protocol(6u8)
+                .src_port(40000u16)
+                .dst_port(443u16)
+                .tcp_flags(0x12u8)
+                .src_mac(0x0011_2233_4455u64)
+                .build()
+                .unwrap(),
+            // NetFlow v5 carries ICMP type 3, code 1 in the destination port.
+            FlowMessageBuilder::default()
+                .protocol(PROTOCOL_ICMP)
+                .dst_port(0x0301u16)
+                .build()
+                .unwrap(),
+            FlowMessageBuilder::default()
+                .protocol(PROTOCOL_ICMPV6)
+                .icmp_type(0x8000u16)
+                .build()
+                .unwrap(),
+            FlowMessageBuilder::default()
+                .protocol(250u8)
+                .build()
+                .unwrap(),
+        ];
+        let flowmessages = processor.process(flowmessages).unwrap();
+
+        let tcp = &flowmessages[0];
+        assert_eq!(tcp.protocol_name.as_deref(), Some("TCP"));
+        assert_eq!(tcp.src_service, None);
+        assert_eq!(tcp.dst_service.as_deref(), Some("https"));
+        assert_eq!(tcp.tcp_flags_name.as_deref(), Some("SYN,ACK"));
+        assert_eq!(tcp.icmp_msg_type, None);
+        assert_eq!(tcp.src_mac_addr.as_deref(), Some("00:11:22:33:44:55"));
+        assert_eq!(tcp.dst_mac_addr, None);
+
+        let icmp = &flowmessages[1];
+        assert_eq!(icmp.protocol_name.as_deref(), Some("ICMP"));
+        assert_eq!((icmp.icmp_msg_type, icmp.icmp_msg_code), (Some(3), Some(1)));
+        assert_eq!(icmp.dst_service, None);
+
+        let icmpv6 = &flowmessages[2];
+        assert_eq!(icmpv6.protocol_name.as_deref(), Some("IPv6-ICMP"));
+        assert_eq!(
+            (icmpv6.icmp_msg_type, icmpv6.icmp_msg_code),
+            (Some(128), Some(0))
+        );
+
+        assert_eq!(flowmessages[3].protocol_name.as_deref(), Some("250"));
+    }
+
+    #[test]
+    fn iana_services() {
+        let path =
+            std::env::temp_dir().join(format!("ferrisflow-services-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "Service Name,Port Number,Transport Protocol,Description\n\
+             https,443,tcp,http protocol over TLS/SSL\n\
+             https,443,udp,http protocol over TLS/SSL\n\
+             x11,6000-6063,tcp,X Window System\n\
+             ,6000,udp,Reserved\n\
+             other-https,443,tcp,Duplicate\n",
+        )
+        .unwrap();
+        let services = Services::load(&path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(services.len(), 66);
+        assert_eq!(services.get(6, 443).map(|x| x.as_str()), Some("https"));
+        assert_eq!(services.get(6, 6010).map(|x| x.as_str()), Some("x11"));
+        assert_eq!(services.get(17, 6000), None);
+        // The built-in names are replaced.
+        assert_eq!(services.get(6, 22), None);
+    }
+}
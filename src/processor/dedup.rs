@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::prefix::Prefix;
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::{anyhow, Result};
+
+type Key = Vec<Option<FieldValue>>;
+
+const KEY_FIELDS: [&str; 5] = ["src_addr", "dst_addr", "src_port", "dst_port", "protocol"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    Drop,
+    Mark,
+}
+
+impl FromStr for DedupMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DedupMode> {
+        match s {
+            "drop" => Ok(DedupMode::Drop),
+            "mark" => Ok(DedupMode::Mark),
+            _ => Err(anyhow!("dedup: invalid mode {}", s)),
+        }
+    }
+}
+
+impl Display for DedupMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DedupMode::Drop => write!(f, "drop"),
+            DedupMode::Mark => write!(f, "mark"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    arrived: u64,
+    flowmessages: Vec<FlowMessage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupProcessor {
+    window: u64,
+    mode: DedupMode,
+    prefer: Vec<Prefix>,
+    groups: Arc<Mutex<HashMap<Key, Group>>>,
+}
+
+impl DedupProcessor {
+    /// Groups flows with the same 5-tuple seen within `window` and keeps
+    /// those of one exporter: the first match in `prefer`, such as the
+    /// ingress edge routers, or else the exporter that reported first.
+    /// Flows from other exporters are dropped or get `duplicate_of`.
+    pub fn new(window: Duration, mode: DedupMode, prefer: Vec<Prefix>) -> DedupProcessor {
+        DedupProcessor {
+            window: window.as_secs().max(1),
+            mode,
+            prefer,
+            groups: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn rank(&self, exporter: &Option<IpAddr>) -> usize {
+        exporter
+            .and_then(|x| self.prefer.iter().position(|prefix| prefix.contains(&x)))
+            .unwrap_or(self.prefer.len())
+    }
+
+    fn resolve(&self, group: Group) -> Vec<FlowMessage> {
+        let exporters = group
+            .flowmessages
+            .iter()
+            .map(|x| x.field("exporter").and_then(|x| x.to_ip().ok()))
+            .collect::<Vec<Option<IpAddr>>>();
+        let primary = exporters
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, x)| (self.rank(x), *i))
+            .and_then(|(_, x)| *x);
+        let mut flowmessages = Vec::with_capacity(group.flowmessages.len());
+        for (mut flowmessage, exporter) in group.flowmessages.into_iter().zip(exporters) {
+            if exporter == primary {
+                flowmessages.push(flowmessage);
+            } else if self.mode == DedupMode::Mark {
+                flowmessage.duplicate_of = primary;
+                flowmessages.push(flowmessage);
+            }
+        }
+        flowmessages
+    }
+}
+
+impl Display for DedupProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefer = self
+            .prefer
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        write!(
+            f,
+            "DedupProcessor({}s window, {}, prefer: [{}])",
+            self.window,
+            self.mode,
+            prefer.join(", ")
+        )
+    }
+}
+
+impl Processor for DedupProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        let mut groups = self.groups.lock().unwrap();
+        for flowmessage in flowmessages {
+            let key = KEY_FIELDS
+                .iter()
+                .map(|x| flowmessage.field(x))
+                .collect::<Key>();
+            groups
+                .entry(key)
+                .or_insert_with(|| Group {
+                    arrived: now,
+                    flowmessages: Vec::new(),
+                })
+                .flowmessages
+                .push(flowmessage);
+        }
+        Ok(Vec::new())
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        self.expire(unix_now())
+    }
+
+    fn close(&self) -> Result<Vec<FlowMessage>> {
+        self.expire(u64::MAX)
+    }
+}
+
+impl DedupProcessor {
+    /// Resolves the groups whose window has passed.
+    fn expire(&self, now: u64) -> Result<Vec<FlowMessage>> {
+        let mut groups = self.groups.lock().unwrap();
+        let expired = groups
+            .iter()
+            .filter(|(_, x)| x.arrived + self.window <= now)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<Key>>();
+        let mut flowmessages = Vec::new();
+        for key in expired {
+            let group = groups.remove(&key).unwrap();
+            flowmessages.extend(self.resolve(group));
+        }
+        Ok(flowmessages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    fn flow(exporter: &str, bytes: u32) -> FlowMessage {
+        FlowMessageBuilder::default()
+            .version(5u16)
+            .exporter_addr(
+                format!("{}:2055", exporter)
+                    .parse::<std::net::SocketAddr>()
+                    .unwrap(),
+            )
+            .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .src_port(40000u16)
+            .ipv4_dst_addr("198.51.100.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .dst_port(443u16)
+            .protocol(6u8)
+            .d0ctets(bytes)
+            .build()
+            .unwrap()
+    }
+
+    fn exporter(flowmessage: &FlowMessage) -> Option<IpAddr> {
+        flowmessage.exporter_addr.map(|x| x.ip())
+    }
+
+    #[test]
+    fn preferred_exporter_wins() {
+        let prefer = vec![Prefix::from_str("10.0.0.0/8").unwrap()];
+        let processor = DedupProcessor::new(Duration::from_secs(5), DedupMode::Drop, prefer);
+        processor.process(vec![flow("192.0.2.254", 100)]).unwrap();
+        processor.process(vec![flow("10.0.0.1", 200)]).unwrap();
+        assert!(processor.flush().unwrap().is_empty());
+
+        let flowmessages = processor.close().unwrap();
+        assert_eq!(flowmessages.len(), 1);
+        assert_eq!(exporter(&flowmessages[0]), "10.0.0.1".parse().ok());
+        assert_eq!(flowmessages[0].d0ctets, Some(200));
+
+        // Without a preference the first exporter wins.
+        let processor = DedupProcessor::new(Duration::from_secs(5), DedupMode::Drop, Vec::new());
+        processor
+            .process(vec![flow("192.0.2.254", 100), flow("10.0.0.1", 200)])
+            .unwrap();
+        let flowmessages = processor.close().unwrap();
+        assert_eq!(flowmessages.len(), 1);
+        assert_eq!(exporter(&flowmessages[0]), "192.0.2.254".parse().ok());
+    }
+
+    #[test]
+    fn mark_mode() {
+        let prefer = vec![Prefix::from_str("10.0.0.1").unwrap()];
+        let processor = DedupProcessor::new(Duration::from_secs(5), DedupMode::Mark, prefer);
+        processor
+            .process(vec![
+                flow("192.0.2.254", 100),
+                flow("10.0.0.1", 200),
+                flow("10.0.0.1", 300),
+            ])
+            .unwrap();
+
+        let flowmessages = processor.close().unwrap();
+        assert_eq!(flowmessages.len(), 3);
+        for flowmessage in &flowmessages {
+            let primary = exporter(flowmessage) == "10.0.0.1".parse().ok();
+            let duplicate_of = if primary {
+                None
+            } else {
+                "10.0.0.1".parse().ok()
+            };
+            assert_eq!(flowmessage.duplicate_of, duplicate_of);
+        }
+        assert_eq!(
+            flowmessages[0].field("duplicate_of"),
+            Some(FieldValue::Ip("10.0.0.1".parse().unwrap()))
+        );
+    }
+}
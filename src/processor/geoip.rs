@@ -0,0 +1,238 @@
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::{anyhow, Result};
+use maxminddb::{geoip2, Reader};
+
+#[derive(Debug)]
+struct Database {
+    path: String,
+    modified: Option<SystemTime>,
+    reader: Reader<Vec<u8>>,
+}
+
+impl Database {
+    fn open(path: &str) -> Result<Database> {
+        let modified = fs::metadata(path)
+            .map_err(|e| anyhow!("{}: {}", path, e))?
+            .modified()
+            .ok();
+        let reader = Reader::open_readfile(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        Ok(Database {
+            path: path.to_string(),
+            modified,
+            reader,
+        })
+    }
+
+    fn is_changed(&self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        modified.is_some() && modified != self.modified
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoIpProcessor {
+    city: Option<Arc<RwLock<Database>>>,
+    asn: Option<Arc<RwLock<Database>>>,
+    reload_interval: u64,
+    last_check: Arc<Mutex<u64>>,
+}
+
+impl GeoIpProcessor {
+    /// `city` accepts a GeoIP2/GeoLite2 City or Country database and `asn` an
+    /// ASN database; the files are reopened when their modification time changes.
+    pub fn new(
+        city: Option<&str>,
+        asn: Option<&str>,
+        reload_interval: Duration,
+    ) -> Result<GeoIpProcessor> {
+        if city.is_none() && asn.is_none() {
+            return Err(anyhow!("geoip: no database"));
+        }
+        let city = city
+            .map(|x| Database::open(x).map(|x| Arc::new(RwLock::new(x))))
+            .transpose()?;
+        let asn = asn
+            .map(|x| Database::open(x).map(|x| Arc::new(RwLock::new(x))))
+            .transpose()?;
+        Ok(GeoIpProcessor {
+            city,
+            asn,
+            reload_interval: reload_interval.as_secs(),
+            last_check: Arc::new(Mutex::new(unix_now())),
+        })
+    }
+
+    fn enrich(&self, flowmessage: &mut FlowMessage, addr: IpAddr, prefix: &str) -> Result<()> {
+        if let Some(city) = &self.city {
+            let database = city.read().unwrap();
+            if let Ok(record) = database.reader.lookup::<geoip2::City>(addr) {
+                let country = record
+                    .country
+                    .and_then(|x| x.iso_code)
+                    .map(|x| FieldValue::Str(x.to_string()));
+                let city = record
+                    .city
+                    .and_then(|x| x.names)
+                    .and_then(|x| x.get("en").map(|x| FieldValue::Str(x.to_string())));
+                flowmessage.set_field(&format!("{}_country", prefix), country)?;
+                flowmessage.set_field(&format!("{}_city", prefix), city)?;
+            }
+        }
+        if let Some(asn) = &self.asn {
+            let database = asn.read().unwrap();
+            if let Ok(record) = database.reader.lookup::<geoip2::Asn>(addr) {
+                let org = record
+                    .autonomous_system_organization
+                    .map(|x| FieldValue::Str(x.to_string()));
+                flowmessage.set_field(&format!("{}_asn_org", prefix), org)?;
+                let as_field = format!("{}_as", prefix);
+                if flowmessage.uint(&as_field).unwrap_or(0) == 0 {
+                    if let Some(number) = record.autonomous_system_number {
+                        flowmessage.set_field(&as_field, Some(FieldValue::Uint(number as u64)))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn reload(database: &Arc<RwLock<Database>>) -> Result<()> {
+    let path = {
+        let database = database.read().unwrap();
+        if !database.is_changed() {
+            return Ok(());
+        }
+        database.path.clone()
+    };
+    let reloaded = Database::open(&path)?;
+    *database.write().unwrap() = reloaded;
+    eprintln!("geoip: reloaded {}", path);
+    Ok(())
+}
+
+impl Display for GeoIpProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let paths = self
+            .city
+            .iter()
+            .chain(self.asn.iter())
+            .map(|x| x.read().unwrap().path.clone())
+            .collect::<Vec<String>>();
+        write!(f, "GeoIpProcessor({})", paths.join(", "))
+    }
+}
+
+impl Processor for GeoIpProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        for flowmessage in flowmessages.iter_mut() {
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("src_addr") {
+                self.enrich(flowmessage, addr, "src")?;
+            }
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("dst_addr") {
+                self.enrich(flowmessage, addr, "dst")?;
+            }
+        }
+        Ok(flowmessages)
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        {
+            let mut last_check = self.last_check.lock().unwrap();
+            if *last_check + self.reload_interval > now {
+                return Ok(Vec::new());
+            }
+            *last_check = now;
+        }
+        for database in self.city.iter().chain(self.asn.iter()) {
+            reload(database)?;
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    /// Has City and ASN records for 192.0.2.0/24 and only a country for
+    /// 198.51.100.0/24, so it serves as both databases.
+    const DATABASE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geoip.mmdb");
+
+    fn flow(src: &str, dst: &str, src_as: u32) -> FlowMessage {
+        FlowMessageBuilder::default()
+            .ipv4_src_addr(src.parse::<std::net::Ipv4Addr>().unwrap())
+            .ipv4_dst_addr(dst.parse::<std::net::Ipv4Addr>().unwrap())
+            .src_as(src_as)
+            .build()
+            .unwrap()
+    }
+
+    fn str_field(flowmessage: &FlowMessage, name: &str) -> Option<String> {
+        flowmessage.field(name).map(|x| x.to_string())
+    }
+
+    #[test]
+    fn lookups() {
+        let processor =
+            GeoIpProcessor::new(Some(DATABASE), Some(DATABASE), Duration::from_secs(60)).unwrap();
+        let flowmessages = processor
+            .process(vec![
+                flow("192.0.2.1", "198.51.100.1", 0),
+                flow("203.0.113.1", "192.0.2.200", 0),
+                flow("192.0.2.1", "203.0.113.1", 64511),
+            ])
+            .unwrap();
+
+        let flowmessage = &flowmessages[0];
+        assert_eq!(str_field(flowmessage, "src_country").as_deref(), Some("NL"));
+        assert_eq!(
+            str_field(flowmessage, "src_city").as_deref(),
+            Some("Amsterdam")
+        );
+        assert_eq!(flowmessage.uint("src_as"), Some(64500));
+        assert_eq!(
+            str_field(flowmessage, "src_asn_org").as_deref(),
+            Some("Example Networks")
+        );
+        assert_eq!(str_field(flowmessage, "dst_country").as_deref(), Some("JP"));
+        assert_eq!(str_field(flowmessage, "dst_city"), None);
+        assert_eq!(str_field(flowmessage, "dst_asn_org"), None);
+
+        // Addresses without a record are left alone.
+        let flowmessage = &flowmessages[1];
+        assert_eq!(str_field(flowmessage, "src_country"), None);
+        assert_eq!(flowmessage.uint("src_as"), Some(0));
+        assert_eq!(str_field(flowmessage, "dst_country").as_deref(), Some("NL"));
+        assert_eq!(flowmessage.uint("dst_as"), Some(64500));
+
+        // An AS number from the exporter is kept.
+        assert_eq!(flowmessages[2].uint("src_as"), Some(64511));
+        assert_eq!(
+            str_field(&flowmessages[2], "src_asn_org").as_deref(),
+            Some("Example Networks")
+        );
+    }
+
+    #[test]
+    fn missing_database() {
+        assert!(GeoIpProcessor::new(None, None, Duration::from_secs(60)).is_err());
+        assert!(
+            GeoIpProcessor::new(Some("/nonexistent.mmdb"), None, Duration::from_secs(60)).is_err()
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::util::{parse_number, unix_now};
+use super::Processor;
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Interface {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub speed: Option<u64>,
+}
+
+/// Interfaces by exporter address and ifIndex; `None` matches any exporter.
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceTable {
+    path: String,
+    modified: Option<SystemTime>,
+    interfaces: HashMap<(Option<IpAddr>, u64), Interface>,
+}
+
+impl InterfaceTable {
+    /// Loads CSV with a header row of `exporter`, `ifindex`, `name`, `speed`
+    /// and `description` columns, where only `ifindex` is required. An empty
+    /// or `*` exporter applies to every exporter, and the speed is in bits per
+    /// second with an optional `K`, `M`, `G` or `T` suffix.
+    pub fn load(path: &str) -> Result<InterfaceTable> {
+        let modified = fs::metadata(path)
+            .map_err(|e| anyhow!("{}: {}", path, e))?
+            .modified()
+            .ok();
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|x| x == name);
+        let exporter_index = column("exporter");
+        let ifindex_index =
+            column("ifindex").ok_or_else(|| anyhow!("{}: no ifindex column", path))?;
+        let name_index = column("name");
+        let speed_index = column("speed");
+        let description_index = column("description");
+
+        let mut interfaces = HashMap::new();
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            let line = i + 2;
+            let get = |index: Option<usize>| {
+                index
+                    .and_then(|x| record.get(x))
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+            };
+            let exporter = match get(exporter_index).as_deref() {
+                None | Some("*") => None,
+                Some(x) => {
+                    Some(IpAddr::from_str(x).map_err(|e| anyhow!("{}:{}: {}", path, line, e))?)
+                }
+            };
+            let ifindex = get(Some(ifindex_index))
+                .and_then(|x| x.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("{}:{}: invalid ifindex", path, line))?;
+            let speed = match get(speed_index) {
+                Some(x) => Some(
+                    parse_number(&x)
+                        .ok_or_else(|| anyhow!("{}:{}: invalid speed {}", path, line, x))?,
+                ),
+                None => None,
+            };
+            interfaces.insert(
+                (exporter, ifindex),
+                Interface {
+                    name: get(name_index),
+                    description: get(description_index),
+                    speed,
+                },
+            );
+        }
+        Ok(InterfaceTable {
+            path: path.to_string(),
+            modified,
+            interfaces,
+        })
+    }
+
+    fn is_changed(&self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        modified.is_some() && modified != self.modified
+    }
+
+    pub fn get(&self, exporter: Option<IpAddr>, ifindex: u64) -> Option<&Interface> {
+        self.interfaces
+            .get(&(exporter, ifindex))
+            .or_else(|| self.interfaces.get(&(None, ifindex)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceProcessor {
+    table: Arc<RwLock<InterfaceTable>>,
+    reload_interval: u64,
+    last_check: Arc<Mutex<u64>>,
+}
+
+impl InterfaceProcessor {
+    /// Sets `in_if_*` from `input`/`input_snmp` and `out_if_*` from
+    /// `output`/`output_snmp`; the file is reloaded when it changes.
+    pub fn new(path: &str, reload_interval: Duration) -> Result<InterfaceProcessor> {
+        Ok(InterfaceProcessor {
+            table: Arc::new(RwLock::new(InterfaceTable::load(path)?)),
+            reload_interval: reload_interval.as_secs(),
+            last_check: Arc::new(Mutex::new(unix_now())),
+        })
+    }
+
+    fn enrich(
+        &self,
+        table: &InterfaceTable,
+        flowmessage: &mut FlowMessage,
+        exporter: Option<IpAddr>,
+        ifindex: u64,
+        prefix: &str,
+    ) -> Result<()> {
+        let interface = match table.get(exporter, ifindex) {
+            Some(interface) => interface,
+            None => return Ok(()),
+        };
+        flowmessage.set_field(
+            &format!("{}_if_name", prefix),
+            interface.name.clone().map(FieldValue::Str),
+        )?;
+        flowmessage.set_field(
+            &format!("{}_if_description", prefix),
+            interface.description.clone().map(FieldValue::Str),
+        )?;
+        flowmessage.set_field(
+            &format!("{}_if_speed", prefix),
+            interface.speed.map(FieldValue::Uint),
+        )?;
+        Ok(())
+    }
+}
+
+impl Display for InterfaceProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let table = self.table.read().unwrap();
+        write!(
+            f,
+            "InterfaceProcessor({}: {} interfaces)",
+            table.path,
+            table.interfaces.len()
+        )
+    }
+}
+
+impl Processor for InterfaceProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let table = self.table.read().unwrap();
+        for flowmessage in flowmessages.iter_mut() {
+            let exporter = match flowmessage.field("exporter") {
+                Some(FieldValue::Ip(x)) => Some(x),
+                _ => None,
+            };
+            let input = flowmessage
+                .uint("input")
+                .or_else(|| flowmessage.uint("input_snmp"));
+            let output = flowmessage
+                .uint("output")
+                .or_else(|| flowmessage.uint("output_snmp"));
+            if let Some(input) = input {
+                self.enrich(&table, flowmessage, exporter, input, "in")?;
+            }
+            if let Some(output) = output {
+                self.enrich(&table, flowmessage, exporter, output, "out")?;
+            }
+        }
+        Ok(flowmessages)
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        {
+            let mut last_check = self.last_check.lock().unwrap();
+            if *last_check + self.reload_interval > now {
+                return Ok(Vec::new());
+            }
+            *last_check = now;
+        }
+        let path = {
+            let table = self.table.read().unwrap();
+            if !table.is_changed() {
+                return Ok(Vec::new());
+            }
+            table.path.clone()
+        };
+        let table = InterfaceTable::load(&path)?;
+        *self.table.write().unwrap() = table;
+        eprintln!("interface: reloaded {}", path);
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+    use std::fs::File;
+
+    fn in_if_name(processor: &InterfaceProcessor) -> Option<FieldValue> {
+        let mut flowmessage = FlowMessageBuilder::default().build().unwrap();
+        flowmessage
+            .set_field("input_snmp", Some(FieldValue::Uint(1)))
+            .unwrap();
+        processor
+            .process(vec![flowmessage])
+            .unwrap()
+            .remove(0)
+            .field("in_if_name")
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = std::env::temp_dir().join("ferrisflow-interfaces.csv");
+        fs::write(&path, "ifindex,name\n1,xe-0/0/0\n").unwrap();
+        let processor = InterfaceProcessor::new(path.to_str().unwrap(), Duration::ZERO).unwrap();
+        assert_eq!(
+            in_if_name(&processor),
+            Some(FieldValue::Str("xe-0/0/0".to_string()))
+        );
+
+        // Files whose modification time is unchanged are not reloaded.
+        let modified = processor.table.read().unwrap().modified.unwrap();
+        fs::write(&path, "ifindex,name\n1,xe-0/0/1\n").unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        processor.flush().unwrap();
+        assert_eq!(
+            in_if_name(&processor),
+            Some(FieldValue::Str("xe-0/0/0".to_string()))
+        );
+
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        processor.flush().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            in_if_name(&processor),
+            Some(FieldValue::Str("xe-0/0/1".to_string()))
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::Display;
+
+use super::event::Event;
+use super::flowmessage::FlowMessage;
+use anyhow::Result;
+
+pub mod aggregate;
+pub use aggregate::AggregateProcessor;
+
+pub mod anonymize;
+pub use anonymize::AnonymizeProcessor;
+
+pub mod biflow;
+pub use biflow::BiflowProcessor;
+
+pub mod bmp;
+pub use bmp::BmpProcessor;
+
+pub mod ddos;
+pub use ddos::DdosProcessor;
+
+pub mod decode;
+pub use decode::DecodeProcessor;
+
+pub mod dedup;
+pub use dedup::DedupProcessor;
+
+pub mod geoip;
+pub use geoip::GeoIpProcessor;
+
+pub mod interface;
+pub use interface::InterfaceProcessor;
+
+pub mod prefix_table;
+pub use prefix_table::PrefixTableProcessor;
+
+pub mod topn;
+pub use topn::TopNProcessor;
+
+pub trait Processor: Send + Display {
+    fn box_clone(&self) -> Box<dyn Processor>;
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>>;
+
+    /// Called periodically by the server so stateful processors can emit
+    /// records without waiting for the next packet.
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        Ok(Vec::new())
+    }
+
+    /// Called once when the input ends, e.g. on shutdown or at the end of a
+    /// replay, to emit everything still held regardless of its window.
+    fn close(&self) -> Result<Vec<FlowMessage>> {
+        self.flush()
+    }
+
+    /// Called periodically by the server to collect derived events, which are
+    /// sent to the publishers without passing through later processors.
+    fn events(&self) -> Result<Vec<Event>> {
+        Ok(Vec::new())
+    }
+}
+
+impl Clone for Box<dyn Processor> {
+    fn clone(&self) -> Box<dyn Processor> {
+        self.box_clone()
+    }
+}
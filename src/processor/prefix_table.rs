@@ -0,0 +1,323 @@
+use std::fmt::Display;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use super::super::bgp::{read_prefix, PathAttributes, AFI_IPV4, AFI_IPV6};
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::prefix::{Prefix, PrefixMap};
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::GzDecoder;
+
+const MRT_TABLE_DUMP: u16 = 12;
+const MRT_TABLE_DUMP_V2: u16 = 13;
+
+const TABLE_DUMP_AFI_IPV4: u16 = 1;
+const TABLE_DUMP_AFI_IPV6: u16 = 2;
+
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+
+/// Origin ASNs and user tags by prefix, loaded from CSV files or MRT RIB dumps.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixTable {
+    paths: Vec<(String, Option<SystemTime>)>,
+    asns: PrefixMap<u32>,
+    tags: PrefixMap<String>,
+}
+
+impl PrefixTable {
+    pub fn load(paths: &[String]) -> Result<PrefixTable> {
+        let mut table = PrefixTable::default();
+        for path in paths {
+            let modified = fs::metadata(path)
+                .map_err(|e| anyhow!("{}: {}", path, e))?
+                .modified()
+                .ok();
+            let mut buf = fs::read(path)?;
+            if buf.starts_with(&[0x1f, 0x8b]) {
+                let mut decoded = Vec::new();
+                GzDecoder::new(buf.as_slice())
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| anyhow!("{}: {}", path, e))?;
+                buf = decoded;
+            }
+            let mrt_type = buf.get(4..6).map(|x| u16::from_be_bytes([x[0], x[1]]));
+            match mrt_type {
+                Some(MRT_TABLE_DUMP) | Some(MRT_TABLE_DUMP_V2) => {
+                    table.load_mrt(&buf).map(|skipped| {
+                        if skipped > 0 {
+                            eprintln!("{}: skipped {} invalid MRT records", path, skipped);
+                        }
+                    })
+                }
+                _ => table.load_csv(&buf),
+            }
+            .map_err(|e| anyhow!("{}: {}", path, e))?;
+            table.paths.push((path.clone(), modified));
+        }
+        Ok(table)
+    }
+
+    /// Loads CSV with a header row: a `prefix` column, an optional `asn`
+    /// column, and any other columns as tags named after their header.
+    fn load_csv(&mut self, buf: &[u8]) -> Result<()> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(buf);
+        let headers = rdr.headers()?.clone();
+        let prefix_index = headers
+            .iter()
+            .position(|x| x == "prefix")
+            .ok_or_else(|| anyhow!("no prefix column"))?;
+        let asn_index = headers.iter().position(|x| x == "asn");
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            let line = i + 2;
+            let prefix = Prefix::from_str(record.get(prefix_index).unwrap_or_default())
+                .map_err(|e| anyhow!("line {}: {}", line, e))?;
+            if let Some(asn) = asn_index.and_then(|x| record.get(x)) {
+                if !asn.is_empty() {
+                    let asn = asn
+                        .trim_start_matches("AS")
+                        .parse::<u32>()
+                        .map_err(|_| anyhow!("line {}: invalid asn {}", line, asn))?;
+                    self.asns.insert(prefix, asn);
+                }
+            }
+            let tags = headers
+                .iter()
+                .zip(record.iter())
+                .enumerate()
+                .filter(|(i, (_, v))| *i != prefix_index && Some(*i) != asn_index && !v.is_empty())
+                .map(|(_, (k, v))| format!("{}={}", k, v))
+                .collect::<Vec<String>>();
+            if !tags.is_empty() {
+                self.tags.insert(prefix, tags.join(";"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the origin AS of each prefix from an MRT TABLE_DUMP or
+    /// TABLE_DUMP_V2 RIB dump (RFC 6396), using the first entry of each prefix.
+    /// Records that fail to parse are skipped and counted.
+    fn load_mrt(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut skipped = 0;
+        let mut rdr = Cursor::new(buf);
+        while (rdr.position() as usize) < buf.len() {
+            let _timestamp = rdr.read_u32::<BigEndian>()?;
+            let type_ = rdr.read_u16::<BigEndian>()?;
+            let subtype = rdr.read_u16::<BigEndian>()?;
+            let length = rdr.read_u32::<BigEndian>()? as usize;
+            let position = rdr.position() as usize;
+            let message = buf
+                .get(position..position + length)
+                .ok_or_else(|| anyhow!("truncated MRT record"))?;
+            rdr.set_position((position + length) as u64);
+            let entry = match (type_, subtype) {
+                (MRT_TABLE_DUMP_V2, RIB_IPV4_UNICAST) => rib_entry(message, AFI_IPV4),
+                (MRT_TABLE_DUMP_V2, RIB_IPV6_UNICAST) => rib_entry(message, AFI_IPV6),
+                (MRT_TABLE_DUMP, TABLE_DUMP_AFI_IPV4) => table_dump_entry(message, AFI_IPV4),
+                (MRT_TABLE_DUMP, TABLE_DUMP_AFI_IPV6) => table_dump_entry(message, AFI_IPV6),
+                _ => Ok(None),
+            };
+            match entry {
+                Ok(Some((prefix, asn))) => {
+                    if self.asns.get(&prefix).is_none() {
+                        self.asns.insert(prefix, asn);
+                    }
+                }
+                Ok(None) => {}
+                Err(_) => skipped += 1,
+            }
+        }
+        Ok(skipped)
+    }
+
+    fn is_changed(&self) -> bool {
+        self.paths.iter().any(|(path, modified)| {
+            let current = fs::metadata(path).and_then(|x| x.modified()).ok();
+            current.is_some() && current != *modified
+        })
+    }
+
+    pub fn asn(&self, addr: &IpAddr) -> Option<u32> {
+        self.asns.lookup(addr).map(|(_, asn)| *asn)
+    }
+
+    pub fn tags(&self, addr: &IpAddr) -> Option<String> {
+        self.tags.lookup(addr).map(|(_, tags)| tags.clone())
+    }
+}
+
+fn rib_entry(message: &[u8], afi: u16) -> Result<Option<(Prefix, u32)>> {
+    let mut rdr = Cursor::new(message);
+    let _sequence = rdr.read_u32::<BigEndian>()?;
+    let len = rdr.read_u8()?;
+    let prefix = read_prefix(&mut rdr, afi, len)?;
+    let entry_count = rdr.read_u16::<BigEndian>()?;
+    for _ in 0..entry_count {
+        let _peer_index = rdr.read_u16::<BigEndian>()?;
+        let _originated_time = rdr.read_u32::<BigEndian>()?;
+        let attribute_length = rdr.read_u16::<BigEndian>()? as usize;
+        let mut attributes = vec![0u8; attribute_length];
+        rdr.read_exact(&mut attributes)?;
+        if let Some(asn) = PathAttributes::parse_mrt(&attributes)?.origin_as() {
+            return Ok(Some((prefix, asn)));
+        }
+    }
+    Ok(None)
+}
+
+fn table_dump_entry(message: &[u8], afi: u16) -> Result<Option<(Prefix, u32)>> {
+    let mut rdr = Cursor::new(message);
+    let _view = rdr.read_u16::<BigEndian>()?;
+    let _sequence = rdr.read_u16::<BigEndian>()?;
+    let addr = if afi == AFI_IPV4 {
+        IpAddr::V4(Ipv4Addr::from(rdr.read_u32::<BigEndian>()?))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(rdr.read_u128::<BigEndian>()?))
+    };
+    let prefix = Prefix::new(addr, rdr.read_u8()?)?;
+    let _status = rdr.read_u8()?;
+    let _originated_time = rdr.read_u32::<BigEndian>()?;
+    let mut peer = vec![0u8; if afi == AFI_IPV4 { 4 } else { 16 }];
+    rdr.read_exact(&mut peer)?;
+    let _peer_as = rdr.read_u16::<BigEndian>()?;
+    let attribute_length = rdr.read_u16::<BigEndian>()? as usize;
+    let mut attributes = vec![0u8; attribute_length];
+    rdr.read_exact(&mut attributes)?;
+    let asn = PathAttributes::parse(&attributes, false)?.origin_as();
+    Ok(asn.map(|x| (prefix, x)))
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefixTableProcessor {
+    paths: Vec<String>,
+    table: Arc<RwLock<PrefixTable>>,
+    reload_interval: u64,
+    last_check: Arc<Mutex<u64>>,
+}
+
+impl PrefixTableProcessor {
+    /// Fills missing `src_as`/`dst_as` by longest-prefix match and sets
+    /// `src_tags`/`dst_tags`; the files are reloaded when they change.
+    pub fn new(paths: Vec<String>, reload_interval: Duration) -> Result<PrefixTableProcessor> {
+        if paths.is_empty() {
+            return Err(anyhow!("prefix table: no files"));
+        }
+        let table = PrefixTable::load(&paths)?;
+        Ok(PrefixTableProcessor {
+            paths,
+            table: Arc::new(RwLock::new(table)),
+            reload_interval: reload_interval.as_secs(),
+            last_check: Arc::new(Mutex::new(unix_now())),
+        })
+    }
+
+    fn enrich(
+        &self,
+        table: &PrefixTable,
+        flowmessage: &mut FlowMessage,
+        addr: IpAddr,
+        prefix: &str,
+    ) -> Result<()> {
+        let as_field = format!("{}_as", prefix);
+        if flowmessage.uint(&as_field).unwrap_or(0) == 0 {
+            if let Some(asn) = table.asn(&addr) {
+                flowmessage.set_field(&as_field, Some(FieldValue::Uint(asn as u64)))?;
+            }
+        }
+        if let Some(tags) = table.tags(&addr) {
+            flowmessage.set_field(&format!("{}_tags", prefix), Some(FieldValue::Str(tags)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PrefixTableProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let table = self.table.read().unwrap();
+        write!(
+            f,
+            "PrefixTableProcessor({}: {} asn prefixes, {} tag prefixes)",
+            self.paths.join(", "),
+            table.asns.len(),
+            table.tags.len()
+        )
+    }
+}
+
+impl Processor for PrefixTableProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, mut flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let table = self.table.read().unwrap();
+        for flowmessage in flowmessages.iter_mut() {
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("src_addr") {
+                self.enrich(&table, flowmessage, addr, "src")?;
+            }
+            if let Some(FieldValue::Ip(addr)) = flowmessage.field("dst_addr") {
+                self.enrich(&table, flowmessage, addr, "dst")?;
+            }
+        }
+        Ok(flowmessages)
+    }
+
+    fn flush(&self) -> Result<Vec<FlowMessage>> {
+        let now = unix_now();
+        {
+            let mut last_check = self.last_check.lock().unwrap();
+            if *last_check + self.reload_interval > now {
+                return Ok(Vec::new());
+            }
+            *last_check = now;
+        }
+        if !self.table.read().unwrap().is_changed() {
+            return Ok(Vec::new());
+        }
+        let table = PrefixTable::load(&self.paths)?;
+        *self.table.write().unwrap() = table;
+        eprintln!("prefix table: reloaded {}", self.paths.join(", "));
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_mrt_rib_dump() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/rib.mrt").to_string();
+        let table = PrefixTable::load(&[path]).unwrap();
+        let addr = |x: &str| IpAddr::from_str(x).unwrap();
+        assert_eq!(table.asn(&addr("2001:db8::1")), Some(64511));
+        assert_eq!(table.asn(&addr("192.0.2.1")), Some(64496));
+        // The truncated entry is skipped and the rest of the file still loads.
+        assert_eq!(table.asn(&addr("198.51.100.1")), None);
+        assert_eq!(table.asn(&addr("203.0.113.1")), Some(64497));
+    }
+
+    #[test]
+    fn abbreviated_mp_reach_nlri() {
+        let mut buf = vec![0x80, 14, 17, 16];
+        buf.extend_from_slice(&Ipv6Addr::from_str("2001:db8::1").unwrap().octets());
+        let attrs = PathAttributes::parse_mrt(&buf).unwrap();
+        assert_eq!(
+            attrs.next_hop,
+            Some(IpAddr::from_str("2001:db8::1").unwrap())
+        );
+        assert!(attrs.mp_reach.is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::event::{Event, TopNEntry, TopNReport};
+use super::super::flowkey::FlowKey;
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::http::{Response, Route};
+use super::super::util::unix_now;
+use super::Processor;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde_json::{Map, Value};
+
+type Key = Vec<Option<FieldValue>>;
+
+/// Space-Saving heavy-hitter sketch: keeps at most `capacity` counters and
+/// replaces the smallest one when a new key arrives. The counters form a
+/// min-heap indexed by key, so both updates and evictions take O(log k).
+#[derive(Debug)]
+struct SpaceSaving {
+    capacity: usize,
+    heap: Vec<Counter>,
+    index: HashMap<Key, usize>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    key: Key,
+    count: u64,
+    error: u64,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> SpaceSaving {
+        SpaceSaving {
+            capacity,
+            heap: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn insert(&mut self, key: Key, weight: u64) {
+        if let Some(&i) = self.index.get(&key) {
+            self.heap[i].count += weight;
+            self.sift_down(i);
+            return;
+        }
+        if self.heap.len() < self.capacity {
+            self.index.insert(key.clone(), self.heap.len());
+            self.heap.push(Counter {
+                key,
+                count: weight,
+                error: 0,
+            });
+            self.sift_up(self.heap.len() - 1);
+            return;
+        }
+        if self.heap.is_empty() {
+            return;
+        }
+        let min_count = self.heap[0].count;
+        self.index.remove(&self.heap[0].key);
+        self.index.insert(key.clone(), 0);
+        self.heap[0] = Counter {
+            key,
+            count: min_count + weight,
+            error: min_count,
+        };
+        self.sift_down(0);
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        *self.index.get_mut(&self.heap[i].key).unwrap() = i;
+        *self.index.get_mut(&self.heap[j].key).unwrap() = j;
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[parent].count <= self.heap[i].count {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut smallest = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.heap.len() && self.heap[child].count < self.heap[smallest].count {
+                    smallest = child;
+                }
+            }
+            if smallest == i {
+                break;
+            }
+            self.swap(i, smallest);
+            i = smallest;
+        }
+    }
+
+    fn top(&self, n: usize) -> Vec<(&Key, u64, u64)> {
+        let mut top = self
+            .heap
+            .iter()
+            .map(|x| (&x.key, x.count, x.error))
+            .collect::<Vec<(&Key, u64, u64)>>();
+        top.sort_by_key(|x| std::cmp::Reverse(x.1));
+        top.truncate(n);
+        top
+    }
+
+    fn clear(&mut self) {
+        self.heap.clear();
+        self.index.clear();
+    }
+}
+
+#[derive(Debug)]
+struct TopNState {
+    window_start: u64,
+    sketches: Vec<SpaceSaving>,
+    pending: Vec<Event>,
+    last: Vec<TopNReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TopNProcessor {
+    window: u64,
+    n: usize,
+    metric: String,
+    keys: Vec<FlowKey>,
+    state: Arc<Mutex<TopNState>>,
+}
+
+impl TopNProcessor {
+    pub fn new(
+        window: Duration,
+        n: usize,
+        capacity: usize,
+        metric: &str,
+        keys: Vec<FlowKey>,
+    ) -> Result<TopNProcessor> {
+        if !["bytes", "packets", "flows"].contains(&metric) {
+            return Err(anyhow!("topn: unknown metric {}", metric));
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("topn: no keys"));
+        }
+        let capacity = capacity.max(n);
+        let window = window.as_secs().max(1);
+        let now = unix_now();
+        let state = TopNState {
+            window_start: now - now % window,
+            sketches: keys.iter().map(|_| SpaceSaving::new(capacity)).collect(),
+            pending: Vec::new(),
+            last: Vec::new(),
+        };
+        Ok(TopNProcessor {
+            window,
+            n,
+            metric: metric.to_string(),
+            keys,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn reports(&self, state: &TopNState, window_end: u64) -> Vec<TopNReport> {
+        let datetime = Utc::now().to_string();
+        self.keys
+            .iter()
+            .zip(state.sketches.iter())
+            .map(|(key, sketch)| {
+                let entries = sketch
+                    .top(self.n)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (values, value, error))| {
+                        let mut map = Map::new();
+                        for (field, value) in key.fields.iter().zip(values) {
+                            map.insert(
+                                field.to_string(),
+                                value.clone().map_or(Value::Null, Value::from),
+                            );
+                        }
+                        TopNEntry {
+                            rank: i + 1,
+                            key: map,
+                            value,
+                            error,
+                        }
+                    })
+                    .collect();
+                TopNReport {
+                    datetime: datetime.clone(),
+                    window_start: state.window_start,
+                    window_end,
+                    key: key.to_string(),
+                    metric: self.metric.clone(),
+                    entries,
+                }
+            })
+            .collect()
+    }
+
+    fn close_window(&self, state: &mut TopNState, now: u64) {
+        let window_end = state.window_start + self.window;
+        if now < window_end {
+            return;
+        }
+        let reports = self.reports(state, window_end);
+        state
+            .pending
+            .extend(reports.iter().cloned().map(Event::TopN));
+        state.last = reports;
+        state.window_start = now - now % self.window;
+        for sketch in state.sketches.iter_mut() {
+            sketch.clear();
+        }
+    }
+
+    /// Serves the reports of the last closed window and the running estimate
+    /// of the current one as JSON.
+    pub fn route(&self) -> Route {
+        let processor = self.clone();
+        Arc::new(move |_| {
+            let state = processor.state.lock().unwrap();
+            let current = processor.reports(&state, state.window_start + processor.window);
+            let body = serde_json::json!({
+                "last": state.last,
+                "current": current,
+            });
+            Ok(Response::json(serde_json::to_string(&body)?))
+        })
+    }
+}
+
+impl Display for TopNProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let keys = self
+            .keys
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        write!(
+            f,
+            "TopNProcessor(top {} by {} per {}s: {})",
+            self.n,
+            self.metric,
+            self.window,
+            keys.join("; ")
+        )
+    }
+}
+
+impl Processor for TopNProcessor {
+    fn box_clone(&self) -> Box<dyn Processor> {
+        Box::new(self.clone())
+    }
+
+    fn process(&self, flowmessages: Vec<FlowMessage>) -> Result<Vec<FlowMessage>> {
+        let mut state = self.state.lock().unwrap();
+        self.close_window(&mut state, unix_now());
+        for flowmessage in flowmessages.iter() {
+            let weight = match self.metric.as_str() {
+                "flows" => flowmessage.uint("flows").unwrap_or(1),
+                metric => flowmessage.uint(metric).unwrap_or(0),
+            };
+            for (key, sketch) in self.keys.iter().zip(state.sketches.iter_mut()) {
+                sketch.insert(key.values(flowmessage), weight);
+            }
+        }
+        Ok(flowmessages)
+    }
+
+    fn events(&self) -> Result<Vec<Event>> {
+        let mut state = self.state.lock().unwrap();
+        self.close_window(&mut state, unix_now());
+        Ok(state.pending.drain(..).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(x: u64) -> Key {
+        vec![Some(FieldValue::Uint(x))]
+    }
+
+    #[test]
+    fn space_saving_keeps_heavy_hitters() {
+        let mut sketch = SpaceSaving::new(3);
+        for i in 0..1000 {
+            sketch.insert(key(1), 10);
+            sketch.insert(key(2), 5);
+            sketch.insert(key(100 + i), 1);
+        }
+        let top = sketch.top(2);
+        assert_eq!(top[0].0, &key(1));
+        assert_eq!(top[1].0, &key(2));
+        assert_eq!(top[0].1 - top[0].2, 10_000);
+        assert_eq!(sketch.heap.len(), 3);
+        assert_eq!(sketch.index.len(), 3);
+        for (i, counter) in sketch.heap.iter().enumerate() {
+            assert_eq!(sketch.index[&counter.key], i);
+        }
+    }
+
+    #[test]
+    fn space_saving_replaces_smallest() {
+        let mut sketch = SpaceSaving::new(2);
+        sketch.insert(key(1), 5);
+        sketch.insert(key(2), 3);
+        sketch.insert(key(3), 1);
+        let top = sketch.top(2);
+        assert_eq!(top[0], (&key(1), 5, 0));
+        assert_eq!(top[1], (&key(3), 4, 3));
+    }
+}
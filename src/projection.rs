@@ -0,0 +1,109 @@
+use super::flowmessage::{FieldValue, FlowMessage};
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub field: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    pub columns: Vec<Column>,
+}
+
+impl Projection {
+    /// Parses a comma separated field list such as `src_addr,dst_addr:destination,bytes`,
+    /// where `field:name` renames the field in the output.
+    pub fn new(spec: &str) -> Result<Projection> {
+        let mut columns = Vec::new();
+        for item in spec.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let (field, name) = match item.split_once(':') {
+                Some((field, name)) => (field.trim(), name.trim()),
+                None => (item, item),
+            };
+            if !FlowMessage::has_field(field) {
+                return Err(anyhow!("projection: unknown field {}", field));
+            }
+            columns.push(Column {
+                field: field.to_string(),
+                name: name.to_string(),
+            });
+        }
+        if columns.is_empty() {
+            return Err(anyhow!("projection: no fields"));
+        }
+        Ok(Projection { columns })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().map(|x| x.name.clone()).collect()
+    }
+
+    pub fn values(&self, flowmessage: &FlowMessage) -> Vec<Option<FieldValue>> {
+        self.columns
+            .iter()
+            .map(|x| flowmessage.field(&x.field))
+            .collect()
+    }
+}
+
+impl Display for Projection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let columns = self
+            .columns
+            .iter()
+            .map(|x| {
+                if x.field == x.name {
+                    x.field.clone()
+                } else {
+                    format!("{}:{}", x.field, x.name)
+                }
+            })
+            .collect::<Vec<String>>();
+        write!(f, "{}", columns.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    #[test]
+    fn renaming_and_aliases() {
+        let projection = Projection::new("src_addr, dst_addr:destination,bytes").unwrap();
+        assert_eq!(projection.names(), vec!["src_addr", "destination", "bytes"]);
+        assert_eq!(
+            projection.to_string(),
+            "src_addr,dst_addr:destination,bytes"
+        );
+
+        let flowmessage = FlowMessageBuilder::default()
+            .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .ipv6_dst_addr("2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap())
+            .d0ctets(1500u32)
+            .build()
+            .unwrap();
+        assert_eq!(
+            projection.values(&flowmessage),
+            vec![
+                Some(FieldValue::Ip("192.0.2.1".parse().unwrap())),
+                Some(FieldValue::Ip("2001:db8::1".parse().unwrap())),
+                Some(FieldValue::Uint(1500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Projection::new("src_addr,nope:x")
+                .err()
+                .map(|x| x.to_string()),
+            Some("projection: unknown field nope".to_string())
+        );
+        assert!(Projection::new(" , ").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::metrics;
+
+/// Hands records to a background thread that calls `sink` with batches of
+/// up to `max_records` records, or with what has arrived once `linger` has
+/// passed since the first record of the batch. Records are dropped when
+/// more than `capacity` are queued, so a slow sink never blocks the
+/// collector. The queue is reported in the internal metrics under `name`.
+#[derive(Debug)]
+pub struct Batcher<T> {
+    sender: SyncSender<Message<T>>,
+    depth: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+enum Message<T> {
+    Record(T),
+    /// Sinks the records queued before it, then acknowledges.
+    Close(SyncSender<()>),
+}
+
+impl<T> Clone for Batcher<T> {
+    fn clone(&self) -> Batcher<T> {
+        Batcher {
+            sender: self.sender.clone(),
+            depth: self.depth.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> Batcher<T> {
+    pub fn new<F>(
+        name: &str,
+        capacity: usize,
+        max_records: usize,
+        linger: Duration,
+        sink: F,
+    ) -> Batcher<T>
+    where
+        F: FnMut(Vec<T>) + Send + 'static,
+    {
+        Batcher::sized(name, capacity, max_records, usize::MAX, |_| 0, linger, sink)
+    }
+
+    /// Like `new`, but also ends a batch once the records in it add up to
+    /// `max_bytes` as measured by `size`.
+    pub fn sized<F, S>(
+        name: &str,
+        capacity: usize,
+        max_records: usize,
+        max_bytes: usize,
+        size: S,
+        linger: Duration,
+        mut sink: F,
+    ) -> Batcher<T>
+    where
+        F: FnMut(Vec<T>) + Send + 'static,
+        S: Fn(&T) -> usize + Send + 'static,
+    {
+        let (sender, receiver) = sync_channel::<Message<T>>(capacity);
+        let max_records = max_records.max(1);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let depth_c = depth.clone();
+        let dropped_c = dropped.clone();
+        metrics::register("ferrisflow_queue_depth", &[("queue", name)], move || {
+            depth_c.load(Ordering::Relaxed) as f64
+        });
+        metrics::register(
+            "ferrisflow_queue_dropped_total",
+            &[("queue", name)],
+            move || dropped_c.load(Ordering::Relaxed) as f64,
+        );
+        metrics::register("ferrisflow_queue_capacity", &[("queue", name)], move || {
+            capacity as f64
+        });
+        let depth_c = depth.clone();
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while let Ok(first) = receiver.recv() {
+                    let deadline = Instant::now() + linger;
+                    let mut batch = Vec::new();
+                    let mut bytes = 0;
+                    let mut close = None;
+                    let mut disconnected = false;
+                    match first {
+                        Message::Record(record) => {
+                            depth_c.fetch_sub(1, Ordering::Relaxed);
+                            bytes += size(&record);
+                            batch.push(record);
+                        }
+                        Message::Close(ack) => close = Some(ack),
+                    }
+                    while close.is_none() && batch.len() < max_records && bytes < max_bytes {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        match receiver.recv_timeout(timeout) {
+                            Ok(Message::Record(record)) => {
+                                depth_c.fetch_sub(1, Ordering::Relaxed);
+                                bytes += size(&record);
+                                batch.push(record);
+                            }
+                            Ok(Message::Close(ack)) => close = Some(ack),
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => {
+                                disconnected = true;
+                                break;
+                            }
+                        }
+                    }
+                    if !batch.is_empty() {
+                        sink(batch);
+                    }
+                    if let Some(ack) = close {
+                        let _ = ack.send(());
+                    }
+                    if disconnected {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn batch thread");
+        Batcher {
+            sender,
+            depth,
+            dropped,
+        }
+    }
+
+    /// Queues a record, returning false if it was dropped.
+    pub fn send(&self, record: T) -> bool {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        match self.sender.try_send(Message::Record(record)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Waits until the records queued so far have been passed to the sink.
+    pub fn close(&self) {
+        let (ack, done) = sync_channel(1);
+        if self.sender.send(Message::Close(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
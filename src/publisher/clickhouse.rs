@@ -0,0 +1,776 @@
+use std::fmt::Display;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use super::super::flowmessage::{FieldType, FieldValue, FlowMessage, FIELD_TYPES};
+use super::super::http::{self, Url};
+use super::super::metrics;
+use super::super::util::unix_now;
+use super::batch::Batcher;
+use super::Publisher;
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use chrono::DateTime;
+
+/// Flow start and end as timestamps, in addition to the `FlowMessage` fields.
+const FLOW_START: &str = "flow_start";
+const FLOW_END: &str = "flow_end";
+
+const QUEUE_CAPACITY: usize = 100_000;
+
+const SPOOL_EXTENSION: &str = "spool";
+/// Batches ClickHouse rejected, kept in the spool directory but never retried.
+const REJECTED_EXTENSION: &str = "rejected";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    RowBinary,
+    JsonEachRow,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::RowBinary => "RowBinary",
+            Format::JsonEachRow => "JSONEachRow",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "rowbinary" => Ok(Format::RowBinary),
+            "jsoneachrow" => Ok(Format::JsonEachRow),
+            _ => Err(anyhow!("clickhouse: unsupported format {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    UInt32,
+    UInt64,
+    Float64,
+    Str,
+    Ipv4,
+    Ipv6,
+    DateTime64Micros,
+    DateTime64Millis,
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    column_type: ColumnType,
+}
+
+impl Column {
+    fn new(name: &str) -> Result<Column> {
+        let column_type = match name {
+            "datetime" => ColumnType::DateTime64Micros,
+            FLOW_START | FLOW_END => ColumnType::DateTime64Millis,
+            _ => match FIELD_TYPES.iter().find(|(x, _)| *x == name) {
+                Some((_, FieldType::Uint32)) => ColumnType::UInt32,
+                Some((_, FieldType::Uint64)) => ColumnType::UInt64,
+                Some((_, FieldType::Double)) => ColumnType::Float64,
+                Some((_, FieldType::Ip)) if name.contains("ipv4") => ColumnType::Ipv4,
+                Some((_, FieldType::Ip)) => ColumnType::Ipv6,
+                Some(_) => ColumnType::Str,
+                None => return Err(anyhow!("clickhouse: unknown field {}", name)),
+            },
+        };
+        Ok(Column {
+            name: name.to_string(),
+            column_type,
+        })
+    }
+
+    fn sql_type(&self) -> &'static str {
+        match self.column_type {
+            ColumnType::UInt32 => "Nullable(UInt32)",
+            ColumnType::UInt64 => "Nullable(UInt64)",
+            ColumnType::Float64 => "Nullable(Float64)",
+            ColumnType::Str => "Nullable(String)",
+            ColumnType::Ipv4 => "Nullable(IPv4)",
+            ColumnType::Ipv6 => "Nullable(IPv6)",
+            ColumnType::DateTime64Micros => "Nullable(DateTime64(6, 'UTC'))",
+            ColumnType::DateTime64Millis => "Nullable(DateTime64(3, 'UTC'))",
+        }
+    }
+}
+
+enum Value {
+    Int(u64),
+    Double(f64),
+    Str(String),
+    Ip(IpAddr),
+    Time(i64),
+}
+
+fn value(flowmessage: &FlowMessage, column: &Column) -> Option<Value> {
+    match column.name.as_str() {
+        "datetime" => return flowmessage.received_nanos().map(|x| Value::Time(x / 1000)),
+        FLOW_START => {
+            return flowmessage
+                .start_end_millis()
+                .map(|x| Value::Time(x.0 as i64))
+        }
+        FLOW_END => {
+            return flowmessage
+                .start_end_millis()
+                .map(|x| Value::Time(x.1 as i64))
+        }
+        _ => {}
+    }
+    match (column.column_type, flowmessage.field(&column.name)?) {
+        (ColumnType::UInt32, FieldValue::Uint(x)) | (ColumnType::UInt64, FieldValue::Uint(x)) => {
+            Some(Value::Int(x))
+        }
+        (ColumnType::Float64, FieldValue::Float(x)) => Some(Value::Double(x.0)),
+        (ColumnType::Ipv4, FieldValue::Ip(x)) if x.is_ipv4() => Some(Value::Ip(x)),
+        (ColumnType::Ipv6, FieldValue::Ip(x)) => Some(Value::Ip(x)),
+        (ColumnType::Str, x) => Some(Value::Str(x.to_string())),
+        _ => None,
+    }
+}
+
+fn ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(x) => x.to_ipv6_mapped(),
+        IpAddr::V6(x) => x,
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Encodes a row of nullable columns in RowBinary: a null byte per column,
+/// then the little-endian value, except for IPv6 which is in network order.
+fn row_binary(flowmessage: &FlowMessage, columns: &[Column]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(512);
+    for column in columns {
+        let value = match value(flowmessage, column) {
+            Some(x) => x,
+            None => {
+                buf.push(1);
+                continue;
+            }
+        };
+        buf.push(0);
+        match (column.column_type, value) {
+            (ColumnType::UInt32, Value::Int(x)) => buf.write_u32::<LittleEndian>(x as u32)?,
+            (ColumnType::UInt64, Value::Int(x)) => buf.write_u64::<LittleEndian>(x)?,
+            (ColumnType::Float64, Value::Double(x)) => buf.write_f64::<LittleEndian>(x)?,
+            (ColumnType::Ipv4, Value::Ip(IpAddr::V4(x))) => {
+                buf.write_u32::<LittleEndian>(u32::from(x))?
+            }
+            (ColumnType::Ipv6, Value::Ip(x)) => buf.extend_from_slice(&ipv6(x).octets()),
+            (ColumnType::DateTime64Micros, Value::Time(x))
+            | (ColumnType::DateTime64Millis, Value::Time(x)) => buf.write_i64::<LittleEndian>(x)?,
+            (_, Value::Str(x)) => {
+                write_varint(&mut buf, x.len() as u64);
+                buf.extend_from_slice(x.as_bytes());
+            }
+            _ => return Err(anyhow!("clickhouse: invalid value for {}", column.name)),
+        }
+    }
+    Ok(buf)
+}
+
+/// Encodes a row as a JSON line, with timestamps as UTC datetime strings.
+fn json_each_row(flowmessage: &FlowMessage, columns: &[Column]) -> Result<Vec<u8>> {
+    let mut map = serde_json::Map::new();
+    for column in columns {
+        let value = match value(flowmessage, column) {
+            Some(Value::Int(x)) => serde_json::Value::from(x),
+            Some(Value::Double(x)) => serde_json::Value::from(x),
+            Some(Value::Str(x)) => serde_json::Value::from(x),
+            Some(Value::Ip(x)) if column.column_type == ColumnType::Ipv6 => {
+                serde_json::Value::from(ipv6(x).to_string())
+            }
+            Some(Value::Ip(x)) => serde_json::Value::from(x.to_string()),
+            Some(Value::Time(x)) => {
+                let time = if column.column_type == ColumnType::DateTime64Micros {
+                    DateTime::from_timestamp_micros(x)
+                } else {
+                    DateTime::from_timestamp_millis(x)
+                };
+                time.map_or(serde_json::Value::Null, |x| {
+                    serde_json::Value::from(x.format("%Y-%m-%d %H:%M:%S%.6f").to_string())
+                })
+            }
+            None => serde_json::Value::Null,
+        };
+        map.insert(column.name.clone(), value);
+    }
+    let mut buf = serde_json::to_vec(&map)?;
+    buf.push(b'\n');
+    Ok(buf)
+}
+
+#[derive(Debug, Clone)]
+pub struct ClickHouseConfig {
+    pub url: String,
+    pub database: String,
+    pub table: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub format: Format,
+    /// Columns to insert, or every field followed by `flow_start` and
+    /// `flow_end` if None.
+    pub fields: Option<Vec<String>>,
+    pub create_table: bool,
+    pub batch_size: usize,
+    pub linger: Duration,
+    pub retries: usize,
+    pub timeout: Duration,
+    /// Directory batches are saved to when they cannot be inserted, and
+    /// inserted from once ClickHouse is back.
+    pub spool: Option<String>,
+    pub spool_max_size: Option<u64>,
+}
+
+/// Inserts flows into a ClickHouse table over the HTTP interface. Rows are
+/// encoded as they are published and inserted in batches from a background
+/// thread, retrying with backoff; batches that still fail are spooled to
+/// disk and inserted before the next batch once ClickHouse is reachable.
+#[derive(Debug, Clone)]
+pub struct ClickHousePublisher {
+    table: String,
+    format: Format,
+    columns: Vec<Column>,
+    batcher: Batcher<Vec<u8>>,
+}
+
+impl ClickHousePublisher {
+    pub fn new(config: ClickHouseConfig) -> Result<ClickHousePublisher> {
+        let names = config.fields.clone().unwrap_or_else(|| {
+            FIELD_TYPES
+                .iter()
+                .map(|(x, _)| x.to_string())
+                .chain([FLOW_START.to_string(), FLOW_END.to_string()])
+                .collect()
+        });
+        let columns = names
+            .iter()
+            .map(|x| Column::new(x))
+            .collect::<Result<Vec<Column>>>()?;
+        if let Some(spool) = &config.spool {
+            fs::create_dir_all(spool).map_err(|e| anyhow!("{}: {}", spool, e))?;
+        }
+        let table = format!("{}.{}", config.database, config.table);
+        let format = config.format;
+        let mut inserter = Inserter::new(config, columns.clone())?;
+        let batcher = Batcher::new(
+            "clickhouse",
+            QUEUE_CAPACITY,
+            inserter.config.batch_size,
+            inserter.config.linger,
+            move |rows| inserter.send(rows),
+        );
+        Ok(ClickHousePublisher {
+            table,
+            format,
+            columns,
+            batcher,
+        })
+    }
+}
+
+impl Display for ClickHousePublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ClickHousePublisher({}, {})",
+            self.table,
+            self.format.name()
+        )
+    }
+}
+
+impl Publisher for ClickHousePublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        for flowmessage in flowmessages {
+            let row = match self.format {
+                Format::RowBinary => row_binary(flowmessage, &self.columns)?,
+                Format::JsonEachRow => json_each_row(flowmessage, &self.columns)?,
+            };
+            self.batcher.send(row);
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        self.batcher.close();
+        Ok(())
+    }
+}
+
+/// ClickHouse refused the data of an insert with 400 or 413, e.g. for an
+/// unknown column, malformed rows or a batch that is too large, so sending
+/// it again cannot succeed. Other 4xx statuses, such as a wrong password,
+/// a missing database or throttling, may clear up and are retried.
+#[derive(Debug)]
+struct Rejected(u16, String);
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+fn is_rejected(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Rejected>().is_some()
+}
+
+/// Runs on the batch thread and owns the spool.
+struct Inserter {
+    config: ClickHouseConfig,
+    url: Url,
+    columns: Vec<Column>,
+    insert_query: String,
+    created: bool,
+    sequence: u64,
+}
+
+impl Inserter {
+    fn new(config: ClickHouseConfig, columns: Vec<Column>) -> Result<Inserter> {
+        let url = Url::parse(&config.url).map_err(|e| anyhow!("clickhouse: {}", e))?;
+        let insert_query = format!(
+            "INSERT INTO {}.{} ({}) FORMAT {}",
+            quote(&config.database),
+            quote(&config.table),
+            columns
+                .iter()
+                .map(|x| quote(&x.name))
+                .collect::<Vec<String>>()
+                .join(", "),
+            config.format.name()
+        );
+        Ok(Inserter {
+            config,
+            url,
+            columns,
+            insert_query,
+            created: false,
+            sequence: 0,
+        })
+    }
+
+    fn send(&mut self, rows: Vec<Vec<u8>>) {
+        let count = rows.len();
+        let body = rows.concat();
+        if self.spool_files().next().is_some() {
+            if let Err(e) = self.drain() {
+                eprintln!("clickhouse: {}", e);
+                self.spool(&body, count);
+                return;
+            }
+        }
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                thread::sleep(Duration::from_millis(100 << attempt.min(6)));
+            }
+            let query = self.insert_query.clone();
+            match self.insert(&query, &body) {
+                Ok(()) => {
+                    sink_records("sent", count);
+                    return;
+                }
+                Err(e) if is_rejected(&e) => {
+                    eprintln!("clickhouse: {}", e);
+                    self.quarantine(&body, count);
+                    return;
+                }
+                Err(e) => eprintln!("clickhouse: {}", e),
+            }
+        }
+        self.spool(&body, count);
+    }
+
+    fn insert(&mut self, query: &str, body: &[u8]) -> Result<()> {
+        if self.config.create_table && !self.created {
+            // A failed table creation says nothing about the rows, so it is
+            // never a rejection of the batch.
+            self.create_table()
+                .map_err(|e| anyhow!("create table: {}", e))?;
+            self.created = true;
+        }
+        self.query(query, body)
+    }
+
+    fn query(&self, query: &str, body: &[u8]) -> Result<()> {
+        let mut headers = Vec::new();
+        if let Some(user) = &self.config.user {
+            headers.push(("X-ClickHouse-User", user.clone()));
+        }
+        if let Some(password) = &self.config.password {
+            headers.push(("X-ClickHouse-Key", password.clone()));
+        }
+        let target = format!("/?query={}", http::encode_query(query));
+        let (status, response) = http::request(
+            "POST",
+            &self.url,
+            &target,
+            &headers,
+            body,
+            self.config.timeout,
+        )?;
+        let message = String::from_utf8_lossy(&response).trim().to_string();
+        match status {
+            200 => Ok(()),
+            400 | 413 => Err(Rejected(status, message).into()),
+            _ => Err(anyhow!("{}: {}", status, message)),
+        }
+    }
+
+    /// Creates the table as a MergeTree ordered by insertion; create it
+    /// yourself for another engine, order or TTL.
+    fn create_table(&self) -> Result<()> {
+        let columns = self
+            .columns
+            .iter()
+            .map(|x| format!("{} {}", quote(&x.name), x.sql_type()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}.{} ({}) ENGINE = MergeTree ORDER BY tuple()",
+            quote(&self.config.database),
+            quote(&self.config.table),
+            columns
+        );
+        self.query(&query, &[])
+    }
+
+    fn spool_files(&self) -> impl Iterator<Item = PathBuf> {
+        self.files(&[SPOOL_EXTENSION])
+    }
+
+    fn files(&self, extensions: &[&str]) -> impl Iterator<Item = PathBuf> {
+        let mut files = match &self.config.spool {
+            Some(spool) => fs::read_dir(spool)
+                .map(|x| {
+                    x.filter_map(|x| x.ok().map(|x| x.path()))
+                        .filter(|x| {
+                            x.extension()
+                                .is_some_and(|x| extensions.iter().any(|y| x == *y))
+                        })
+                        .collect::<Vec<PathBuf>>()
+                })
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        files.sort();
+        files.into_iter()
+    }
+
+    /// Saves a batch with its insert query so that it is inserted as it was
+    /// encoded, even if the columns change before it is drained.
+    fn spool(&mut self, body: &[u8], count: usize) {
+        if self.config.spool.is_none() {
+            eprintln!("clickhouse: dropped {} rows after retries", count);
+            sink_records("dropped", count);
+            return;
+        }
+        if let Some(path) = self.save(body, count, SPOOL_EXTENSION) {
+            eprintln!("clickhouse: spooled {} rows to {}", count, path.display());
+            sink_records("spooled", count);
+        }
+    }
+
+    /// Keeps a batch ClickHouse rejected next to the spool for inspection,
+    /// where it is never inserted from, or drops it without a spool.
+    fn quarantine(&mut self, body: &[u8], count: usize) {
+        if self.config.spool.is_none() {
+            eprintln!("clickhouse: dropped {} rejected rows", count);
+            sink_records("rejected", count);
+            return;
+        }
+        if let Some(path) = self.save(body, count, REJECTED_EXTENSION) {
+            eprintln!(
+                "clickhouse: quarantined {} rejected rows to {}",
+                count,
+                path.display()
+            );
+            sink_records("rejected", count);
+        }
+    }
+
+    fn save(&mut self, body: &[u8], count: usize, extension: &str) -> Option<PathBuf> {
+        let spool = self.config.spool.clone()?;
+        let size = self
+            .files(&[SPOOL_EXTENSION, REJECTED_EXTENSION])
+            .filter_map(|x| fs::metadata(x).ok())
+            .map(|x| x.len())
+            .sum::<u64>();
+        if self
+            .config
+            .spool_max_size
+            .is_some_and(|x| size + body.len() as u64 > x)
+        {
+            eprintln!("clickhouse: spool full, dropped {} rows", count);
+            sink_records("dropped", count);
+            return None;
+        }
+        self.sequence += 1;
+        let name = format!(
+            "{:020}-{}-{:010}.{}",
+            unix_now(),
+            std::process::id(),
+            self.sequence,
+            extension
+        );
+        let path = Path::new(&spool).join(name);
+        let mut data = Vec::with_capacity(self.insert_query.len() + 1 + body.len());
+        data.extend_from_slice(self.insert_query.as_bytes());
+        data.push(b'\n');
+        data.extend_from_slice(body);
+        match fs::write(&path, data) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                eprintln!("clickhouse: {}: {}", path.display(), e);
+                sink_records("dropped", count);
+                None
+            }
+        }
+    }
+
+    /// Inserts the spooled batches oldest first, stopping at the first error
+    /// other than a rejection; rejected files are renamed out of the spool.
+    fn drain(&mut self) -> Result<()> {
+        for path in self.spool_files() {
+            let data = fs::read(&path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            let (query, body) = match data.iter().position(|x| *x == b'\n') {
+                Some(i) => (
+                    String::from_utf8_lossy(&data[..i]).to_string(),
+                    &data[i + 1..],
+                ),
+                None => {
+                    eprintln!("clickhouse: {}: invalid spool file", path.display());
+                    fs::remove_file(&path)?;
+                    continue;
+                }
+            };
+            if let Err(e) = self.insert(&query, body) {
+                if !is_rejected(&e) {
+                    return Err(e);
+                }
+                let rejected = path.with_extension(REJECTED_EXTENSION);
+                fs::rename(&path, &rejected).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                eprintln!("clickhouse: {}: {}, quarantined", rejected.display(), e);
+                continue;
+            }
+            fs::remove_file(&path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            eprintln!("clickhouse: inserted {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+fn sink_records(result: &str, count: usize) {
+    metrics::add(
+        "ferrisflow_sink_records_total",
+        &[("sink", "clickhouse"), ("result", result)],
+        count as u64,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers each request with the next status of `statuses`, then 200,
+    /// and records the query of every request.
+    fn mock_server(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let queries_c = queries.clone();
+        thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|x| x.strip_prefix("Content-Length: "))
+                            .and_then(|x| x.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length || n == 0 {
+                            let target = text.split(' ').nth(1).unwrap_or_default();
+                            queries_c.lock().unwrap().push(target.to_string());
+                            break;
+                        }
+                    }
+                }
+                let status = statuses.next().unwrap_or(200);
+                let body = if status == 200 { "" } else { "error" };
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, queries)
+    }
+
+    fn inserter(url: &str, spool: &Path) -> Inserter {
+        let config = ClickHouseConfig {
+            url: url.to_string(),
+            database: "default".to_string(),
+            table: "flows".to_string(),
+            user: None,
+            password: None,
+            format: Format::JsonEachRow,
+            fields: Some(vec!["src_port".to_string()]),
+            create_table: false,
+            batch_size: 100,
+            linger: Duration::from_millis(10),
+            retries: 2,
+            timeout: Duration::from_secs(5),
+            spool: Some(spool.display().to_string()),
+            spool_max_size: None,
+        };
+        let columns = vec![Column::new("src_port").unwrap()];
+        Inserter::new(config, columns).unwrap()
+    }
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ferrisflow-clickhouse-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn count(spool: &Path, extension: &str) -> usize {
+        fs::read_dir(spool)
+            .unwrap()
+            .filter(|x| {
+                x.as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|x| x == extension)
+            })
+            .count()
+    }
+
+    fn rows() -> Vec<Vec<u8>> {
+        vec![b"{\"src_port\":80}\n".to_vec()]
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let spool = spool_dir("retry");
+        let (url, queries) = mock_server(vec![503, 500]);
+        let mut inserter = inserter(&url, &spool);
+        inserter.send(rows());
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 3);
+        assert!(queries[0].contains("INSERT"));
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 0);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn quarantines_rejected_batches() {
+        let spool = spool_dir("rejected");
+        let (url, queries) = mock_server(vec![400]);
+        let mut inserter = inserter(&url, &spool);
+        inserter.send(rows());
+        assert_eq!(queries.lock().unwrap().len(), 1);
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 0);
+        assert_eq!(count(&spool, REJECTED_EXTENSION), 1);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn spools_other_client_errors_and_drains() {
+        let spool = spool_dir("client");
+        let (url, queries) = mock_server(vec![401, 403, 429]);
+        let mut inserter = inserter(&url, &spool);
+        inserter.send(rows());
+        assert_eq!(queries.lock().unwrap().len(), 3);
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 1);
+        assert_eq!(count(&spool, REJECTED_EXTENSION), 0);
+
+        // The spooled batch is inserted before the new one.
+        inserter.send(rows());
+        assert_eq!(queries.lock().unwrap().len(), 5);
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 0);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn spools_when_table_creation_fails() {
+        let spool = spool_dir("create");
+        let (url, queries) = mock_server(vec![400, 400, 400]);
+        let mut inserter = inserter(&url, &spool);
+        inserter.config.create_table = true;
+        inserter.send(rows());
+        assert!(queries.lock().unwrap()[0].contains("CREATE"));
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 1);
+        assert_eq!(count(&spool, REJECTED_EXTENSION), 0);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn spools_when_unreachable_and_drains() {
+        let spool = spool_dir("drain");
+        let unreachable = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let mut inserter = inserter(&unreachable, &spool);
+        inserter.send(rows());
+        inserter.send(rows());
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 2);
+
+        // The first spooled batch is rejected and quarantined, the second is
+        // inserted, then the new batch.
+        let (url, queries) = mock_server(vec![400]);
+        inserter.url = Url::parse(&url).unwrap();
+        inserter.send(rows());
+        assert_eq!(queries.lock().unwrap().len(), 3);
+        assert_eq!(count(&spool, SPOOL_EXTENSION), 0);
+        assert_eq!(count(&spool, REJECTED_EXTENSION), 1);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+}
@@ -1,32 +1,70 @@
+use super::super::event::Event;
 use super::super::flowmessage::FlowMessage;
+use super::super::projection::Projection;
+use super::rotate::RotatingFile;
 use super::Publisher;
 use anyhow::Result;
 use csv::WriterBuilder;
-use std::{fmt::Display, io::stdout};
+use std::fmt::Display;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Clone)]
-pub struct CsvPublisher {}
+/// Prints CSV records to stdout, or writes them to rotating files that each
+/// start with the header. Events such as top-N reports and alerts don't fit
+/// the flow columns and are dropped.
+#[derive(Clone)]
+pub struct CsvPublisher {
+    projection: Option<Projection>,
+    file: Option<Arc<Mutex<RotatingFile>>>,
+    events_dropped: Arc<AtomicBool>,
+}
 
 impl CsvPublisher {
-    pub fn new(header_none: bool) -> CsvPublisher {
+    pub fn new(header_none: bool, projection: Option<Projection>) -> CsvPublisher {
         if !header_none {
-            let fields = FlowMessage::as_field_name_array();
-            println!(
-                "{}",
-                fields
-                    .iter()
-                    .map(|x| x.name().to_string())
-                    .collect::<Vec<String>>()
-                    .join(",")
-            );
+            print!("{}", header(&projection));
+        }
+        CsvPublisher {
+            projection,
+            file: None,
+            events_dropped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn to_file(
+        header_none: bool,
+        projection: Option<Projection>,
+        mut file: RotatingFile,
+    ) -> CsvPublisher {
+        if !header_none {
+            file.set_header(header(&projection).into_bytes());
+        }
+        CsvPublisher {
+            projection,
+            file: Some(Arc::new(Mutex::new(file))),
+            events_dropped: Arc::new(AtomicBool::new(false)),
         }
-        CsvPublisher {}
     }
 }
 
+fn header(projection: &Option<Projection>) -> String {
+    let fields = match projection {
+        Some(projection) => projection.names(),
+        None => FlowMessage::as_field_name_array()
+            .iter()
+            .map(|x| x.name().to_string())
+            .collect::<Vec<String>>(),
+    };
+    format!("{}\n", fields.join(","))
+}
+
 impl Display for CsvPublisher {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "CsvPublisher")
+        match &self.file {
+            Some(file) => write!(f, "CsvPublisher({})", file.lock().unwrap().template()),
+            None => write!(f, "CsvPublisher"),
+        }
     }
 }
 
@@ -35,14 +73,94 @@ impl Publisher for CsvPublisher {
         Box::new(self.clone())
     }
 
-    fn publish(&self, flowmessages: &Vec<FlowMessage>) -> Result<()> {
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
         let mut wtr = WriterBuilder::new()
             .has_headers(false)
-            .from_writer(stdout());
+            .from_writer(Vec::new());
         for flowmessage in flowmessages {
-            wtr.serialize(flowmessage)?;
+            match &self.projection {
+                Some(projection) => {
+                    let record = projection
+                        .values(flowmessage)
+                        .into_iter()
+                        .map(|x| x.map(|x| x.to_string()).unwrap_or_default())
+                        .collect::<Vec<String>>();
+                    wtr.write_record(&record)?;
+                }
+                None => wtr.serialize(flowmessage)?,
+            }
+        }
+        let buf = wtr.into_inner()?;
+        match &self.file {
+            Some(file) => file.lock().unwrap().write(&buf)?,
+            None => {
+                let mut stdout = stdout();
+                stdout.write_all(&buf)?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn publish_events(&self, events: &[Event]) -> Result<()> {
+        if !events.is_empty() && !self.events_dropped.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "{}: events are not supported, use --json to write them",
+                self
+            );
         }
-        wtr.flush()?;
         Ok(())
     }
+
+    fn flush(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => file.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => file.lock().unwrap().close(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::super::rotate::{FileCompression, Rotation};
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn header_per_file() {
+        let dir = std::env::temp_dir().join(format!("ferrisflow-csv-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let template = dir.join("flows.csv").to_string_lossy().to_string();
+        let rotation = Rotation {
+            interval: None,
+            max_size: Some(1),
+        };
+        let file = RotatingFile::new(&template, rotation, FileCompression::None, None).unwrap();
+        let projection = Projection::new("dst_port:port,bytes").unwrap();
+        let publisher = CsvPublisher::to_file(false, Some(projection), file);
+
+        let flowmessage = |port: u16| {
+            FlowMessageBuilder::default()
+                .dst_port(port)
+                .build()
+                .unwrap()
+        };
+        publisher.publish(&[flowmessage(443)]).unwrap();
+        publisher.publish(&[flowmessage(53)]).unwrap();
+        publisher.close().unwrap();
+        assert_eq!(fs::read_to_string(&template).unwrap(), "port,bytes\n443,\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("flows-1.csv")).unwrap(),
+            "port,bytes\n53,\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
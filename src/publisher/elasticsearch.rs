@@ -0,0 +1,505 @@
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+
+use super::super::flowmessage::{FieldType, FlowMessage, FIELD_TYPES};
+use super::super::http::{self, Url};
+use super::super::metrics;
+use super::super::projection::Projection;
+use super::super::util::unix_now;
+use super::batch::Batcher;
+use super::json::JsonEncoder;
+use super::rotate;
+use super::Publisher;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{json, Map, Value};
+
+/// Added to every document in addition to the `FlowMessage` fields.
+const TIMESTAMP: &str = "@timestamp";
+const FLOW_START: &str = "flow_start";
+const FLOW_END: &str = "flow_end";
+
+const QUEUE_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct ElasticsearchConfig {
+    pub url: String,
+    /// Index name with strftime escapes, expanded with the time the flow
+    /// was received, e.g. `ferrisflow-%Y.%m.%d` for daily indices.
+    pub index: String,
+    /// Installs an index template for the indices before the first request.
+    pub template: bool,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub batch_size: usize,
+    pub batch_bytes: usize,
+    pub linger: Duration,
+    pub retries: usize,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    index: String,
+    body: Vec<u8>,
+}
+
+/// Indexes flows into Elasticsearch or OpenSearch with the `_bulk` API.
+/// Documents are batched on a background thread; those the cluster rejects
+/// as overloaded are retried with backoff, the others are dropped and
+/// logged.
+#[derive(Debug, Clone)]
+pub struct ElasticsearchPublisher {
+    index: String,
+    encoder: JsonEncoder,
+    /// The names the `datetime` field is written under, rewritten as
+    /// RFC 3339 so that they map to dates.
+    datetime_names: Vec<String>,
+    batcher: Batcher<Document>,
+}
+
+impl ElasticsearchPublisher {
+    pub fn new(
+        config: ElasticsearchConfig,
+        projection: Option<Projection>,
+    ) -> Result<ElasticsearchPublisher> {
+        rotate::expand_path(&config.index, unix_now())?;
+        let columns = match &projection {
+            Some(projection) => projection
+                .columns
+                .iter()
+                .map(|x| (x.field.clone(), x.name.clone()))
+                .collect::<Vec<(String, String)>>(),
+            None => FIELD_TYPES
+                .iter()
+                .map(|(x, _)| (x.to_string(), x.to_string()))
+                .collect(),
+        };
+        let datetime_names = columns
+            .iter()
+            .filter(|(field, _)| field == "datetime")
+            .map(|(_, name)| name.clone())
+            .collect();
+        let index = config.index.clone();
+        let mut indexer = Indexer::new(config, &columns)?;
+        let batcher = Batcher::sized(
+            "elasticsearch",
+            QUEUE_CAPACITY,
+            indexer.config.batch_size,
+            indexer.config.batch_bytes,
+            |x: &Document| x.body.len() + x.index.len(),
+            indexer.config.linger,
+            move |documents| indexer.send(documents),
+        );
+        Ok(ElasticsearchPublisher {
+            index,
+            encoder: JsonEncoder::new(projection, true),
+            datetime_names,
+            batcher,
+        })
+    }
+
+    fn document(&self, flowmessage: &FlowMessage) -> Result<Document> {
+        let received = flowmessage
+            .received_nanos()
+            .map_or(unix_now() as i64 * 1000, |x| x / 1_000_000);
+        let mut value = self.encoder.to_value(flowmessage)?;
+        if let Value::Object(map) = &mut value {
+            for name in self.datetime_names.iter() {
+                if let Some(datetime) = map.get_mut(name) {
+                    *datetime = rfc3339(received);
+                }
+            }
+            map.insert(TIMESTAMP.to_string(), rfc3339(received));
+            if let Some((start, end)) = flowmessage.start_end_millis() {
+                map.insert(FLOW_START.to_string(), rfc3339(start as i64));
+                map.insert(FLOW_END.to_string(), rfc3339(end as i64));
+            }
+        }
+        Ok(Document {
+            index: rotate::expand_path(&self.index, (received / 1000) as u64)?,
+            body: serde_json::to_vec(&value)?,
+        })
+    }
+}
+
+fn rfc3339(millis: i64) -> Value {
+    DateTime::from_timestamp_millis(millis).map_or(Value::Null, |x| {
+        Value::from(x.to_rfc3339_opts(SecondsFormat::Millis, true))
+    })
+}
+
+/// The Elasticsearch type of a field or alias.
+fn mapping_type(field: &str) -> &'static str {
+    match field {
+        "datetime" => "date",
+        "exporter" | "src_addr" | "dst_addr" | "next_hop" => "ip",
+        "bytes" | "packets" | "proto" | "in_if" | "out_if" => "long",
+        _ => match FIELD_TYPES.iter().find(|(x, _)| *x == field) {
+            Some((_, FieldType::Uint32)) | Some((_, FieldType::Uint64)) => "long",
+            Some((_, FieldType::Double)) => "double",
+            Some((_, FieldType::Ip)) => "ip",
+            _ => "keyword",
+        },
+    }
+}
+
+impl Display for ElasticsearchPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ElasticsearchPublisher({})", self.index)
+    }
+}
+
+impl Publisher for ElasticsearchPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        for flowmessage in flowmessages {
+            self.batcher.send(self.document(flowmessage)?);
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        self.batcher.close();
+        Ok(())
+    }
+}
+
+/// Runs on the batch thread.
+struct Indexer {
+    config: ElasticsearchConfig,
+    url: Url,
+    headers: Vec<(&'static str, String)>,
+    template_name: String,
+    template: Value,
+    installed: bool,
+}
+
+impl Indexer {
+    fn new(config: ElasticsearchConfig, columns: &[(String, String)]) -> Result<Indexer> {
+        let url = Url::parse(&config.url).map_err(|e| anyhow!("elasticsearch: {}", e))?;
+        let mut headers = Vec::new();
+        if let Some(user) = &config.user {
+            let password = config.password.as_deref().unwrap_or_default();
+            headers.push(("Authorization", http::basic_auth(user, password)));
+        }
+
+        let pattern = match config.index.find('%') {
+            Some(i) => format!("{}*", &config.index[..i]),
+            None => config.index.clone(),
+        };
+        let template_name = match pattern.trim_end_matches(['*', '-', '.', '_']) {
+            "" => "ferrisflow".to_string(),
+            x => x.to_string(),
+        };
+        let mut properties = Map::new();
+        for (field, name) in columns {
+            properties.insert(name.clone(), json!({ "type": mapping_type(field) }));
+        }
+        for name in [TIMESTAMP, FLOW_START, FLOW_END] {
+            properties.insert(name.to_string(), json!({ "type": "date" }));
+        }
+        let template = json!({
+            "index_patterns": [pattern],
+            "priority": 100,
+            "template": {
+                "mappings": {
+                    "dynamic_templates": [{
+                        "strings": {
+                            "match_mapping_type": "string",
+                            "mapping": { "type": "keyword" }
+                        }
+                    }],
+                    "properties": properties
+                }
+            }
+        });
+        Ok(Indexer {
+            config,
+            url,
+            headers,
+            template_name,
+            template,
+            installed: false,
+        })
+    }
+
+    fn send(&mut self, mut documents: Vec<Document>) {
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                thread::sleep(Duration::from_millis(100 << attempt.min(6)));
+            }
+            match self.bulk(&documents) {
+                Ok(retry) if retry.is_empty() => return,
+                Ok(retry) => {
+                    eprintln!("elasticsearch: retrying {} documents", retry.len());
+                    documents = retry;
+                }
+                Err(e) => eprintln!("elasticsearch: {}", e),
+            }
+        }
+        eprintln!(
+            "elasticsearch: dropped {} documents after retries",
+            documents.len()
+        );
+        sink_records("dropped", documents.len());
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        target: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut headers = self.headers.clone();
+        headers.push(("Content-Type", content_type.to_string()));
+        let (status, response) = http::request(
+            method,
+            &self.url,
+            target,
+            &headers,
+            body,
+            self.config.timeout,
+        )?;
+        if !(200..300).contains(&status) {
+            return Err(anyhow!(
+                "{}: {}",
+                status,
+                String::from_utf8_lossy(&response).trim()
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Sends one bulk request and returns the documents to retry, those
+    /// rejected with 429 or a server error. Documents rejected for other
+    /// reasons, e.g. mapping conflicts, would fail again and are dropped.
+    fn bulk(&mut self, documents: &[Document]) -> Result<Vec<Document>> {
+        if self.config.template && !self.installed {
+            let target = format!("/_index_template/{}", self.template_name);
+            let template = serde_json::to_vec(&self.template)?;
+            self.request("PUT", &target, "application/json", &template)?;
+            self.installed = true;
+        }
+        let mut body = Vec::with_capacity(documents.iter().map(|x| x.body.len() + 64).sum());
+        for document in documents {
+            serde_json::to_writer(&mut body, &json!({ "index": { "_index": document.index } }))?;
+            body.push(b'\n');
+            body.extend_from_slice(&document.body);
+            body.push(b'\n');
+        }
+        let response = self.request("POST", "/_bulk", "application/x-ndjson", &body)?;
+        let response: Value = serde_json::from_slice(&response)?;
+        if response["errors"] != Value::Bool(true) {
+            sink_records("sent", documents.len());
+            return Ok(Vec::new());
+        }
+        let items = response["items"]
+            .as_array()
+            .ok_or_else(|| anyhow!("invalid bulk response"))?;
+        let mut retry = Vec::new();
+        let mut rejected = 0;
+        let mut reason = None;
+        for (item, document) in items.iter().zip(documents) {
+            let result = match item.as_object().and_then(|x| x.values().next()) {
+                Some(x) => x,
+                None => continue,
+            };
+            let status = result["status"].as_u64().unwrap_or(0);
+            if status == 429 || status >= 500 {
+                retry.push(document.clone());
+            } else if status >= 300 {
+                rejected += 1;
+                if reason.is_none() {
+                    reason = Some(result["error"]["reason"].to_string());
+                }
+            }
+        }
+        sink_records("sent", documents.len() - retry.len() - rejected);
+        sink_records("rejected", rejected);
+        if rejected > 0 {
+            eprintln!(
+                "elasticsearch: {} documents rejected: {}",
+                rejected,
+                reason.unwrap_or_default()
+            );
+        }
+        Ok(retry)
+    }
+}
+
+fn sink_records(result: &str, count: usize) {
+    metrics::add(
+        "ferrisflow_sink_records_total",
+        &[("sink", "elasticsearch"), ("result", result)],
+        count as u64,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Method and target, and body of a request.
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Answers each request with the next of `responses`, then 200 with
+    /// `{}`, and records every request.
+    fn mock_server(responses: Vec<(u16, Value)>) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_c = requests.clone();
+        thread::spawn(move || {
+            let mut responses = responses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf) {
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|x| x.strip_prefix("Content-Length: "))
+                            .and_then(|x| x.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length || n == 0 {
+                            let target = text.split(' ').take(2).collect::<Vec<&str>>().join(" ");
+                            let body = text[end + 4..].to_string();
+                            requests_c.lock().unwrap().push((target, body));
+                            break;
+                        }
+                    }
+                }
+                let (status, body) = responses.next().unwrap_or((200, json!({})));
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, requests)
+    }
+
+    fn config(url: &str) -> ElasticsearchConfig {
+        ElasticsearchConfig {
+            url: url.to_string(),
+            index: "flows-%Y.%m.%d".to_string(),
+            template: true,
+            user: None,
+            password: None,
+            batch_size: 100,
+            batch_bytes: 1 << 20,
+            linger: Duration::from_millis(10),
+            retries: 2,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn document(index: &str, port: u16) -> Document {
+        Document {
+            index: index.to_string(),
+            body: format!("{{\"src_port\":{}}}", port).into_bytes(),
+        }
+    }
+
+    #[test]
+    fn documents() {
+        let projection = Projection::new("datetime:time,src_addr,bytes").unwrap();
+        let publisher =
+            ElasticsearchPublisher::new(config("http://127.0.0.1:9"), Some(projection)).unwrap();
+        let flowmessage = FlowMessageBuilder::default()
+            .datetime("2021-01-02 03:04:05.678901 UTC")
+            .sys_up_time(10_000u32)
+            .unix_secs(1_609_556_645u32)
+            .first(4000u32)
+            .last(5500u32)
+            .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .build()
+            .unwrap();
+        let document = publisher.document(&flowmessage).unwrap();
+        assert_eq!(document.index, "flows-2021.01.02");
+        let body: Value = serde_json::from_slice(&document.body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "time": "2021-01-02T03:04:05.678Z",
+                "src_addr": "192.0.2.1",
+                "@timestamp": "2021-01-02T03:04:05.678Z",
+                "flow_start": "2021-01-02T03:03:59.000Z",
+                "flow_end": "2021-01-02T03:04:00.500Z"
+            })
+        );
+        publisher.close().unwrap();
+    }
+
+    #[test]
+    fn bulk_retries_failed_items() {
+        let partial = json!({
+            "errors": true,
+            "items": [
+                { "index": { "status": 201 } },
+                { "index": { "status": 429, "error": { "reason": "overloaded" } } },
+                { "index": { "status": 400, "error": { "reason": "mapping" } } },
+                { "index": { "status": 503 } }
+            ]
+        });
+        let (url, requests) = mock_server(vec![
+            (200, json!({ "acknowledged": true })),
+            (200, partial),
+            (200, json!({ "errors": false })),
+        ]);
+        let columns = vec![("src_port".to_string(), "src_port".to_string())];
+        let mut indexer = Indexer::new(config(&url), &columns).unwrap();
+        indexer.send(vec![
+            document("flows-a", 1),
+            document("flows-a", 2),
+            document("flows-b", 3),
+            document("flows-b", 4),
+        ]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].0, "PUT /_index_template/flows");
+        let template: Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(template["index_patterns"], json!(["flows-*"]));
+        assert_eq!(
+            template["template"]["mappings"]["properties"]["src_port"],
+            json!({ "type": "long" })
+        );
+
+        assert_eq!(requests[1].0, "POST /_bulk");
+        assert_eq!(
+            requests[1].1,
+            concat!(
+                "{\"index\":{\"_index\":\"flows-a\"}}\n{\"src_port\":1}\n",
+                "{\"index\":{\"_index\":\"flows-a\"}}\n{\"src_port\":2}\n",
+                "{\"index\":{\"_index\":\"flows-b\"}}\n{\"src_port\":3}\n",
+                "{\"index\":{\"_index\":\"flows-b\"}}\n{\"src_port\":4}\n",
+            )
+        );
+        // Only the overloaded and failed items are sent again.
+        assert_eq!(
+            requests[2].1,
+            concat!(
+                "{\"index\":{\"_index\":\"flows-a\"}}\n{\"src_port\":2}\n",
+                "{\"index\":{\"_index\":\"flows-b\"}}\n{\"src_port\":4}\n",
+            )
+        );
+    }
+}
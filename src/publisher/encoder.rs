@@ -0,0 +1,60 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::super::event::Event;
+use super::super::flowmessage::FlowMessage;
+use super::json::JsonEncoder;
+use super::protobuf::ProtobufEncoder;
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Protobuf,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "json" => Ok(Format::Json),
+            "protobuf" => Ok(Format::Protobuf),
+            _ => Err(anyhow!("unknown format {}", s)),
+        }
+    }
+}
+
+/// The payload format of the publishers that can send either JSON or
+/// protobuf.
+#[derive(Debug, Clone)]
+pub enum Encoder {
+    Json(JsonEncoder),
+    Protobuf(ProtobufEncoder),
+}
+
+impl Encoder {
+    pub fn encode(&self, flowmessage: &FlowMessage) -> Result<Vec<u8>> {
+        match self {
+            Encoder::Json(encoder) => Ok(encoder.encode(flowmessage)?.into_bytes()),
+            Encoder::Protobuf(encoder) => Ok(encoder.encode(flowmessage)),
+        }
+    }
+
+    /// Events have no protobuf schema, so they are only encoded as JSON.
+    pub fn encode_event(&self, event: &Event) -> Result<Option<Vec<u8>>> {
+        match self {
+            Encoder::Json(_) => Ok(Some(serde_json::to_vec(event)?)),
+            Encoder::Protobuf(_) => Ok(None),
+        }
+    }
+}
+
+impl Display for Encoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Encoder::Json(_) => write!(f, "json"),
+            Encoder::Protobuf(_) => write!(f, "protobuf"),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt::Display;
+
+use super::super::event::Event;
+use super::super::filter::Filter;
+use super::super::flowmessage::FlowMessage;
+use super::Publisher;
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct FilteredPublisher {
+    filter: Filter,
+    publisher: Box<dyn Publisher>,
+}
+
+impl FilteredPublisher {
+    pub fn new(filter: Filter, publisher: Box<dyn Publisher>) -> FilteredPublisher {
+        FilteredPublisher { filter, publisher }
+    }
+}
+
+impl Display for FilteredPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}(filter: {})", self.publisher, self.filter)
+    }
+}
+
+impl Publisher for FilteredPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        let flowmessages = flowmessages
+            .iter()
+            .filter(|x| self.filter.matches(x))
+            .cloned()
+            .collect::<Vec<FlowMessage>>();
+        if flowmessages.is_empty() {
+            return Ok(());
+        }
+        self.publisher.publish(&flowmessages)
+    }
+
+    fn publish_events(&self, events: &[Event]) -> Result<()> {
+        self.publisher.publish_events(events)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.publisher.flush()
+    }
+
+    fn close(&self) -> Result<()> {
+        self.publisher.close()
+    }
+}
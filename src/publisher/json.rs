@@ -1,21 +1,115 @@
 use std::fmt::Display;
+use std::sync::{Arc, Mutex};
 
+use super::super::event::Event;
 use super::super::flowmessage::FlowMessage;
+use super::super::projection::Projection;
+use super::rotate::RotatingFile;
 use super::Publisher;
 use anyhow::Result;
+use serde_json::{Map, Value};
 
-#[derive(Debug, Clone)]
-pub struct JsonPublisher {}
+/// Serializes flows as JSON objects, shared by the publishers that emit JSON.
+#[derive(Debug, Clone, Default)]
+pub struct JsonEncoder {
+    projection: Option<Projection>,
+    omit_null: bool,
+}
+
+impl JsonEncoder {
+    pub fn new(projection: Option<Projection>, omit_null: bool) -> JsonEncoder {
+        JsonEncoder {
+            projection,
+            omit_null,
+        }
+    }
+
+    pub fn to_value(&self, flowmessage: &FlowMessage) -> Result<Value> {
+        let mut value = match &self.projection {
+            Some(projection) => {
+                let mut map = Map::new();
+                for (name, value) in projection
+                    .names()
+                    .into_iter()
+                    .zip(projection.values(flowmessage))
+                {
+                    map.insert(name, value.map_or(Value::Null, Value::from));
+                }
+                Value::Object(map)
+            }
+            None => serde_json::to_value(flowmessage)?,
+        };
+        if self.omit_null {
+            if let Value::Object(map) = &mut value {
+                map.retain(|_, v| !v.is_null());
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn encode(&self, flowmessage: &FlowMessage) -> Result<String> {
+        Ok(serde_json::to_string(&self.to_value(flowmessage)?)?)
+    }
+}
+
+/// Prints JSON lines to stdout, or writes them to rotating files.
+#[derive(Clone)]
+pub struct JsonPublisher {
+    encoder: JsonEncoder,
+    file: Option<Arc<Mutex<RotatingFile>>>,
+}
 
 impl JsonPublisher {
-    pub fn new() -> JsonPublisher {
-        JsonPublisher {}
+    pub fn new(projection: Option<Projection>, omit_null: bool) -> JsonPublisher {
+        JsonPublisher {
+            encoder: JsonEncoder::new(projection, omit_null),
+            file: None,
+        }
+    }
+
+    pub fn to_file(
+        projection: Option<Projection>,
+        omit_null: bool,
+        file: RotatingFile,
+    ) -> JsonPublisher {
+        JsonPublisher {
+            encoder: JsonEncoder::new(projection, omit_null),
+            file: Some(Arc::new(Mutex::new(file))),
+        }
+    }
+
+    fn write(&self, lines: Vec<String>) -> Result<()> {
+        match &self.file {
+            Some(file) => {
+                let mut buf = String::new();
+                for line in lines {
+                    buf.push_str(&line);
+                    buf.push('\n');
+                }
+                file.lock().unwrap().write(buf.as_bytes())
+            }
+            None => {
+                for line in lines {
+                    println!("{}", line);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for JsonPublisher {
+    fn default() -> JsonPublisher {
+        JsonPublisher::new(None, false)
     }
 }
 
 impl Display for JsonPublisher {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "JsonPublisher")
+        match &self.file {
+            Some(file) => write!(f, "JsonPublisher({})", file.lock().unwrap().template()),
+            None => write!(f, "JsonPublisher"),
+        }
     }
 }
 
@@ -24,11 +118,65 @@ impl Publisher for JsonPublisher {
         Box::new(self.clone())
     }
 
-    fn publish(&self, flowmessages: &Vec<FlowMessage>) -> Result<()> {
-        for flowmessage in flowmessages {
-            let serialized = serde_json::to_string(flowmessage)?;
-            println!("{}", serialized);
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        let lines = flowmessages
+            .iter()
+            .map(|x| self.encoder.encode(x))
+            .collect::<Result<Vec<String>>>()?;
+        self.write(lines)
+    }
+
+    fn publish_events(&self, events: &[Event]) -> Result<()> {
+        let lines = events
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<serde_json::Result<Vec<String>>>()?;
+        self.write(lines)
+    }
+
+    fn flush(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => file.lock().unwrap().flush(),
+            None => Ok(()),
         }
-        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => file.lock().unwrap().close(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    #[test]
+    fn projection_and_omit_null() {
+        let flowmessage = FlowMessageBuilder::default()
+            .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .d0ctets(1500u32)
+            .build()
+            .unwrap();
+        let projection = Projection::new("src_addr:src,dst_addr,bytes").unwrap();
+
+        let encoder = JsonEncoder::new(Some(projection.clone()), false);
+        assert_eq!(
+            encoder.encode(&flowmessage).unwrap(),
+            r#"{"src":"192.0.2.1","dst_addr":null,"bytes":1500}"#
+        );
+        let encoder = JsonEncoder::new(Some(projection), true);
+        assert_eq!(
+            encoder.encode(&flowmessage).unwrap(),
+            r#"{"src":"192.0.2.1","bytes":1500}"#
+        );
+
+        let value = JsonEncoder::new(None, true).to_value(&flowmessage).unwrap();
+        let map = value.as_object().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["d0ctets"], Value::from(1500));
     }
 }
@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{Cursor, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::super::event::Event;
+use super::super::flowmessage::FlowMessage;
+use super::super::metrics;
+use super::batch::Batcher;
+use super::encoder::Encoder;
+use super::Publisher;
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::write::GzEncoder;
+
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+const PRODUCE_VERSION: i16 = 3;
+const METADATA_VERSION: i16 = 1;
+
+const CLIENT_ID: &str = "ferrisflow";
+const MAX_RESPONSE_SIZE: usize = 64 << 20;
+const QUEUE_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+impl Compression {
+    fn codec(&self) -> i16 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(anyhow!("kafka: unsupported compression {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub brokers: Vec<String>,
+    pub topic: String,
+    /// Flow fields whose values form the message key and so the partition.
+    pub key: Vec<String>,
+    pub acks: i16,
+    pub timeout: Duration,
+    pub compression: Compression,
+    pub retries: usize,
+    pub batch_size: usize,
+    pub linger: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    key: Option<Vec<u8>>,
+    value: Vec<u8>,
+    timestamp: i64,
+}
+
+/// Sends flows and events to a Kafka topic. Records are batched on a
+/// background thread and produced to the partition leaders with the
+/// Kafka protocol directly; keyed records use the murmur2 partitioner of
+/// the Java client, the others are spread round-robin per batch.
+#[derive(Debug, Clone)]
+pub struct KafkaPublisher {
+    topic: String,
+    key: Vec<String>,
+    encoder: Encoder,
+    batcher: Batcher<Record>,
+}
+
+impl KafkaPublisher {
+    pub fn new(config: KafkaConfig, encoder: Encoder) -> Result<KafkaPublisher> {
+        if config.brokers.is_empty() {
+            return Err(anyhow!("kafka: no brokers"));
+        }
+        if let Some(field) = config.key.iter().find(|x| !FlowMessage::has_field(x)) {
+            return Err(anyhow!("kafka: unknown key field {}", field));
+        }
+        let topic = config.topic.clone();
+        let key = config.key.clone();
+        let mut producer = Producer::new(config.clone());
+        let batcher = Batcher::new(
+            "kafka",
+            QUEUE_CAPACITY,
+            config.batch_size,
+            config.linger,
+            move |records| producer.send(records),
+        );
+        Ok(KafkaPublisher {
+            topic,
+            key,
+            encoder,
+            batcher,
+        })
+    }
+
+    fn send(&self, key: Option<Vec<u8>>, value: Vec<u8>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0);
+        self.batcher.send(Record {
+            key,
+            value,
+            timestamp,
+        });
+    }
+}
+
+impl Display for KafkaPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "KafkaPublisher({}, {})", self.topic, self.encoder)
+    }
+}
+
+impl Publisher for KafkaPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        for flowmessage in flowmessages {
+            let key = if self.key.is_empty() {
+                None
+            } else {
+                let values = self
+                    .key
+                    .iter()
+                    .map(|x| {
+                        flowmessage
+                            .field(x)
+                            .map_or(String::new(), |x| x.to_string())
+                    })
+                    .collect::<Vec<String>>();
+                Some(values.join("|").into_bytes())
+            };
+            let value = self.encoder.encode(flowmessage)?;
+            self.send(key, value);
+        }
+        Ok(())
+    }
+
+    fn publish_events(&self, events: &[Event]) -> Result<()> {
+        for event in events {
+            if let Some(value) = self.encoder.encode_event(event)? {
+                self.send(None, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        self.batcher.close();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Partition {
+    id: i32,
+    leader: i32,
+}
+
+/// A minimal Kafka producer: Metadata v1 to find the partition leaders and
+/// Produce v3 with record batch v2.
+#[derive(Debug)]
+struct Producer {
+    config: KafkaConfig,
+    correlation_id: i32,
+    brokers: HashMap<i32, String>,
+    partitions: Vec<Partition>,
+    connections: HashMap<i32, TcpStream>,
+    next_partition: usize,
+}
+
+impl Producer {
+    fn new(config: KafkaConfig) -> Producer {
+        Producer {
+            config,
+            correlation_id: 0,
+            brokers: HashMap::new(),
+            partitions: Vec::new(),
+            connections: HashMap::new(),
+            next_partition: 0,
+        }
+    }
+
+    fn send(&mut self, mut records: Vec<Record>) {
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                thread::sleep(Duration::from_millis(100 << attempt.min(6)));
+            }
+            let count = records.len();
+            match self.produce(records) {
+                Ok(()) => {
+                    sink_records("sent", count);
+                    return;
+                }
+                Err((e, failed)) => {
+                    eprintln!("kafka: {}", e);
+                    sink_records("sent", count - failed.len());
+                    self.partitions.clear();
+                    self.connections.clear();
+                    records = failed;
+                }
+            }
+        }
+        eprintln!("kafka: dropped {} records after retries", records.len());
+        sink_records("dropped", records.len());
+    }
+
+    /// Produces the records and returns the ones that failed on error.
+    fn produce(&mut self, records: Vec<Record>) -> Result<(), (anyhow::Error, Vec<Record>)> {
+        if self.partitions.is_empty() {
+            if let Err(e) = self.refresh_metadata() {
+                return Err((e, records));
+            }
+        }
+        let mut by_partition: HashMap<i32, Vec<Record>> = HashMap::new();
+        let round_robin = self.next_partition;
+        self.next_partition = self.next_partition.wrapping_add(1);
+        for record in records {
+            let index = match &record.key {
+                Some(key) => (murmur2(key) & 0x7fffffff) as usize,
+                None => round_robin,
+            } % self.partitions.len();
+            by_partition
+                .entry(self.partitions[index].id)
+                .or_default()
+                .push(record);
+        }
+        let mut by_leader: HashMap<i32, Vec<(i32, Vec<Record>)>> = HashMap::new();
+        for (partition, records) in by_partition {
+            let leader = self
+                .partitions
+                .iter()
+                .find(|x| x.id == partition)
+                .map_or(-1, |x| x.leader);
+            by_leader
+                .entry(leader)
+                .or_default()
+                .push((partition, records));
+        }
+
+        let mut error = None;
+        let mut failed = Vec::new();
+        for (leader, partitions) in by_leader {
+            match self.produce_to(leader, &partitions) {
+                Ok(errors) => {
+                    for (partition, records) in partitions {
+                        if let Some(code) = errors.get(&partition) {
+                            error = Some(anyhow!("partition {} error code {}", partition, code));
+                            failed.extend(records);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error = Some(e);
+                    failed.extend(partitions.into_iter().flat_map(|(_, records)| records));
+                }
+            }
+        }
+        match error {
+            Some(e) => Err((e, failed)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends one Produce request and returns the error code of each failed partition.
+    fn produce_to(
+        &mut self,
+        leader: i32,
+        partitions: &[(i32, Vec<Record>)],
+    ) -> Result<HashMap<i32, i16>> {
+        let mut body = Vec::new();
+        body.write_i16::<BigEndian>(-1)?;
+        body.write_i16::<BigEndian>(self.config.acks)?;
+        body.write_i32::<BigEndian>(self.config.timeout.as_millis() as i32)?;
+        body.write_i32::<BigEndian>(1)?;
+        write_string(&mut body, &self.config.topic)?;
+        body.write_i32::<BigEndian>(partitions.len() as i32)?;
+        for (partition, records) in partitions {
+            let batch = record_batch(records, self.config.compression)?;
+            body.write_i32::<BigEndian>(*partition)?;
+            body.write_i32::<BigEndian>(batch.len() as i32)?;
+            body.write_all(&batch)?;
+        }
+        let mut errors = HashMap::new();
+        if self.config.acks == 0 {
+            self.request(leader, API_PRODUCE, PRODUCE_VERSION, &body, false)?;
+            return Ok(errors);
+        }
+        let response = self.request(leader, API_PRODUCE, PRODUCE_VERSION, &body, true)?;
+        let mut rdr = Cursor::new(response.as_slice());
+        for _ in 0..rdr.read_i32::<BigEndian>()? {
+            let _topic = read_string(&mut rdr)?;
+            for _ in 0..rdr.read_i32::<BigEndian>()? {
+                let partition = rdr.read_i32::<BigEndian>()?;
+                let error_code = rdr.read_i16::<BigEndian>()?;
+                let _base_offset = rdr.read_i64::<BigEndian>()?;
+                let _log_append_time = rdr.read_i64::<BigEndian>()?;
+                if error_code != 0 {
+                    errors.insert(partition, error_code);
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    fn refresh_metadata(&mut self) -> Result<()> {
+        let mut body = Vec::new();
+        body.write_i32::<BigEndian>(1)?;
+        write_string(&mut body, &self.config.topic)?;
+        let mut last_error = anyhow!("no brokers");
+        for (i, broker) in self.config.brokers.clone().iter().enumerate() {
+            let bootstrap = -1 - i as i32;
+            self.brokers.insert(bootstrap, broker.clone());
+            match self.request(bootstrap, API_METADATA, METADATA_VERSION, &body, true) {
+                Ok(response) => return self.parse_metadata(&response),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn parse_metadata(&mut self, response: &[u8]) -> Result<()> {
+        let mut rdr = Cursor::new(response);
+        for _ in 0..rdr.read_i32::<BigEndian>()? {
+            let node_id = rdr.read_i32::<BigEndian>()?;
+            let host = read_string(&mut rdr)?;
+            let port = rdr.read_i32::<BigEndian>()?;
+            let _rack = read_string(&mut rdr)?;
+            self.brokers.insert(node_id, format!("{}:{}", host, port));
+        }
+        let _controller_id = rdr.read_i32::<BigEndian>()?;
+        let mut partitions = Vec::new();
+        for _ in 0..rdr.read_i32::<BigEndian>()? {
+            let error_code = rdr.read_i16::<BigEndian>()?;
+            let _name = read_string(&mut rdr)?;
+            let _is_internal = rdr.read_u8()?;
+            if error_code != 0 {
+                return Err(anyhow!(
+                    "topic {} error code {}",
+                    self.config.topic,
+                    error_code
+                ));
+            }
+            for _ in 0..rdr.read_i32::<BigEndian>()? {
+                let _error_code = rdr.read_i16::<BigEndian>()?;
+                let id = rdr.read_i32::<BigEndian>()?;
+                let leader = rdr.read_i32::<BigEndian>()?;
+                for _ in 0..rdr.read_i32::<BigEndian>()? {
+                    rdr.read_i32::<BigEndian>()?;
+                }
+                for _ in 0..rdr.read_i32::<BigEndian>()? {
+                    rdr.read_i32::<BigEndian>()?;
+                }
+                partitions.push(Partition { id, leader });
+            }
+        }
+        if partitions.is_empty() {
+            return Err(anyhow!("topic {} has no partitions", self.config.topic));
+        }
+        partitions.sort_by_key(|x| x.id);
+        self.partitions = partitions;
+        Ok(())
+    }
+
+    fn request(
+        &mut self,
+        node: i32,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+        response: bool,
+    ) -> Result<Vec<u8>> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut request = Vec::with_capacity(body.len() + 32);
+        request.write_i32::<BigEndian>(0)?;
+        request.write_i16::<BigEndian>(api_key)?;
+        request.write_i16::<BigEndian>(api_version)?;
+        request.write_i32::<BigEndian>(self.correlation_id)?;
+        write_string(&mut request, CLIENT_ID)?;
+        request.write_all(body)?;
+        let size = (request.len() - 4) as i32;
+        request[..4].copy_from_slice(&size.to_be_bytes());
+
+        if !self.connections.contains_key(&node) {
+            let addr = self
+                .brokers
+                .get(&node)
+                .ok_or_else(|| anyhow!("unknown broker {}", node))?;
+            let stream = TcpStream::connect(addr).map_err(|e| anyhow!("{}: {}", addr, e))?;
+            stream.set_read_timeout(Some(self.config.timeout + Duration::from_secs(5)))?;
+            stream.set_write_timeout(Some(self.config.timeout))?;
+            self.connections.insert(node, stream);
+        }
+        let stream = self.connections.get_mut(&node).unwrap();
+        let result = exchange(stream, &request, self.correlation_id, response);
+        if result.is_err() {
+            self.connections.remove(&node);
+        }
+        result
+    }
+}
+
+fn exchange(
+    stream: &mut TcpStream,
+    request: &[u8],
+    correlation_id: i32,
+    response: bool,
+) -> Result<Vec<u8>> {
+    stream.write_all(request)?;
+    if !response {
+        return Ok(Vec::new());
+    }
+    let size = stream.read_i32::<BigEndian>()? as usize;
+    if !(4..=MAX_RESPONSE_SIZE).contains(&size) {
+        return Err(anyhow!("invalid response size {}", size));
+    }
+    let mut buf = vec![0u8; size];
+    stream.read_exact(&mut buf)?;
+    let received = i32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    if received != correlation_id {
+        return Err(anyhow!(
+            "correlation id mismatch: {} != {}",
+            received,
+            correlation_id
+        ));
+    }
+    buf.drain(..4);
+    Ok(buf)
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    buf.write_i16::<BigEndian>(s.len() as i16)?;
+    buf.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_string(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let len = rdr.read_i16::<BigEndian>()?;
+    if len < 0 {
+        return Ok(String::new());
+    }
+    let mut buf = vec![0u8; len as usize];
+    rdr.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+fn write_varint(buf: &mut Vec<u8>, v: i64) {
+    let mut zigzag = ((v << 1) ^ (v >> 63)) as u64;
+    while zigzag >= 0x80 {
+        buf.push((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    buf.push(zigzag as u8);
+}
+
+fn record_batch(records: &[Record], compression: Compression) -> Result<Vec<u8>> {
+    let first_timestamp = records.iter().map(|x| x.timestamp).min().unwrap_or(0);
+    let max_timestamp = records.iter().map(|x| x.timestamp).max().unwrap_or(0);
+
+    let mut encoded = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let mut body = vec![0u8];
+        write_varint(&mut body, record.timestamp - first_timestamp);
+        write_varint(&mut body, i as i64);
+        match &record.key {
+            Some(key) => {
+                write_varint(&mut body, key.len() as i64);
+                body.extend_from_slice(key);
+            }
+            None => write_varint(&mut body, -1),
+        }
+        write_varint(&mut body, record.value.len() as i64);
+        body.extend_from_slice(&record.value);
+        write_varint(&mut body, 0);
+        write_varint(&mut encoded, body.len() as i64);
+        encoded.extend(body);
+    }
+    if compression == Compression::Gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&encoded)?;
+        encoded = encoder.finish()?;
+    }
+
+    let mut checked = Vec::with_capacity(encoded.len() + 40);
+    checked.write_i16::<BigEndian>(compression.codec())?;
+    checked.write_i32::<BigEndian>(records.len() as i32 - 1)?;
+    checked.write_i64::<BigEndian>(first_timestamp)?;
+    checked.write_i64::<BigEndian>(max_timestamp)?;
+    checked.write_i64::<BigEndian>(-1)?;
+    checked.write_i16::<BigEndian>(-1)?;
+    checked.write_i32::<BigEndian>(-1)?;
+    checked.write_i32::<BigEndian>(records.len() as i32)?;
+    checked.extend(encoded);
+
+    let mut batch = Vec::with_capacity(checked.len() + 21);
+    batch.write_i64::<BigEndian>(0)?;
+    batch.write_i32::<BigEndian>(checked.len() as i32 + 9)?;
+    batch.write_i32::<BigEndian>(-1)?;
+    batch.write_i8(2)?;
+    batch.write_u32::<BigEndian>(crc32c::crc32c(&checked))?;
+    batch.extend(checked);
+    Ok(batch)
+}
+
+/// The murmur2 hash used by the default partitioner of the Java client.
+fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+    let mut h = SEED ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+fn sink_records(result: &str, count: usize) {
+    metrics::add(
+        "ferrisflow_sink_records_total",
+        &[("sink", "kafka"), ("result", result)],
+        count as u64,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    fn record(key: Option<&str>, value: &str, timestamp: i64) -> Record {
+        Record {
+            key: key.map(|x| x.as_bytes().to_vec()),
+            value: value.as_bytes().to_vec(),
+            timestamp,
+        }
+    }
+
+    fn read_varint(rdr: &mut Cursor<&[u8]>) -> i64 {
+        let mut zigzag = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = rdr.read_u8().unwrap();
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64)
+    }
+
+    /// Returns the attributes and the records section of a record batch after
+    /// checking its framing and CRC.
+    fn check_batch(batch: &[u8], count: i32) -> (i16, Vec<u8>) {
+        let mut rdr = Cursor::new(batch);
+        assert_eq!(rdr.read_i64::<BigEndian>().unwrap(), 0);
+        assert_eq!(
+            rdr.read_i32::<BigEndian>().unwrap() as usize,
+            batch.len() - 12
+        );
+        assert_eq!(rdr.read_i32::<BigEndian>().unwrap(), -1);
+        assert_eq!(rdr.read_i8().unwrap(), 2);
+        let crc = rdr.read_u32::<BigEndian>().unwrap();
+        // The CRC covers everything from the attributes to the end of the batch.
+        assert_eq!(crc, crc32c::crc32c(&batch[21..]));
+        let attributes = rdr.read_i16::<BigEndian>().unwrap();
+        assert_eq!(rdr.read_i32::<BigEndian>().unwrap(), count - 1);
+        let _first_timestamp = rdr.read_i64::<BigEndian>().unwrap();
+        let _max_timestamp = rdr.read_i64::<BigEndian>().unwrap();
+        assert_eq!(rdr.read_i64::<BigEndian>().unwrap(), -1);
+        assert_eq!(rdr.read_i16::<BigEndian>().unwrap(), -1);
+        assert_eq!(rdr.read_i32::<BigEndian>().unwrap(), -1);
+        assert_eq!(rdr.read_i32::<BigEndian>().unwrap(), count);
+        (attributes, batch[61..].to_vec())
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c::crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn record_batch_framing() {
+        let records = [
+            record(Some("key"), "first", 1_000),
+            record(None, "second", 1_250),
+        ];
+        let batch = record_batch(&records, Compression::None).unwrap();
+        let (attributes, encoded) = check_batch(&batch, 2);
+        assert_eq!(attributes, 0);
+        // The first and the max timestamp.
+        assert_eq!(
+            &batch[27..43],
+            &[0, 0, 0, 0, 0, 0, 3, 232, 0, 0, 0, 0, 0, 0, 4, 226]
+        );
+
+        let mut rdr = Cursor::new(encoded.as_slice());
+        for (i, (key, value, timestamp_delta)) in [(Some("key"), "first", 0), (None, "second", 250)]
+            .iter()
+            .enumerate()
+        {
+            let length = read_varint(&mut rdr) as u64;
+            let start = rdr.position();
+            assert_eq!(rdr.read_u8().unwrap(), 0);
+            assert_eq!(read_varint(&mut rdr), *timestamp_delta);
+            assert_eq!(read_varint(&mut rdr), i as i64);
+            match key {
+                Some(key) => {
+                    assert_eq!(read_varint(&mut rdr), key.len() as i64);
+                    let mut buf = vec![0u8; key.len()];
+                    rdr.read_exact(&mut buf).unwrap();
+                    assert_eq!(buf, key.as_bytes());
+                }
+                None => assert_eq!(read_varint(&mut rdr), -1),
+            }
+            assert_eq!(read_varint(&mut rdr), value.len() as i64);
+            let mut buf = vec![0u8; value.len()];
+            rdr.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, value.as_bytes());
+            assert_eq!(read_varint(&mut rdr), 0);
+            assert_eq!(rdr.position() - start, length);
+        }
+        assert_eq!(rdr.position() as usize, encoded.len());
+    }
+
+    #[test]
+    fn record_batch_gzip() {
+        let records = [record(Some("key"), "value", 1_000)];
+        let plain = record_batch(&records, Compression::None).unwrap();
+        let batch = record_batch(&records, Compression::Gzip).unwrap();
+        let (attributes, compressed) = check_batch(&batch, 1);
+        assert_eq!(attributes, 1);
+        let mut encoded = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut encoded)
+            .unwrap();
+        assert_eq!(encoded, &plain[61..]);
+    }
+
+    /// The vectors of the Java client's `UtilsTest.testMurmur2`.
+    #[test]
+    fn murmur2_java_vectors() {
+        for (data, hash) in [
+            (&b"21"[..], -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (
+                b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            (b"abc", 479470107),
+        ] {
+            assert_eq!(murmur2(data), hash, "{}", String::from_utf8_lossy(data));
+        }
+    }
+
+    /// Produces to the broker in `KAFKA_BROKER` (default `localhost:9092`),
+    /// which must allow topics to be created automatically.
+    #[test]
+    #[ignore]
+    fn produce_to_local_broker() {
+        let broker = std::env::var("KAFKA_BROKER").unwrap_or_else(|_| "localhost:9092".into());
+        let mut producer = Producer::new(KafkaConfig {
+            brokers: vec![broker],
+            topic: "ferrisflow-test".to_string(),
+            key: Vec::new(),
+            acks: 1,
+            timeout: Duration::from_secs(5),
+            compression: Compression::Gzip,
+            retries: 0,
+            batch_size: 100,
+            linger: Duration::from_millis(10),
+        });
+        let mut records = vec![
+            record(Some("key"), "first", 1_000),
+            record(None, "second", 1_001),
+        ];
+        // The first requests fail while the topic is being created.
+        for _ in 0..10 {
+            match producer.produce(records) {
+                Ok(()) => return,
+                Err((e, failed)) => {
+                    eprintln!("kafka: {}", e);
+                    producer.partitions.clear();
+                    records = failed;
+                }
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        panic!("kafka: {} records not produced", records.len());
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::Display;
 
+use super::event::Event;
 use super::flowmessage::FlowMessage;
 use anyhow::Result;
 
@@ -12,9 +13,53 @@ pub use json::JsonPublisher;
 pub mod csv;
 pub use self::csv::CsvPublisher;
 
+pub mod filter;
+pub use filter::FilteredPublisher;
+
+pub mod batch;
+
+pub mod encoder;
+
+pub mod parquet;
+pub use self::parquet::ParquetPublisher;
+
+pub mod protobuf;
+pub use protobuf::ProtobufPublisher;
+
+pub mod rotate;
+
+pub mod kafka;
+pub use kafka::KafkaPublisher;
+
+pub mod nfcapd;
+pub use self::nfcapd::NfcapdPublisher;
+
+pub mod clickhouse;
+pub use clickhouse::ClickHousePublisher;
+
+pub mod elasticsearch;
+pub use elasticsearch::ElasticsearchPublisher;
+
+pub mod prometheus;
+pub use prometheus::PrometheusPublisher;
+
 pub trait Publisher: Send + Display {
     fn box_clone(&self) -> Box<dyn Publisher>;
-    fn publish(&self, flowmessages: &Vec<FlowMessage>) -> Result<()>;
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()>;
+
+    fn publish_events(&self, _events: &[Event]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called every second, e.g. to rotate files on time.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once on shutdown to finish pending output.
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 impl Clone for Box<dyn Publisher> {
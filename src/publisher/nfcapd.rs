@@ -0,0 +1,141 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::super::flowmessage::FlowMessage;
+use super::super::nfcapd::{Compression, NfcapdWriter};
+use super::super::util::unix_now;
+use super::rotate::{self, Rotation};
+use super::Publisher;
+use anyhow::{anyhow, Result};
+
+struct OpenFile {
+    path: String,
+    temp: PathBuf,
+    period: u64,
+    writer: NfcapdWriter<BufWriter<File>>,
+}
+
+/// Writes flows to nfdump files that nfdump and nfsen read like those of
+/// nfcapd. Each file is written under a hidden name and renamed to its
+/// final path once its period is over.
+#[derive(Clone)]
+pub struct NfcapdPublisher {
+    template: String,
+    rotation: Rotation,
+    compression: Compression,
+    ident: String,
+    hook: Option<String>,
+    file: Arc<Mutex<Option<OpenFile>>>,
+}
+
+impl NfcapdPublisher {
+    /// `template` is a path with strftime escapes, expanded with the start of
+    /// the rotation period, e.g. `/data/nfcapd.%Y%m%d%H%M` as nfcapd names
+    /// its files. `hook` is run for every finished file.
+    pub fn new(
+        template: &str,
+        rotation: Rotation,
+        compression: Compression,
+        ident: &str,
+        hook: Option<String>,
+    ) -> Result<NfcapdPublisher> {
+        rotate::expand_path(template, unix_now())?;
+        Ok(NfcapdPublisher {
+            template: template.to_string(),
+            rotation,
+            compression,
+            ident: ident.to_string(),
+            hook,
+            file: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn open(&self, now: u64) -> Result<OpenFile> {
+        let period = self.rotation.period(now);
+        let path = rotate::expand_path(&self.template, period)?;
+        let temp = rotate::temp_path(&path);
+        rotate::create_parent(&temp)?;
+        let file = File::create(&temp).map_err(|e| anyhow!("{}: {}", temp.display(), e))?;
+        let writer =
+            NfcapdWriter::new(BufWriter::new(file), period, self.compression, &self.ident)?;
+        Ok(OpenFile {
+            path,
+            temp,
+            period,
+            writer,
+        })
+    }
+
+    fn close_file(&self, file: &mut Option<OpenFile>) -> Result<()> {
+        if let Some(OpenFile {
+            path, temp, writer, ..
+        }) = file.take()
+        {
+            writer
+                .finish()
+                .map_err(|e| anyhow!("{}: {}", temp.display(), e))?;
+            let path = rotate::finalize(&temp, &path)?;
+            eprintln!("nfcapd: wrote {}", path);
+            if let Some(hook) = &self.hook {
+                rotate::run_hook(hook, &path);
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate(&self, file: &mut Option<OpenFile>, now: u64) -> Result<()> {
+        if file
+            .as_ref()
+            .is_some_and(|x| self.rotation.expired(x.period, now))
+        {
+            self.close_file(file)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for NfcapdPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "NfcapdPublisher({})", self.template)
+    }
+}
+
+impl Publisher for NfcapdPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        let now = unix_now();
+        let mut file = self.file.lock().unwrap();
+        self.rotate(&mut file, now)?;
+        if file.is_none() {
+            *file = Some(self.open(now)?);
+        }
+        let writer = &mut file.as_mut().unwrap().writer;
+        for flowmessage in flowmessages.iter() {
+            writer.write(flowmessage)?;
+        }
+        Ok(())
+    }
+
+    /// Rotates on time and, as nfcapd does, opens the file of the new
+    /// period even if no flows arrive, so that nfsen finds one per period.
+    fn flush(&self) -> Result<()> {
+        let now = unix_now();
+        let mut file = self.file.lock().unwrap();
+        self.rotate(&mut file, now)?;
+        if file.is_none() {
+            *file = Some(self.open(now)?);
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        self.close_file(&mut file)
+    }
+}
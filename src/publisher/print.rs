@@ -1,21 +1,377 @@
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use super::super::flowmessage::FlowMessage;
+use super::super::decode::protocol_name;
+use super::super::event::Event;
+use super::super::flowmessage::{FieldValue, FlowMessage};
+use super::super::projection::Projection;
+use super::super::util::unix_now;
 use super::Publisher;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+
+const TCP_FLAGS: &[u8; 8] = b"CEUAPRSF";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableColumn {
+    Time,
+    Duration,
+    Proto,
+    Src,
+    Dst,
+    Packets,
+    Bytes,
+    Flags,
+    Field(String),
+}
+
+impl TableColumn {
+    fn parse(name: &str) -> Result<TableColumn> {
+        let column = match name {
+            "time" => TableColumn::Time,
+            "duration" => TableColumn::Duration,
+            "proto" => TableColumn::Proto,
+            "src" => TableColumn::Src,
+            "dst" => TableColumn::Dst,
+            "packets" => TableColumn::Packets,
+            "bytes" => TableColumn::Bytes,
+            "flags" => TableColumn::Flags,
+            field if FlowMessage::has_field(field) => TableColumn::Field(field.to_string()),
+            field => return Err(anyhow!("table: unknown column {}", field)),
+        };
+        Ok(column)
+    }
+
+    fn header(&self) -> &str {
+        match self {
+            TableColumn::Time => "Date first seen",
+            TableColumn::Duration => "Duration",
+            TableColumn::Proto => "Proto",
+            TableColumn::Src => "Src IP Addr:Port",
+            TableColumn::Dst => "Dst IP Addr:Port",
+            TableColumn::Packets => "Packets",
+            TableColumn::Bytes => "Bytes",
+            TableColumn::Flags => "Flags",
+            TableColumn::Field(field) => field,
+        }
+    }
+
+    /// Column width and whether the column is right aligned.
+    fn width(&self) -> (usize, bool) {
+        match self {
+            TableColumn::Time => (23, false),
+            TableColumn::Duration => (9, true),
+            TableColumn::Proto => (5, false),
+            TableColumn::Src | TableColumn::Dst => (21, false),
+            TableColumn::Packets | TableColumn::Bytes => (8, true),
+            TableColumn::Flags => (8, false),
+            TableColumn::Field(field) => (field.len().max(15), false),
+        }
+    }
+
+    /// Metric columns are summed in the top view; the others form its key.
+    fn is_metric(&self) -> bool {
+        matches!(
+            self,
+            TableColumn::Time
+                | TableColumn::Duration
+                | TableColumn::Packets
+                | TableColumn::Bytes
+                | TableColumn::Flags
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    flowmessage: FlowMessage,
+    start: Option<u64>,
+    end: Option<u64>,
+    packets: u64,
+    bytes: u64,
+    flags: Option<u8>,
+    last_seen: u64,
+}
+
+impl Row {
+    fn new(flowmessage: &FlowMessage) -> Row {
+        let (start, end) = match flowmessage.start_end_millis() {
+            Some((start, end)) => (Some(start), Some(end)),
+            None => (None, None),
+        };
+        Row {
+            flowmessage: flowmessage.clone(),
+            start,
+            end,
+            packets: flowmessage.uint("packets").unwrap_or(0),
+            bytes: flowmessage.uint("bytes").unwrap_or(0),
+            flags: flowmessage.tcp_flags,
+            last_seen: unix_now(),
+        }
+    }
+
+    fn merge(&mut self, other: Row) {
+        self.start = self.start.into_iter().chain(other.start).min();
+        self.end = self.end.into_iter().chain(other.end).max();
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+        self.flags = match (self.flags, other.flags) {
+            (Some(x), Some(y)) => Some(x | y),
+            (x, y) => x.or(y),
+        };
+        self.last_seen = other.last_seen;
+    }
+}
+
+#[derive(Debug, Default)]
+struct TableState {
+    header_printed: bool,
+    rows: HashMap<Vec<String>, Row>,
+    last_draw: u64,
+}
+
+/// nfdump-style columnar output, either one line per flow or a live view
+/// of the top flows by bytes redrawn every second.
+#[derive(Debug, Clone)]
+pub struct Table {
+    columns: Vec<TableColumn>,
+    top: Option<usize>,
+    window: u64,
+    state: Arc<Mutex<TableState>>,
+}
+
+impl Table {
+    /// `columns` is a comma separated list of `time`, `duration`, `proto`,
+    /// `src`, `dst`, `packets`, `bytes`, `flags` and any `FlowMessage` field.
+    pub fn new(columns: &str, top: Option<usize>, window: Duration) -> Result<Table> {
+        let columns = columns
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(TableColumn::parse)
+            .collect::<Result<Vec<TableColumn>>>()?;
+        if columns.is_empty() {
+            return Err(anyhow!("table: no columns"));
+        }
+        Ok(Table {
+            columns,
+            top,
+            window: window.as_secs().max(1),
+            state: Arc::new(Mutex::new(TableState::default())),
+        })
+    }
+
+    fn cell(&self, column: &TableColumn, row: &Row) -> String {
+        let flowmessage = &row.flowmessage;
+        match column {
+            TableColumn::Time => match row
+                .start
+                .and_then(|x| DateTime::from_timestamp_millis(x as i64))
+            {
+                Some(start) => start.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+                None => flowmessage
+                    .datetime
+                    .as_deref()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(23)
+                    .collect(),
+            },
+            TableColumn::Duration => match (row.start, row.end) {
+                (Some(start), Some(end)) => format!("{:.3}", (end - start) as f64 / 1000.0),
+                _ => "-".to_string(),
+            },
+            TableColumn::Proto => flowmessage
+                .protocol
+                .map(protocol_name)
+                .unwrap_or_else(|| "-".to_string()),
+            TableColumn::Src => endpoint(flowmessage.field("src_addr"), flowmessage.src_port),
+            TableColumn::Dst => endpoint(flowmessage.field("dst_addr"), flowmessage.dst_port),
+            TableColumn::Packets => scale(row.packets),
+            TableColumn::Bytes => scale(row.bytes),
+            TableColumn::Flags => match row.flags {
+                Some(flags) => TCP_FLAGS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if flags & (0x80 >> i) != 0 {
+                            *c as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect(),
+                None => "-".to_string(),
+            },
+            TableColumn::Field(field) => flowmessage
+                .field(field)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        }
+    }
+
+    fn line(&self, cells: &[String]) -> String {
+        let mut line = String::new();
+        for (i, (column, cell)) in self.columns.iter().zip(cells).enumerate() {
+            if i > 0 {
+                let arrow = *column == TableColumn::Dst && self.columns[i - 1] == TableColumn::Src;
+                line.push_str(if arrow { " -> " } else { " " });
+            }
+            let (width, right) = column.width();
+            if right {
+                line.push_str(&format!("{:>width$}", cell, width = width));
+            } else {
+                line.push_str(&format!("{:<width$}", cell, width = width));
+            }
+        }
+        line.trim_end().to_string()
+    }
+
+    fn header(&self) -> String {
+        let cells = self
+            .columns
+            .iter()
+            .map(|x| x.header().to_string())
+            .collect::<Vec<String>>();
+        self.line(&cells)
+    }
+
+    fn row(&self, row: &Row) -> String {
+        let cells = self
+            .columns
+            .iter()
+            .map(|x| self.cell(x, row))
+            .collect::<Vec<String>>();
+        self.line(&cells)
+    }
+
+    fn key(&self, row: &Row) -> Vec<String> {
+        self.columns
+            .iter()
+            .filter(|x| !x.is_metric())
+            .map(|x| self.cell(x, row))
+            .collect()
+    }
+
+    fn print(&self, flowmessages: &[FlowMessage]) {
+        let mut state = self.state.lock().unwrap();
+        if !state.header_printed {
+            println!("{}", self.header());
+            state.header_printed = true;
+        }
+        for flowmessage in flowmessages {
+            println!("{}", self.row(&Row::new(flowmessage)));
+        }
+    }
+
+    fn update_top(&self, flowmessages: &[FlowMessage]) {
+        let mut state = self.state.lock().unwrap();
+        for flowmessage in flowmessages {
+            let row = Row::new(flowmessage);
+            match state.rows.get_mut(&self.key(&row)) {
+                Some(x) => x.merge(row),
+                None => {
+                    state.rows.insert(self.key(&row), row);
+                }
+            }
+        }
+    }
+
+    /// Redraws the top view at most once a second, also while no flows
+    /// arrive so that idle rows age out of the window.
+    fn draw_top(&self, n: usize) {
+        let now = unix_now();
+        let mut state = self.state.lock().unwrap();
+        if state.last_draw >= now {
+            return;
+        }
+        state.last_draw = now;
+        state.rows.retain(|_, x| x.last_seen + self.window > now);
+
+        let mut rows = state.rows.values().collect::<Vec<&Row>>();
+        rows.sort_by_key(|x| std::cmp::Reverse(x.bytes));
+        let mut screen = format!(
+            "\x1b[2J\x1b[Hferrisflow top {} by bytes, {} active in the last {}s, {}\n\n{}\n",
+            n,
+            rows.len(),
+            self.window,
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            self.header()
+        );
+        for row in rows.into_iter().take(n) {
+            screen.push_str(&self.row(row));
+            screen.push('\n');
+        }
+        print!("{}", screen);
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let columns = self
+            .columns
+            .iter()
+            .map(|x| match x {
+                TableColumn::Field(field) => field.clone(),
+                column => format!("{:?}", column).to_lowercase(),
+            })
+            .collect::<Vec<String>>();
+        match self.top {
+            Some(n) => write!(f, "top {}: {}", n, columns.join(",")),
+            None => write!(f, "table: {}", columns.join(",")),
+        }
+    }
+}
+
+fn endpoint(addr: Option<FieldValue>, port: Option<u16>) -> String {
+    let port = port.map_or("-".to_string(), |x| x.to_string());
+    match addr {
+        Some(FieldValue::Ip(IpAddr::V6(addr))) => format!("[{}]:{}", addr, port),
+        Some(addr) => format!("{}:{}", addr, port),
+        None => "-".to_string(),
+    }
+}
+
+/// Scales large counters like nfdump, e.g. `1.5 M`.
+fn scale(value: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [
+        (1_000_000_000_000, "T"),
+        (1_000_000_000, "G"),
+        (1_000_000, "M"),
+    ];
+    match UNITS.iter().find(|(unit, _)| value >= *unit) {
+        Some((unit, name)) => format!("{:.1} {}", value as f64 / *unit as f64, name),
+        None => value.to_string(),
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct PrintPublisher {}
+pub struct PrintPublisher {
+    projection: Option<Projection>,
+    table: Option<Table>,
+}
 
 impl PrintPublisher {
-    pub fn new() -> PrintPublisher {
-        PrintPublisher {}
+    pub fn new(projection: Option<Projection>, table: Option<Table>) -> PrintPublisher {
+        PrintPublisher { projection, table }
+    }
+}
+
+impl Default for PrintPublisher {
+    fn default() -> PrintPublisher {
+        PrintPublisher::new(None, None)
     }
 }
 
 impl Display for PrintPublisher {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "PrintPublisher")
+        match &self.table {
+            Some(table) => write!(f, "PrintPublisher({})", table),
+            None => write!(f, "PrintPublisher"),
+        }
     }
 }
 
@@ -24,8 +380,134 @@ impl Publisher for PrintPublisher {
         Box::new(self.clone())
     }
 
-    fn publish(&self, flowmessages: &Vec<FlowMessage>) -> Result<()> {
-        println!("{:?}", flowmessages);
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        if let Some(table) = &self.table {
+            match table.top {
+                Some(_) => table.update_top(flowmessages),
+                None => table.print(flowmessages),
+            }
+            return Ok(());
+        }
+        match &self.projection {
+            Some(projection) => {
+                for flowmessage in flowmessages {
+                    let line = projection
+                        .names()
+                        .into_iter()
+                        .zip(projection.values(flowmessage))
+                        .filter_map(|(name, value)| value.map(|x| format!("{}={}", name, x)))
+                        .collect::<Vec<String>>();
+                    println!("{}", line.join(" "));
+                }
+            }
+            None => println!("{:?}", flowmessages),
+        }
+        Ok(())
+    }
+
+    fn publish_events(&self, events: &[Event]) -> Result<()> {
+        if self.table.as_ref().is_some_and(|x| x.top.is_some()) {
+            return Ok(());
+        }
+        println!("{:?}", events);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        if let Some(table) = &self.table {
+            if let Some(n) = table.top {
+                table.draw_top(n);
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+
+    fn flow(dst: IpAddr, bytes: u32, flags: u8) -> FlowMessage {
+        let mut builder = FlowMessageBuilder::default();
+        match dst {
+            IpAddr::V4(x) => builder
+                .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+                .ipv4_dst_addr(x),
+            IpAddr::V6(x) => builder
+                .ipv6_src_addr("2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap())
+                .ipv6_dst_addr(x),
+        };
+        builder
+            .sys_up_time(10_000u32)
+            .unix_secs(1_600_000_000u32)
+            .first(4000u32)
+            .last(5500u32)
+            .protocol(6u8)
+            .src_port(40000u16)
+            .dst_port(443u16)
+            .dpkts(3u32)
+            .d0ctets(bytes)
+            .tcp_flags(flags)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn rows() {
+        let table = Table::new(
+            "time,duration,proto,src,dst,packets,bytes,flags,dst_port",
+            None,
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        assert_eq!(
+            table.header(),
+            concat!(
+                "Date first seen          Duration Proto Src IP Addr:Port      -> Dst IP Addr:Port",
+                "       Packets    Bytes Flags    dst_port"
+            )
+        );
+        let flowmessage = flow("198.51.100.1".parse().unwrap(), 2_500_000, 0x12);
+        assert_eq!(
+            table.row(&Row::new(&flowmessage)),
+            concat!(
+                "2020-09-13 12:26:34.000     1.500 TCP   192.0.2.1:40000       -> 198.51.100.1:443",
+                "             3    2.5 M ...A..S. 443"
+            )
+        );
+        let flowmessage = flow("2001:db8::2".parse().unwrap(), 999, 0);
+        assert_eq!(
+            table.row(&Row::new(&flowmessage)),
+            concat!(
+                "2020-09-13 12:26:34.000     1.500 TCP   [2001:db8::1]:40000   -> [2001:db8::2]:443",
+                "            3      999 ........ 443"
+            )
+        );
+        assert!(Table::new("src,nope", None, Duration::from_secs(10)).is_err());
+    }
+
+    #[test]
+    fn top_merges_rows() {
+        let table = Table::new(
+            "src,dst,packets,bytes,flags",
+            Some(10),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        let dst = "198.51.100.1".parse().unwrap();
+        table.update_top(&[flow(dst, 1000, 0x02), flow(dst, 2000, 0x10)]);
+        table.update_top(&[flow("198.51.100.2".parse().unwrap(), 500, 0x02)]);
+
+        let state = table.state.lock().unwrap();
+        assert_eq!(state.rows.len(), 2);
+        let row = &state.rows[&vec![
+            "192.0.2.1:40000".to_string(),
+            "198.51.100.1:443".to_string(),
+        ]];
+        assert_eq!(
+            table.row(row),
+            "192.0.2.1:40000       -> 198.51.100.1:443             6     3000 ...A..S."
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use super::super::flowkey::FlowKey;
+use super::super::flowmessage::FlowMessage;
+use super::super::metrics::escape;
+use super::Publisher;
+use anyhow::{anyhow, Result};
+
+/// The label value of the series that flows are counted in once a label
+/// set has reached its series limit.
+const OTHER: &str = "other";
+
+/// The label sets that are always counted, before the configured ones.
+pub const DEFAULT_LABEL_SETS: [&str; 4] = [
+    "exporter=exporter",
+    "in_interface=exporter,in_if",
+    "out_interface=exporter,out_if",
+    "protocol=exporter,proto",
+];
+
+/// A set of flow fields that counters are broken down by, written
+/// `name=fields` such as `dst_port=exporter,dst_port`, or just the fields,
+/// in which case the name is made of the label names. A field may be given
+/// its label name as `label:field`; otherwise the label is the field name
+/// with characters Prometheus does not allow replaced by `_`.
+#[derive(Debug, Clone)]
+pub struct LabelSet {
+    pub name: String,
+    pub labels: Vec<String>,
+    pub key: FlowKey,
+}
+
+/// Whether `name` matches `[a-zA-Z_][a-zA-Z0-9_]*`.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(x) if x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+impl FromStr for LabelSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<LabelSet> {
+        let (name, spec) = match s.split_once('=') {
+            Some((name, spec)) => (Some(name.trim().to_string()), spec),
+            None => (None, s),
+        };
+        let mut labels = Vec::new();
+        let mut fields = Vec::new();
+        for item in spec.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            let (label, field) = match item.split_once(':') {
+                Some((label, field)) => (label.trim().to_string(), field.trim()),
+                None => {
+                    let field = item.split('/').next().unwrap_or_default();
+                    let label = field
+                        .chars()
+                        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+                        .collect();
+                    (label, item)
+                }
+            };
+            if !is_label_name(&label) || label.starts_with("__") {
+                return Err(anyhow!("prometheus: invalid label name {}", label));
+            }
+            if labels.contains(&label) {
+                return Err(anyhow!("prometheus: duplicate label name {}", label));
+            }
+            labels.push(label);
+            fields.push(field);
+        }
+        let key = FlowKey::new(&fields.join(","))?;
+        let name = name.unwrap_or_else(|| labels.join("_"));
+        if name.is_empty() || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_') {
+            return Err(anyhow!("prometheus: invalid label set name {}", name));
+        }
+        Ok(LabelSet { name, labels, key })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    bytes: u64,
+    packets: u64,
+    flows: u64,
+}
+
+#[derive(Debug)]
+struct Family {
+    label_set: LabelSet,
+    series: HashMap<Vec<String>, Counters>,
+}
+
+/// Counts bytes, packets and flows per label set and serves them in the
+/// Prometheus text format. Each label set holds at most `max_series`
+/// series; flows with new label values beyond that are counted in a series
+/// whose labels are all `other`, so memory and scrape size stay bounded.
+#[derive(Debug, Clone)]
+pub struct PrometheusPublisher {
+    max_series: usize,
+    families: Arc<Mutex<Vec<Family>>>,
+}
+
+impl PrometheusPublisher {
+    pub fn new(label_sets: Vec<LabelSet>, max_series: usize) -> Result<PrometheusPublisher> {
+        let mut names = label_sets.iter().map(|x| &x.name).collect::<Vec<_>>();
+        names.sort();
+        if let Some(name) = names.windows(2).find(|x| x[0] == x[1]) {
+            return Err(anyhow!("prometheus: duplicate label set {}", name[0]));
+        }
+        let families = label_sets
+            .into_iter()
+            .map(|label_set| Family {
+                label_set,
+                series: HashMap::new(),
+            })
+            .collect();
+        Ok(PrometheusPublisher {
+            max_series: max_series.max(1),
+            families: Arc::new(Mutex::new(families)),
+        })
+    }
+
+    /// Renders the counters in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut body = String::new();
+        for family in families.iter() {
+            let labels = &family.label_set.labels;
+            let mut series = family.series.iter().collect::<Vec<_>>();
+            series.sort_by(|a, b| a.0.cmp(b.0));
+            for (metric, help, counter) in [
+                (
+                    "bytes",
+                    "Bytes",
+                    (|x: &Counters| x.bytes) as fn(&Counters) -> u64,
+                ),
+                ("packets", "Packets", |x: &Counters| x.packets),
+                ("flows", "Flows", |x: &Counters| x.flows),
+            ] {
+                let name = format!("ferrisflow_{}_{}_total", family.label_set.name, metric);
+                let _ = writeln!(body, "# HELP {} {} by {}.", name, help, labels.join(", "));
+                let _ = writeln!(body, "# TYPE {} counter", name);
+                for (values, counters) in series.iter() {
+                    let pairs = labels
+                        .iter()
+                        .zip(values.iter())
+                        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                        .collect::<Vec<String>>();
+                    let _ = writeln!(
+                        body,
+                        "{}{{{}}} {}",
+                        name,
+                        pairs.join(","),
+                        counter(counters)
+                    );
+                }
+            }
+        }
+        body
+    }
+}
+
+impl Display for PrometheusPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let families = self.families.lock().unwrap();
+        let names = families
+            .iter()
+            .map(|x| x.label_set.name.clone())
+            .collect::<Vec<String>>();
+        write!(f, "PrometheusPublisher({})", names.join(", "))
+    }
+}
+
+impl Publisher for PrometheusPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        let mut families = self.families.lock().unwrap();
+        for flowmessage in flowmessages {
+            let bytes = flowmessage.uint("bytes").unwrap_or(0);
+            let packets = flowmessage.uint("packets").unwrap_or(0);
+            let flows = flowmessage.uint("flows").unwrap_or(1);
+            for family in families.iter_mut() {
+                let mut values = family
+                    .label_set
+                    .key
+                    .values(flowmessage)
+                    .into_iter()
+                    .map(|x| x.map(|x| x.to_string()).unwrap_or_default())
+                    .collect::<Vec<String>>();
+                if !family.series.contains_key(&values) && family.series.len() >= self.max_series {
+                    values = vec![OTHER.to_string(); values.len()];
+                }
+                let counters = family.series.entry(values).or_default();
+                counters.bytes += bytes;
+                counters.packets += packets;
+                counters.flows += flows;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_names() {
+        let label_set = LabelSet::from_str("exporter,src_tag.customer").unwrap();
+        assert_eq!(label_set.labels, ["exporter", "src_tag_customer"]);
+        assert_eq!(label_set.name, "exporter_src_tag_customer");
+        let label_set =
+            LabelSet::from_str("nets=customer:src_tag.customer,net:dst_addr/24/48").unwrap();
+        assert_eq!(label_set.labels, ["customer", "net"]);
+        assert_eq!(label_set.key.fields[1].v4_len, Some(24));
+        assert!(LabelSet::from_str("x=1customer:src_tag.customer").is_err());
+        assert!(LabelSet::from_str("x=__customer:src_tag.customer").is_err());
+        assert!(LabelSet::from_str("x=proto,proto").is_err());
+    }
+}
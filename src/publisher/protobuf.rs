@@ -0,0 +1,568 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::flowmessage::{FieldValue, FlowMessage, FIELD_TYPES};
+use super::super::metrics;
+use super::batch::Batcher;
+use super::Publisher;
+use anyhow::{anyhow, Result};
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LEN: u32 = 2;
+
+const ETYPE_IPV4: u64 = 0x800;
+const ETYPE_IPV6: u64 = 0x86dd;
+
+const QUEUE_CAPACITY: usize = 100_000;
+const BATCH_SIZE: usize = 1000;
+const LINGER: Duration = Duration::from_millis(100);
+/// How long connecting to and writing to a TCP receiver may take.
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtobufMode {
+    /// `proto/ferrisflow.proto`, carrying every field.
+    Native,
+    /// The goflow2 v2 `FlowMessage` in `proto/goflow2.proto`.
+    Goflow2,
+}
+
+impl FromStr for ProtobufMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ProtobufMode> {
+        match s {
+            "native" => Ok(ProtobufMode::Native),
+            "goflow2" => Ok(ProtobufMode::Goflow2),
+            _ => Err(anyhow!("protobuf: unknown mode {}", s)),
+        }
+    }
+}
+
+impl Display for ProtobufMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProtobufMode::Native => write!(f, "native"),
+            ProtobufMode::Goflow2 => write!(f, "goflow2"),
+        }
+    }
+}
+
+/// Serializes flows as length-delimited protobuf messages.
+#[derive(Debug, Clone, Copy)]
+pub struct ProtobufEncoder {
+    mode: ProtobufMode,
+}
+
+impl ProtobufEncoder {
+    pub fn new(mode: ProtobufMode) -> ProtobufEncoder {
+        ProtobufEncoder { mode }
+    }
+
+    /// Encodes one message preceded by its length as a varint.
+    pub fn encode(&self, flowmessage: &FlowMessage) -> Vec<u8> {
+        let message = match self.mode {
+            ProtobufMode::Native => native(flowmessage),
+            ProtobufMode::Goflow2 => goflow2(flowmessage),
+        };
+        let mut buf = Vec::with_capacity(message.len() + 2);
+        write_varint(&mut buf, message.len() as u64);
+        buf.extend(message);
+        buf
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, number: u32, wire: u32) {
+    write_varint(buf, ((number << 3) | wire) as u64);
+}
+
+fn write_uint(buf: &mut Vec<u8>, number: u32, v: u64) {
+    write_key(buf, number, WIRE_VARINT);
+    write_varint(buf, v);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, number: u32, v: &[u8]) {
+    write_key(buf, number, WIRE_LEN);
+    write_varint(buf, v.len() as u64);
+    buf.extend_from_slice(v);
+}
+
+fn write_double(buf: &mut Vec<u8>, number: u32, v: f64) {
+    write_key(buf, number, WIRE_FIXED64);
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn write_packed(buf: &mut Vec<u8>, number: u32, values: &[u64]) {
+    if values.is_empty() {
+        return;
+    }
+    let mut packed = Vec::new();
+    for v in values {
+        write_varint(&mut packed, *v);
+    }
+    write_bytes(buf, number, &packed);
+}
+
+fn ip_bytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(x) => x.octets().to_vec(),
+        IpAddr::V6(x) => x.octets().to_vec(),
+    }
+}
+
+/// Encodes `proto/ferrisflow.proto`, where each field is numbered by its
+/// position in `FIELD_TYPES`.
+fn native(flowmessage: &FlowMessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (i, (name, field_type)) in FIELD_TYPES.iter().enumerate() {
+        let number = i as u32 + 1;
+        let value = match flowmessage.field(name) {
+            Some(x) => x,
+            None => continue,
+        };
+        match (field_type, value) {
+            (_, FieldValue::Uint(x)) => write_uint(&mut buf, number, x),
+            (_, FieldValue::Ip(x)) => write_bytes(&mut buf, number, &ip_bytes(&x)),
+            (_, FieldValue::Float(x)) => write_double(&mut buf, number, x.0),
+            (_, x) => write_bytes(&mut buf, number, x.to_string().as_bytes()),
+        }
+    }
+    buf
+}
+
+fn goflow2(flowmessage: &FlowMessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let uint = |name: &str| flowmessage.uint(name).filter(|x| *x != 0);
+    let ip = |name: &str| match flowmessage.field(name) {
+        Some(FieldValue::Ip(x)) => Some(x),
+        _ => None,
+    };
+
+    let flow_type = match flowmessage.version {
+        Some(5) => 2,
+        Some(9) => 3,
+        Some(10) => 4,
+        _ => 0,
+    };
+    if flow_type != 0 {
+        write_uint(&mut buf, 1, flow_type);
+    }
+    if let Some(x) = uint("sampling_interval") {
+        write_uint(&mut buf, 3, x);
+    }
+    if let Some(x) = uint("flow_sequence") {
+        write_uint(&mut buf, 4, x);
+    }
+    let src_addr = ip("src_addr");
+    if let Some(x) = &src_addr {
+        write_bytes(&mut buf, 6, &ip_bytes(x));
+    }
+    if let Some(x) = ip("dst_addr") {
+        write_bytes(&mut buf, 7, &ip_bytes(&x));
+    }
+    if let Some(x) = uint("bytes") {
+        write_uint(&mut buf, 9, x);
+    }
+    if let Some(x) = uint("packets") {
+        write_uint(&mut buf, 10, x);
+    }
+    if let Some(x) = ip("exporter") {
+        write_bytes(&mut buf, 11, &ip_bytes(&x));
+    }
+    if let Some(x) = ip("next_hop") {
+        write_bytes(&mut buf, 12, &ip_bytes(&x));
+    }
+    let fields = [
+        (14, "src_as"),
+        (15, "dst_as"),
+        (20, "protocol"),
+        (21, "src_port"),
+        (22, "dst_port"),
+        (23, "tos"),
+        (26, "tcp_flags"),
+        (27, "src_mac"),
+        (28, "dst_mac"),
+        (33, "src_vlan"),
+        (34, "dst_vlan"),
+        (37, "ipv6_flow_label"),
+    ];
+    for (number, name) in fields.iter() {
+        if let Some(x) = uint(name) {
+            write_uint(&mut buf, *number, x);
+        }
+    }
+    let alternatives = [
+        (16, "src_mask", "ipv6_src_mask"),
+        (17, "dst_mask", "ipv6_dst_mask"),
+        (18, "input", "input_snmp"),
+        (19, "output", "output_snmp"),
+    ];
+    for (number, a, b) in alternatives.iter() {
+        if let Some(x) = uint(a).or_else(|| uint(b)) {
+            write_uint(&mut buf, *number, x);
+        }
+    }
+    match src_addr {
+        Some(IpAddr::V4(_)) => write_uint(&mut buf, 30, ETYPE_IPV4),
+        Some(IpAddr::V6(_)) => write_uint(&mut buf, 30, ETYPE_IPV6),
+        None => {}
+    }
+    if let Some(x) = uint("icmp_type") {
+        write_uint(&mut buf, 31, x >> 8);
+        write_uint(&mut buf, 32, x & 0xff);
+    }
+    let labels = (1..=10)
+        .filter_map(|i| uint(&format!("mpls_label_{}", i)))
+        .map(|x| x >> 4)
+        .collect::<Vec<u64>>();
+    write_packed(&mut buf, 81, &labels);
+    if let Some(x) = ip("bgp_ipv4_next_hop").or_else(|| ip("bgp_ipv6_next_hop")) {
+        write_bytes(&mut buf, 100, &ip_bytes(&x));
+    }
+    if let Some(communities) = &flowmessage.dst_communities {
+        let communities = communities
+            .split_whitespace()
+            .filter_map(|x| x.split_once(':'))
+            .filter_map(|(a, b)| Some((u64::from_str(a).ok()? << 16) | u64::from_str(b).ok()?))
+            .collect::<Vec<u64>>();
+        write_packed(&mut buf, 101, &communities);
+    }
+    if let Some(as_path) = &flowmessage.dst_as_path {
+        let as_path = as_path
+            .split_whitespace()
+            .filter_map(|x| u64::from_str(x).ok())
+            .collect::<Vec<u64>>();
+        write_packed(&mut buf, 102, &as_path);
+    }
+    let received = flowmessage.received_nanos();
+    if let Some(x) = received {
+        write_uint(&mut buf, 110, x as u64);
+    }
+    if let Some((start, end)) = flowmessage.start_end_millis() {
+        write_uint(&mut buf, 111, start * 1_000_000);
+        write_uint(&mut buf, 112, end * 1_000_000);
+    }
+    buf
+}
+
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File(BufWriter<File>),
+    Tcp(String, Option<TcpStream>),
+    Udp(UdpSocket, String),
+}
+
+impl Output {
+    fn open(target: &str) -> Result<Output> {
+        if target == "-" {
+            Ok(Output::Stdout)
+        } else if let Some(addr) = target.strip_prefix("tcp://") {
+            Ok(Output::Tcp(addr.to_string(), None))
+        } else if let Some(addr) = target.strip_prefix("udp://") {
+            Ok(Output::Udp(UdpSocket::bind("0.0.0.0:0")?, addr.to_string()))
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(target)
+                .map_err(|e| anyhow!("protobuf: {}: {}", target, e))?;
+            Ok(Output::File(BufWriter::new(file)))
+        }
+    }
+
+    fn is_socket(&self) -> bool {
+        matches!(self, Output::Tcp(..) | Output::Udp(..))
+    }
+
+    fn write(&mut self, messages: &[Vec<u8>]) -> Result<()> {
+        match self {
+            Output::Stdout => {
+                let stdout = io::stdout();
+                let mut lock = stdout.lock();
+                for message in messages {
+                    lock.write_all(message)?;
+                }
+                lock.flush()?;
+            }
+            Output::File(file) => {
+                for message in messages {
+                    file.write_all(message)?;
+                }
+                file.flush()?;
+            }
+            Output::Tcp(addr, stream) => {
+                if stream.is_none() {
+                    *stream = Some(connect(addr)?);
+                }
+                let result = messages
+                    .iter()
+                    .try_for_each(|x| stream.as_mut().unwrap().write_all(x));
+                if let Err(e) = result {
+                    *stream = None;
+                    return Err(e.into());
+                }
+            }
+            Output::Udp(socket, addr) => {
+                for message in messages {
+                    socket.send_to(message, addr.as_str())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn connect(addr: &str) -> Result<TcpStream> {
+    let mut last_error = anyhow!("{}: no addresses", addr);
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, TCP_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e.into(),
+        }
+    }
+    Err(last_error)
+}
+
+#[derive(Debug, Clone)]
+enum Sink {
+    Direct(Arc<Mutex<Output>>),
+    /// Sockets are written on a background thread, so that a slow or
+    /// unreachable receiver never blocks the collector.
+    Queued(Batcher<Vec<u8>>),
+}
+
+/// Writes length-delimited protobuf flows to stdout, a file, a TCP
+/// connection or UDP datagrams of one message each.
+#[derive(Debug, Clone)]
+pub struct ProtobufPublisher {
+    target: String,
+    encoder: ProtobufEncoder,
+    sink: Sink,
+}
+
+impl ProtobufPublisher {
+    /// `target` is `-` for stdout, `tcp://host:port`, `udp://host:port` or a
+    /// file path that is appended to.
+    pub fn new(target: &str, encoder: ProtobufEncoder) -> Result<ProtobufPublisher> {
+        let mut output = Output::open(target)?;
+        let sink = if output.is_socket() {
+            let target = target.to_string();
+            Sink::Queued(Batcher::new(
+                "protobuf",
+                QUEUE_CAPACITY,
+                BATCH_SIZE,
+                LINGER,
+                move |messages| match output.write(&messages) {
+                    Ok(()) => sink_records("sent", messages.len()),
+                    Err(e) => {
+                        eprintln!("protobuf: {}: {}", target, e);
+                        sink_records("dropped", messages.len());
+                    }
+                },
+            ))
+        } else {
+            Sink::Direct(Arc::new(Mutex::new(output)))
+        };
+        Ok(ProtobufPublisher {
+            target: target.to_string(),
+            encoder,
+            sink,
+        })
+    }
+}
+
+impl Display for ProtobufPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ProtobufPublisher({}, {})",
+            self.encoder.mode, self.target
+        )
+    }
+}
+
+impl Publisher for ProtobufPublisher {
+    fn box_clone(&self) -> Box<dyn Publisher> {
+        Box::new(self.clone())
+    }
+
+    fn publish(&self, flowmessages: &[FlowMessage]) -> Result<()> {
+        let messages = flowmessages.iter().map(|x| self.encoder.encode(x));
+        match &self.sink {
+            Sink::Direct(output) => output
+                .lock()
+                .unwrap()
+                .write(&messages.collect::<Vec<Vec<u8>>>())
+                .map_err(|e| anyhow!("protobuf: {}: {}", self.target, e)),
+            Sink::Queued(batcher) => {
+                for message in messages {
+                    batcher.send(message);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        if let Sink::Queued(batcher) = &self.sink {
+            batcher.close();
+        }
+        Ok(())
+    }
+}
+
+fn sink_records(result: &str, count: usize) {
+    metrics::add(
+        "ferrisflow_sink_records_total",
+        &[("sink", "protobuf"), ("result", result)],
+        count as u64,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::flowmessage::FlowMessageBuilder;
+    use super::*;
+    use std::convert::TryInto;
+
+    #[derive(Debug, PartialEq)]
+    enum Wire {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+        let mut v = 0;
+        let mut shift = 0;
+        loop {
+            let b = buf[*pos];
+            *pos += 1;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b < 0x80 {
+                return v;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Splits a length-delimited message into its fields.
+    fn decode(buf: &[u8]) -> Vec<(u32, Wire)> {
+        let mut pos = 0;
+        let length = read_varint(buf, &mut pos) as usize;
+        assert_eq!(buf.len(), pos + length);
+        let mut fields = Vec::new();
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos);
+            let value = match key as u32 & 7 {
+                WIRE_VARINT => Wire::Varint(read_varint(buf, &mut pos)),
+                WIRE_FIXED64 => {
+                    pos += 8;
+                    Wire::Fixed64(u64::from_le_bytes(buf[pos - 8..pos].try_into().unwrap()))
+                }
+                WIRE_LEN => {
+                    let length = read_varint(buf, &mut pos) as usize;
+                    pos += length;
+                    Wire::Bytes(buf[pos - length..pos].to_vec())
+                }
+                x => panic!("wire type {}", x),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    fn flow() -> FlowMessage {
+        FlowMessageBuilder::default()
+            .version(9u16)
+            .exporter_addr("10.0.0.1:2055".parse::<std::net::SocketAddr>().unwrap())
+            .flow_sequence(7u32)
+            .ipv4_src_addr("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .ipv4_dst_addr("198.51.100.1".parse::<std::net::Ipv4Addr>().unwrap())
+            .in_bytes(1500usize)
+            .in_pkts(3usize)
+            .protocol(1u8)
+            .src_port(0u16)
+            .icmp_type(0x0301u16)
+            .input_snmp(2usize)
+            .mpls_label_1(0x12345u32)
+            .dst_as_path("64500 64501")
+            .dst_communities("64500:100")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn goflow2_field_numbers() {
+        let fields = decode(&ProtobufEncoder::new(ProtobufMode::Goflow2).encode(&flow()));
+        assert_eq!(
+            fields,
+            vec![
+                (1, Wire::Varint(3)),
+                (4, Wire::Varint(7)),
+                (6, Wire::Bytes(vec![192, 0, 2, 1])),
+                (7, Wire::Bytes(vec![198, 51, 100, 1])),
+                (9, Wire::Varint(1500)),
+                (10, Wire::Varint(3)),
+                (11, Wire::Bytes(vec![10, 0, 0, 1])),
+                (20, Wire::Varint(1)),
+                (18, Wire::Varint(2)),
+                (30, Wire::Varint(ETYPE_IPV4)),
+                (31, Wire::Varint(3)),
+                (32, Wire::Varint(1)),
+                // Packed varints: label 0x1234 without the EXP and bottom of
+                // stack bits, community 64500:100 and AS path 64500 64501.
+                (81, Wire::Bytes(vec![0xb4, 0x24])),
+                (101, Wire::Bytes(vec![0xe4, 0x80, 0xd0, 0xdf, 0x0f])),
+                (102, Wire::Bytes(vec![0xf4, 0xf7, 0x03, 0xf5, 0xf7, 0x03])),
+            ]
+        );
+    }
+
+    #[test]
+    fn native_field_numbers() {
+        let mut flowmessage = flow();
+        flowmessage.in_if_utilization = Some(12.5);
+        let fields = decode(&ProtobufEncoder::new(ProtobufMode::Native).encode(&flowmessage));
+        let number =
+            |name: &str| FIELD_TYPES.iter().position(|(x, _)| *x == name).unwrap() as u32 + 1;
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(x, _)| *x == number(name))
+                .map(|(_, x)| x)
+        };
+        assert_eq!(field("in_bytes"), Some(&Wire::Varint(1500)));
+        assert_eq!(
+            field("ipv4_src_addr"),
+            Some(&Wire::Bytes(vec![192, 0, 2, 1]))
+        );
+        assert_eq!(
+            field("exporter_addr"),
+            Some(&Wire::Bytes(b"10.0.0.1:2055".to_vec()))
+        );
+        assert_eq!(
+            field("in_if_utilization"),
+            Some(&Wire::Fixed64(12.5f64.to_bits()))
+        );
+        assert_eq!(field("d0ctets"), None);
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use super::super::util::unix_now;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use flate2::write::GzEncoder;
+
+/// When the file publishers close the current file and start a new one:
+/// at every multiple of `interval` since the epoch, so that 5m files start
+/// at :00, :05, ..., and once a file has reached `max_size` bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    pub interval: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+impl Rotation {
+    /// Start of the period `now` falls in, in Unix seconds.
+    pub fn period(&self, now: u64) -> u64 {
+        match self.interval.map(|x| x.as_secs().max(1)) {
+            Some(interval) => now / interval * interval,
+            None => now,
+        }
+    }
+
+    pub fn expired(&self, period: u64, now: u64) -> bool {
+        match self.interval.map(|x| x.as_secs().max(1)) {
+            Some(interval) => now >= period + interval,
+            None => false,
+        }
+    }
+
+    pub fn full(&self, size: u64) -> bool {
+        self.max_size.is_some_and(|x| size >= x)
+    }
+}
+
+/// Expands the strftime escapes of `template`, e.g. `/data/flows-%Y%m%d%H%M.csv`,
+/// with the UTC time `secs`.
+pub fn expand_path(template: &str, secs: u64) -> Result<String> {
+    let time =
+        DateTime::from_timestamp(secs as i64, 0).ok_or_else(|| anyhow!("invalid time {}", secs))?;
+    let mut path = String::new();
+    write!(path, "{}", time.format(template))
+        .map_err(|_| anyhow!("invalid path template {}", template))?;
+    Ok(path)
+}
+
+/// A hidden name next to `path` that a file is written under until it is
+/// complete, so that readers globbing the directory never see partial files.
+pub fn temp_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.inprogress", name))
+}
+
+/// Creates the parent directory of `path` if needed.
+pub fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| anyhow!("{}: {}", parent.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Moves a completed file to `path`, or to `path` with `-1`, `-2`, ... before
+/// the extension if a file of that name already exists, and returns where
+/// it went.
+pub fn finalize(temp: &Path, path: &str) -> Result<String> {
+    let mut target = path.to_string();
+    let (stem, ext) = match Path::new(path).file_name().map(|x| x.to_string_lossy()) {
+        Some(name) => match name.find('.') {
+            Some(i) if i > 0 => {
+                let ext = name[i..].to_string();
+                (path[..path.len() - ext.len()].to_string(), ext)
+            }
+            _ => (path.to_string(), String::new()),
+        },
+        None => (path.to_string(), String::new()),
+    };
+    let mut i = 0;
+    while Path::new(&target).exists() {
+        i += 1;
+        target = format!("{}-{}{}", stem, i, ext);
+    }
+    fs::rename(temp, &target).map_err(|e| anyhow!("{}: {}", target, e))?;
+    Ok(target)
+}
+
+/// Runs `hook` through `sh -c` with the finished file as `$1`, without
+/// waiting for it.
+pub fn run_hook(hook: &str, path: &str) {
+    match Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .arg("sh")
+        .arg(path)
+        .spawn()
+    {
+        Ok(mut child) => {
+            let hook = hook.to_string();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    eprintln!("rotate: {} exited with {}", hook, status)
+                }
+                Err(e) => eprintln!("rotate: {}: {}", hook, e),
+                _ => {}
+            });
+        }
+        Err(e) => eprintln!("rotate: {}: {}", hook, e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for FileCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<FileCompression> {
+        match s {
+            "none" => Ok(FileCompression::None),
+            "gzip" => Ok(FileCompression::Gzip),
+            "zstd" => Ok(FileCompression::Zstd),
+            _ => Err(anyhow!("unsupported compression {}", s)),
+        }
+    }
+}
+
+enum Writer {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Writer {
+    fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Writer::Plain(x) => x.write_all(data),
+            Writer::Gzip(x) => x.write_all(data),
+            Writer::Zstd(x) => x.write_all(data),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Writer::Plain(mut x) => x.flush(),
+            Writer::Gzip(x) => x.finish()?.flush(),
+            Writer::Zstd(x) => x.finish()?.flush(),
+        }
+    }
+}
+
+struct OpenFile {
+    path: String,
+    temp: PathBuf,
+    period: u64,
+    size: u64,
+    writer: Writer,
+}
+
+/// A file output written under a path template and rotated on time and
+/// size. Each file starts with `header`, is written under a hidden name and
+/// renamed once complete, and is then passed to the post-rotate hook.
+pub struct RotatingFile {
+    template: String,
+    rotation: Rotation,
+    compression: FileCompression,
+    hook: Option<String>,
+    header: Option<Vec<u8>>,
+    file: Option<OpenFile>,
+}
+
+impl RotatingFile {
+    pub fn new(
+        template: &str,
+        rotation: Rotation,
+        compression: FileCompression,
+        hook: Option<String>,
+    ) -> Result<RotatingFile> {
+        expand_path(template, unix_now())?;
+        Ok(RotatingFile {
+            template: template.to_string(),
+            rotation,
+            compression,
+            hook,
+            header: None,
+            file: None,
+        })
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Sets the bytes every file starts with, e.g. a CSV header line.
+    pub fn set_header(&mut self, header: Vec<u8>) {
+        self.header = Some(header);
+    }
+
+    fn open(&self, now: u64) -> Result<OpenFile> {
+        let period = self.rotation.period(now);
+        let path = expand_path(&self.template, period)?;
+        let temp = temp_path(&path);
+        create_parent(&temp)?;
+        let file =
+            BufWriter::new(File::create(&temp).map_err(|e| anyhow!("{}: {}", temp.display(), e))?);
+        let writer = match self.compression {
+            FileCompression::None => Writer::Plain(file),
+            FileCompression::Gzip => {
+                Writer::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            FileCompression::Zstd => Writer::Zstd(zstd::Encoder::new(file, 0)?),
+        };
+        let mut file = OpenFile {
+            path,
+            temp,
+            period,
+            size: 0,
+            writer,
+        };
+        if let Some(header) = &self.header {
+            file.writer.write_all(header)?;
+            file.size += header.len() as u64;
+        }
+        Ok(file)
+    }
+
+    /// Appends `data`, opening a file first if needed. `max_size` is checked
+    /// against the uncompressed bytes written.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let now = unix_now();
+        self.rotate(now)?;
+        if self.file.is_none() {
+            self.file = Some(self.open(now)?);
+        }
+        let file = self.file.as_mut().unwrap();
+        file.writer.write_all(data)?;
+        file.size += data.len() as u64;
+        if self.rotation.full(file.size) {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the file once its period is over, or else flushes it so that
+    /// uncompressed output is on disk within a second.
+    pub fn flush(&mut self) -> Result<()> {
+        self.rotate(unix_now())?;
+        if let Some(OpenFile {
+            writer: Writer::Plain(x),
+            ..
+        }) = &mut self.file
+        {
+            x.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: u64) -> Result<()> {
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|x| self.rotation.expired(x.period, now))
+        {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        if let Some(OpenFile {
+            path, temp, writer, ..
+        }) = self.file.take()
+        {
+            writer
+                .finish()
+                .map_err(|e| anyhow!("{}: {}", temp.display(), e))?;
+            let path = finalize(&temp, &path)?;
+            if let Some(hook) = &self.hook {
+                run_hook(hook, &path);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ferrisflow-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn strftime_rotation() {
+        let rotation = Rotation {
+            interval: Some(Duration::from_secs(300)),
+            max_size: None,
+        };
+        // 2021-01-02 03:04:05 UTC
+        let now = 1_609_556_645;
+        let period = rotation.period(now);
+        assert_eq!(period, 1_609_556_400);
+        assert_eq!(
+            expand_path("/data/%Y/%m/%d/flows-%H%M.csv", period).unwrap(),
+            "/data/2021/01/02/flows-0300.csv"
+        );
+        assert!(!rotation.expired(period, period + 299));
+        assert!(rotation.expired(period, period + 300));
+        assert!(!Rotation::default().expired(period, u64::MAX));
+
+        // Files are named after the start of their period.
+        let dir = temp_dir("strftime");
+        let template = dir.join("flows-%s.csv").to_string_lossy().to_string();
+        let rotation = Rotation {
+            interval: Some(Duration::from_secs(1)),
+            max_size: None,
+        };
+        let mut file = RotatingFile::new(&template, rotation, FileCompression::None, None).unwrap();
+        let first = unix_now();
+        file.write(b"1\n").unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        file.flush().unwrap();
+        let first = expand_path(&template, first).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), b"1\n");
+        file.write(b"2\n").unwrap();
+        file.close().unwrap();
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().path().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0], first);
+        assert_eq!(fs::read(&names[1]).unwrap(), b"2\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hidden_file_and_header_per_file() {
+        let dir = temp_dir("rotate");
+        let template = dir.join("flows.csv").to_string_lossy().to_string();
+        let rotation = Rotation {
+            interval: None,
+            max_size: Some(20),
+        };
+        let mut file = RotatingFile::new(&template, rotation, FileCompression::None, None).unwrap();
+        file.set_header(b"a,b\n".to_vec());
+
+        file.write(b"1,2\n").unwrap();
+        file.flush().unwrap();
+        assert!(!Path::new(&template).exists());
+        assert_eq!(fs::read(temp_path(&template)).unwrap(), b"a,b\n1,2\n");
+
+        // Reaching the size limit finishes the file, and the next one gets
+        // a suffix and the header again.
+        file.write(b"3,4,5,6,7,8,9\n").unwrap();
+        assert!(!temp_path(&template).exists());
+        file.write(b"10,11\n").unwrap();
+        file.close().unwrap();
+        assert_eq!(fs::read(&template).unwrap(), b"a,b\n1,2\n3,4,5,6,7,8,9\n");
+        assert_eq!(fs::read(dir.join("flows-1.csv")).unwrap(), b"a,b\n10,11\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn post_rotate_hook() {
+        let dir = temp_dir("hook");
+        let template = dir.join("flows.json").to_string_lossy().to_string();
+        let hook = "echo \"$1\" > \"$1.done\"".to_string();
+        let mut file = RotatingFile::new(
+            &template,
+            Rotation::default(),
+            FileCompression::None,
+            Some(hook),
+        )
+        .unwrap();
+        file.write(b"{}\n").unwrap();
+        file.close().unwrap();
+
+        let done = dir.join("flows.json.done");
+        for _ in 0..50 {
+            if fs::read_to_string(&done).is_ok_and(|x| x.ends_with('\n')) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(
+            fs::read_to_string(&done).unwrap(),
+            format!("{}\n", template)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::metrics;
+
+/// Packets arriving up to this many packets behind the expected sequence
+/// number are counted as reordered rather than as a reset, as long as no
+/// more than this many arrive late in a row.
+const REORDER_PACKETS: i64 = 100;
+/// A gap is counted as lost once `REORDER_PACKETS` packets have arrived
+/// after it or this long has passed, unless late packets filled it.
+const REORDER_DELAY: Duration = Duration::from_secs(10);
+/// Forward jumps of more than this many packets are counted as a reset.
+const MAX_GAP_PACKETS: i64 = 100_000;
+/// Seconds the boot time derived from the header may move by before the
+/// exporter is considered restarted.
+const BOOT_TOLERANCE: i64 = 10;
+/// The period the loss ratio is measured over.
+const RATIO_PERIOD: Duration = Duration::from_secs(60);
+/// Domains not heard from for this long are forgotten.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+/// How often idle domains are looked for.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+/// Domains beyond this many are not followed.
+const MAX_DOMAINS: usize = 10_000;
+
+/// The metrics reported per domain.
+const METRICS: [&str; 5] = [
+    "ferrisflow_sequence_received_total",
+    "ferrisflow_sequence_lost_total",
+    "ferrisflow_sequence_reordered_total",
+    "ferrisflow_sequence_resets_total",
+    "ferrisflow_sequence_loss_ratio",
+];
+
+/// An observation domain: the exporter, the version and the engine type and
+/// id (v5) or source id (v9), each numbering its packets independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SequenceKey {
+    pub exporter: IpAddr,
+    pub version: u16,
+    pub domain: u32,
+}
+
+impl SequenceKey {
+    pub fn v5(exporter: IpAddr, engine_type: u8, engine_id: u8) -> SequenceKey {
+        SequenceKey {
+            exporter,
+            version: 5,
+            domain: (engine_type as u32) << 8 | engine_id as u32,
+        }
+    }
+
+    pub fn v9(exporter: IpAddr, source_id: u32) -> SequenceKey {
+        SequenceKey {
+            exporter,
+            version: 9,
+            domain: source_id,
+        }
+    }
+
+    /// The exporter, version and domain label values.
+    fn label_values(&self) -> [String; 3] {
+        let domain = match self.version {
+            5 => format!("{}/{}", self.domain >> 8, self.domain & 0xff),
+            _ => self.domain.to_string(),
+        };
+        [self.exporter.to_string(), self.version.to_string(), domain]
+    }
+}
+
+/// Sequence numbers that have not been received yet.
+#[derive(Debug, Clone, Copy)]
+struct Gap {
+    start: u32,
+    len: u32,
+    /// When the gap was seen, and the packets the domain had received then.
+    seen: Instant,
+    packets: u64,
+}
+
+#[derive(Debug)]
+struct Domain {
+    /// The sequence number the next packet should carry.
+    next: u32,
+    boot: i64,
+    /// Packets received late in a row.
+    late: i64,
+    /// Packets received, which gaps age by.
+    packets: u64,
+    received: u64,
+    lost: u64,
+    reordered: u64,
+    resets: u64,
+    /// Gaps within the reorder window, oldest first.
+    gaps: VecDeque<Gap>,
+    last_seen: Instant,
+    period_start: Instant,
+    period_received: u64,
+    period_lost: u64,
+    ratio: f64,
+    /// Until when flows are tagged, and the loss since the first gap.
+    loss: Option<(Instant, u32)>,
+}
+
+impl Domain {
+    fn new(sequence: u32, count: u32, boot: i64, now: Instant) -> Domain {
+        Domain {
+            next: sequence.wrapping_add(count),
+            boot,
+            late: 0,
+            packets: 1,
+            received: count as u64,
+            lost: 0,
+            reordered: 0,
+            resets: 0,
+            gaps: VecDeque::new(),
+            last_seen: now,
+            period_start: now,
+            period_received: count as u64,
+            period_lost: 0,
+            ratio: 0.0,
+            loss: None,
+        }
+    }
+
+    /// Counts the gaps that are out of the reorder window as lost, or all
+    /// of them.
+    fn settle(&mut self, now: Instant, all: bool) {
+        while let Some(gap) = self.gaps.front() {
+            if !all
+                && self.packets - gap.packets < REORDER_PACKETS as u64
+                && now.duration_since(gap.seen) < REORDER_DELAY
+            {
+                break;
+            }
+            self.lost += gap.len as u64;
+            self.period_lost += gap.len as u64;
+            self.gaps.pop_front();
+        }
+    }
+
+    /// Removes the sequence numbers of a late packet from the gaps.
+    fn fill(&mut self, sequence: u32, count: u32) {
+        let mut gaps = VecDeque::with_capacity(self.gaps.len() + 1);
+        for gap in std::mem::take(&mut self.gaps) {
+            let start = sequence.wrapping_sub(gap.start) as i32 as i64;
+            let (lo, hi) = (start.max(0), (start + count as i64).min(gap.len as i64));
+            if lo >= hi {
+                gaps.push_back(gap);
+                continue;
+            }
+            if lo > 0 {
+                gaps.push_back(Gap {
+                    len: lo as u32,
+                    ..gap
+                });
+            }
+            if hi < gap.len as i64 {
+                gaps.push_back(Gap {
+                    start: gap.start.wrapping_add(hi as u32),
+                    len: gap.len - hi as u32,
+                    ..gap
+                });
+            }
+        }
+        self.gaps = gaps;
+    }
+
+    /// Starts a new loss ratio period once the current one is over.
+    fn roll(&mut self, now: Instant) {
+        self.settle(now, false);
+        let elapsed = now.duration_since(self.period_start);
+        if elapsed < RATIO_PERIOD {
+            return;
+        }
+        let expected = self.period_received + self.period_lost;
+        self.ratio = if elapsed >= RATIO_PERIOD * 2 || expected == 0 {
+            0.0
+        } else {
+            self.period_lost as f64 / expected as f64
+        };
+        self.period_start = now;
+        self.period_received = 0;
+        self.period_lost = 0;
+    }
+}
+
+#[derive(Debug)]
+struct Domains {
+    domains: HashMap<SequenceKey, Domain>,
+    /// When idle domains were last looked for.
+    expired: Instant,
+}
+
+impl Default for Domains {
+    fn default() -> Domains {
+        Domains {
+            domains: HashMap::new(),
+            expired: Instant::now(),
+        }
+    }
+}
+
+/// Follows the sequence numbers in export packet headers per observation
+/// domain, counting gaps as lost, late packets as reordered and jumps or
+/// exporter restarts as resets. NetFlow v5 numbers flows and v9 numbers
+/// packets, so the counts are in flows and packets respectively. A gap is
+/// only counted as lost once it is out of the reorder window, so packets
+/// that arrive late are not counted as lost as well. Domains idle for an
+/// hour are forgotten, and at most `MAX_DOMAINS` are followed.
+///
+/// With a tag period, flows received within that period after a gap get
+/// `sequence_lost`, the sequence numbers missed by their domain since the
+/// first gap of the period.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    tag_period: Option<Duration>,
+    domains: Arc<Mutex<Domains>>,
+}
+
+impl SequenceTracker {
+    pub fn new(tag_period: Option<Duration>) -> SequenceTracker {
+        SequenceTracker {
+            tag_period,
+            domains: Arc::new(Mutex::new(Domains::default())),
+        }
+    }
+
+    /// Records a packet carrying `sequence` and `count` sequence numbers,
+    /// sent by an exporter that booted at `boot` (unix seconds), and returns
+    /// what to tag its flows with. Packets must be observed in the order
+    /// they were received.
+    pub fn observe(&self, key: SequenceKey, sequence: u32, count: u32, boot: i64) -> Option<u32> {
+        let now = Instant::now();
+        let mut domains = self.domains.lock().unwrap();
+        if now.duration_since(domains.expired) >= EXPIRE_INTERVAL {
+            expire(&mut domains, now);
+        }
+        let full = domains.domains.len() >= MAX_DOMAINS;
+        let domain = match domains.domains.entry(key) {
+            Entry::Occupied(x) => x.into_mut(),
+            Entry::Vacant(_) if full => return None,
+            Entry::Vacant(x) => {
+                x.insert(Domain::new(sequence, count, boot, now));
+                self.register(key);
+                return None;
+            }
+        };
+        domain.last_seen = now;
+        domain.roll(now);
+
+        let diff = sequence.wrapping_sub(domain.next) as i32 as i64;
+        let unit = (count as i64).max(1);
+        domain.packets += 1;
+        domain.received += count as u64;
+        domain.period_received += count as u64;
+        if (boot - domain.boot).abs() > BOOT_TOLERANCE
+            || diff < -REORDER_PACKETS * unit
+            || diff > MAX_GAP_PACKETS * unit
+            || (diff < 0 && domain.late >= REORDER_PACKETS)
+        {
+            domain.settle(now, true);
+            domain.resets += 1;
+            domain.late = 0;
+            domain.boot = boot;
+            domain.next = sequence.wrapping_add(count);
+        } else if diff < 0 {
+            domain.late += 1;
+            domain.reordered += count as u64;
+            domain.fill(sequence, count);
+        } else {
+            domain.late = 0;
+            if diff > 0 {
+                domain.gaps.push_back(Gap {
+                    start: domain.next,
+                    len: diff as u32,
+                    seen: now,
+                    packets: domain.packets,
+                });
+                if let Some(tag_period) = self.tag_period {
+                    let lost = match domain.loss {
+                        Some((until, lost)) if now < until => lost,
+                        _ => 0,
+                    };
+                    domain.loss = Some((now + tag_period, lost.saturating_add(diff as u32)));
+                }
+            }
+            domain.next = sequence.wrapping_add(count);
+        }
+        domain.settle(now, false);
+
+        match domain.loss {
+            Some((until, lost)) if now < until => Some(lost),
+            _ => None,
+        }
+    }
+
+    fn register(&self, key: SequenceKey) {
+        let [exporter, version, domain] = key.label_values();
+        let labels = [
+            ("exporter", exporter.as_str()),
+            ("version", version.as_str()),
+            ("domain", domain.as_str()),
+        ];
+        for (name, read) in METRICS.iter().zip([
+            (|x: &Domain| x.received as f64) as fn(&Domain) -> f64,
+            |x: &Domain| x.lost as f64,
+            |x: &Domain| x.reordered as f64,
+            |x: &Domain| x.resets as f64,
+        ]) {
+            let domains = self.domains.clone();
+            metrics::register(name, &labels, move || {
+                domains.lock().unwrap().domains.get(&key).map_or(0.0, read)
+            });
+        }
+        let domains = self.domains.clone();
+        metrics::register("ferrisflow_sequence_loss_ratio", &labels, move || {
+            let mut domains = domains.lock().unwrap();
+            domains.domains.get_mut(&key).map_or(0.0, |x| {
+                x.roll(Instant::now());
+                x.ratio
+            })
+        });
+    }
+}
+
+/// Forgets the domains idle for `IDLE_TIMEOUT` and their metrics.
+fn expire(domains: &mut Domains, now: Instant) {
+    domains.expired = now;
+    domains.domains.retain(|key, domain| {
+        if now.duration_since(domain.last_seen) < IDLE_TIMEOUT {
+            return true;
+        }
+        let [exporter, version, domain] = key.label_values();
+        let labels = [
+            ("exporter", exporter.as_str()),
+            ("version", version.as_str()),
+            ("domain", domain.as_str()),
+        ];
+        for name in METRICS.iter() {
+            metrics::unregister(name, &labels);
+        }
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(exporter: [u8; 4], domain: u32) -> SequenceKey {
+        SequenceKey::v9(IpAddr::from(exporter), domain)
+    }
+
+    fn counts(tracker: &SequenceTracker, key: SequenceKey) -> (u64, u64, u64) {
+        let domains = tracker.domains.lock().unwrap();
+        let domain = &domains.domains[&key];
+        (domain.lost, domain.reordered, domain.period_lost)
+    }
+
+    #[test]
+    fn late_packets_are_not_lost() {
+        let tracker = SequenceTracker::new(None);
+        let key = key([192, 0, 2, 1], 0);
+        for sequence in [0, 1, 3, 2, 5, 4, 6] {
+            tracker.observe(key, sequence, 1, 0);
+        }
+        for sequence in 7..200 {
+            tracker.observe(key, sequence, 1, 0);
+        }
+        assert_eq!(counts(&tracker, key), (0, 2, 0));
+    }
+
+    #[test]
+    fn gaps_are_lost_after_the_reorder_window() {
+        let tracker = SequenceTracker::new(None);
+        let key = key([192, 0, 2, 2], 0);
+        for sequence in [0, 1, 4, 2] {
+            tracker.observe(key, sequence, 1, 0);
+        }
+        assert_eq!(counts(&tracker, key), (0, 1, 0));
+        for sequence in 5..5 + REORDER_PACKETS as u32 {
+            tracker.observe(key, sequence, 1, 0);
+        }
+        assert_eq!(counts(&tracker, key), (1, 1, 1));
+    }
+
+    #[test]
+    fn idle_domains_expire() {
+        let tracker = SequenceTracker::new(None);
+        let key = key([192, 0, 2, 3], 0);
+        tracker.observe(key, 0, 1, 0);
+        assert!(metrics::render().contains("exporter=\"192.0.2.3\""));
+        let now = Instant::now() + IDLE_TIMEOUT;
+        expire(&mut tracker.domains.lock().unwrap(), now);
+        assert!(tracker.domains.lock().unwrap().domains.is_empty());
+        assert!(!metrics::render().contains("exporter=\"192.0.2.3\""));
+    }
+
+    #[test]
+    fn domains_are_capped() {
+        let tracker = SequenceTracker::new(None);
+        for domain in 0..MAX_DOMAINS as u32 + 1 {
+            tracker.observe(key([192, 0, 2, 4], domain), 0, 1, 0);
+        }
+        let domains = tracker.domains.lock().unwrap();
+        assert_eq!(domains.domains.len(), MAX_DOMAINS);
+        assert!(!domains
+            .domains
+            .contains_key(&key([192, 0, 2, 4], MAX_DOMAINS as u32)));
+    }
+}
@@ -1,13 +1,36 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::iter;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time;
 
-use super::handler::Handler;
+use super::event::Event;
+use super::filter::Filter;
+use super::flowmessage::FlowMessage;
+use super::handler::{DecodeError, Handler};
+use super::metrics;
+use super::nfcapd::NfcapdReader;
+use super::processor::Processor;
 use super::publisher::Publisher;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+/// The label value of the exporters and versions that are not counted
+/// separately.
+const OTHER: &str = "other";
+/// Exporters beyond this many are counted in the `other` series.
+const MAX_EXPORTERS: usize = 1000;
 
 pub struct Server {
     pub socket: UdpSocket,
     pub buf: Vec<u8>,
     pub handlers: Vec<Box<dyn Handler>>,
+    pub filter: Option<Filter>,
+    pub processors: Vec<Box<dyn Processor>>,
     pub publishers: Vec<Box<dyn Publisher>>,
 }
 
@@ -17,40 +40,458 @@ impl Server {
             socket,
             mut buf,
             handlers,
+            filter,
+            processors,
             publishers,
         } = self;
+        let filter = Arc::new(filter);
+        let publisher_metrics = PublisherMetrics::new(&publishers);
+        let mut exporters = Exporters::new(&handlers);
+        let in_flight = metrics::gauge("ferrisflow_packets_in_flight", &[]);
+
+        let processors_c = processors.clone();
+        let publishers_c = publishers.clone();
+        let publisher_metrics_c = publisher_metrics.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                for (i, processor) in processors_c.iter().enumerate() {
+                    match processor.flush() {
+                        Ok(flowmessages) => {
+                            publish(
+                                &processors_c[i + 1..],
+                                &publishers_c,
+                                &publisher_metrics_c,
+                                flowmessages,
+                            );
+                        }
+                        Err(e) => {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    match processor.events() {
+                        Ok(events) => {
+                            publish_events(&publishers_c, &events);
+                        }
+                        Err(e) => {
+                            eprintln!("{}", e);
+                        }
+                    }
+                }
+                for publisher in publishers_c.iter() {
+                    if let Err(e) = publisher.flush() {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+        });
+
+        let processors_c = processors.clone();
+        let publishers_c = publishers.clone();
+        let publisher_metrics_c = publisher_metrics.clone();
+        tokio::spawn(async move {
+            let mut terminate = match signal(SignalKind::terminate()) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            close(&processors_c, &publishers_c, &publisher_metrics_c);
+            std::process::exit(0);
+        });
 
         loop {
             match socket.recv_from(&mut buf).await {
                 Ok((size, addr)) => {
-                    let buf_c = buf.clone();
+                    let exporter = exporters.get(addr.ip());
+                    let version = exporters.version(&buf[..size]);
+                    exporter.received[version].inc();
+                    let sequence_lost = handlers.iter().find_map(|x| x.observe(&buf[..size], addr));
+                    let in_flight = InFlight::new(&in_flight);
+                    let buf_c = buf[..size].to_vec();
                     let handlers_c = handlers.clone();
+                    let filter_c = filter.clone();
+                    let processors_c = processors.clone();
                     let publishers_c = publishers.clone();
+                    let publisher_metrics_c = publisher_metrics.clone();
                     tokio::spawn(async move {
+                        let mut error: Option<anyhow::Error> = None;
+                        let mut decoded = false;
                         for handler in handlers_c.iter() {
                             match handler.handle(&buf_c, size, addr) {
-                                Ok(flowdatas) => {
-                                    for publisher in publishers_c.iter() {
-                                        match publisher.publish(&flowdatas) {
-                                            Ok(_) => {}
-                                            Err(e) => {
-                                                eprintln!("{}", e);
-                                            }
+                                Ok(mut flowdatas) => {
+                                    decoded = true;
+                                    exporter.flows.add(flowdatas.len() as u64);
+                                    if sequence_lost.is_some() {
+                                        for flowdata in flowdatas.iter_mut() {
+                                            flowdata.sequence_lost = sequence_lost;
                                         }
                                     }
+                                    let flowdatas = match filter_c.as_ref() {
+                                        Some(filter) => filter.apply(flowdatas),
+                                        None => flowdatas,
+                                    };
+                                    publish(
+                                        &processors_c,
+                                        &publishers_c,
+                                        &publisher_metrics_c,
+                                        flowdatas,
+                                    );
                                     break;
                                 }
                                 Err(e) => {
                                     eprintln!("{}", e);
+                                    // Every handler but one rejects the version,
+                                    // so prefer the reason of the one that tried.
+                                    let unsupported = matches!(
+                                        e.downcast_ref::<DecodeError>(),
+                                        Some(DecodeError::UnsupportedVersion(..))
+                                    );
+                                    if error.is_none() || !unsupported {
+                                        error = Some(e);
+                                    }
                                 }
                             }
                         }
+                        if let (false, Some(e)) = (decoded, error) {
+                            exporter.dropped[version].inc();
+                            metrics::inc(
+                                "ferrisflow_decode_errors_total",
+                                &[("reason", reason(&e))],
+                            );
+                            if let Some(DecodeError::UnknownTemplate(_)) = e.downcast_ref() {
+                                exporter.template_misses.inc();
+                            }
+                        }
+                        drop(in_flight);
                     });
                 }
                 Err(e) => {
+                    metrics::inc("ferrisflow_receive_errors_total", &[]);
                     eprintln!("{}", e);
                 }
             };
         }
     }
 }
+
+/// The metrics of an exporter, per version label.
+struct ExporterMetrics {
+    received: Vec<metrics::Counter>,
+    dropped: Vec<metrics::Counter>,
+    flows: metrics::Counter,
+    template_misses: metrics::Counter,
+}
+
+impl ExporterMetrics {
+    fn new(exporter: &str, versions: &[String]) -> ExporterMetrics {
+        let counters = |name| {
+            versions
+                .iter()
+                .map(|x| metrics::counter(name, &[("exporter", exporter), ("version", x)]))
+                .collect()
+        };
+        ExporterMetrics {
+            received: counters("ferrisflow_packets_received_total"),
+            dropped: counters("ferrisflow_packets_dropped_total"),
+            flows: metrics::counter("ferrisflow_flows_received_total", &[("exporter", exporter)]),
+            template_misses: metrics::counter(
+                "ferrisflow_template_misses_total",
+                &[("exporter", exporter)],
+            ),
+        }
+    }
+}
+
+/// The metrics of each exporter, kept by the receive loop so that counting
+/// a packet does not take the registry lock. Versions no handler decodes
+/// and exporters beyond `MAX_EXPORTERS` are counted as `other`.
+struct Exporters {
+    versions: Vec<u16>,
+    labels: Vec<String>,
+    exporters: HashMap<IpAddr, Arc<ExporterMetrics>>,
+    other: Option<Arc<ExporterMetrics>>,
+}
+
+impl Exporters {
+    fn new(handlers: &[Box<dyn Handler>]) -> Exporters {
+        let mut versions = handlers.iter().map(|x| x.version()).collect::<Vec<u16>>();
+        versions.sort_unstable();
+        versions.dedup();
+        let labels = versions
+            .iter()
+            .map(|x| x.to_string())
+            .chain(iter::once(OTHER.to_string()))
+            .collect();
+        Exporters {
+            versions,
+            labels,
+            exporters: HashMap::new(),
+            other: None,
+        }
+    }
+
+    fn get(&mut self, exporter: IpAddr) -> Arc<ExporterMetrics> {
+        if let Some(metrics) = self.exporters.get(&exporter) {
+            return metrics.clone();
+        }
+        let labels = &self.labels;
+        if self.exporters.len() >= MAX_EXPORTERS {
+            return self
+                .other
+                .get_or_insert_with(|| Arc::new(ExporterMetrics::new(OTHER, labels)))
+                .clone();
+        }
+        let metrics = Arc::new(ExporterMetrics::new(&exporter.to_string(), labels));
+        self.exporters.insert(exporter, metrics.clone());
+        metrics
+    }
+
+    /// The index of the version label of a packet.
+    fn version(&self, buf: &[u8]) -> usize {
+        let other = self.versions.len();
+        match buf {
+            [x, y, ..] => {
+                let version = u16::from_be_bytes([*x, *y]);
+                self.versions
+                    .iter()
+                    .position(|x| *x == version)
+                    .unwrap_or(other)
+            }
+            _ => other,
+        }
+    }
+}
+
+/// The metrics of a publisher.
+struct PublisherMetrics {
+    duration: metrics::Histogram,
+    success: metrics::Counter,
+    failure: metrics::Counter,
+}
+
+impl PublisherMetrics {
+    fn new(publishers: &[Box<dyn Publisher>]) -> Arc<Vec<PublisherMetrics>> {
+        let metrics = publishers
+            .iter()
+            .map(|publisher| {
+                let name = publisher.to_string();
+                let publish = |result| {
+                    metrics::counter(
+                        "ferrisflow_publisher_publish_total",
+                        &[("publisher", &name), ("result", result)],
+                    )
+                };
+                PublisherMetrics {
+                    duration: metrics::histogram(
+                        "ferrisflow_publisher_duration_seconds",
+                        &[("publisher", &name)],
+                    ),
+                    success: publish("success"),
+                    failure: publish("failure"),
+                }
+            })
+            .collect();
+        Arc::new(metrics)
+    }
+}
+
+/// Counts a packet in flight until dropped, also when a handler panics.
+struct InFlight(metrics::Gauge);
+
+impl InFlight {
+    fn new(gauge: &metrics::Gauge) -> InFlight {
+        gauge.add(1);
+        InFlight(gauge.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.add(-1);
+    }
+}
+
+/// Feeds the flows of nfcapd files through the processors and publishers
+/// instead of listening for packets, then closes them.
+pub struct Replay {
+    pub paths: Vec<String>,
+    pub filter: Option<Filter>,
+    pub processors: Vec<Box<dyn Processor>>,
+    pub publishers: Vec<Box<dyn Publisher>>,
+}
+
+impl Replay {
+    pub fn run(self) -> Result<()> {
+        let publisher_metrics = PublisherMetrics::new(&self.publishers);
+        for path in self.paths.iter() {
+            let file = File::open(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+            let reader =
+                NfcapdReader::new(BufReader::new(file)).map_err(|e| anyhow!("{}: {}", path, e))?;
+            for flowmessages in reader {
+                let flowmessages = flowmessages.map_err(|e| anyhow!("{}: {}", path, e))?;
+                let flowmessages = match self.filter.as_ref() {
+                    Some(filter) => filter.apply(flowmessages),
+                    None => flowmessages,
+                };
+                publish(
+                    &self.processors,
+                    &self.publishers,
+                    &publisher_metrics,
+                    flowmessages,
+                );
+            }
+        }
+        close(&self.processors, &self.publishers, &publisher_metrics);
+        Ok(())
+    }
+}
+
+/// Passes what the processors still hold down the pipeline, then closes
+/// the publishers.
+fn close(
+    processors: &[Box<dyn Processor>],
+    publishers: &[Box<dyn Publisher>],
+    publisher_metrics: &[PublisherMetrics],
+) {
+    for (i, processor) in processors.iter().enumerate() {
+        match processor.close() {
+            Ok(flowmessages) => {
+                publish(
+                    &processors[i + 1..],
+                    publishers,
+                    publisher_metrics,
+                    flowmessages,
+                );
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+            }
+        }
+        match processor.events() {
+            Ok(events) => {
+                publish_events(publishers, &events);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    for publisher in publishers.iter() {
+        if let Err(e) = publisher.close() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn publish(
+    processors: &[Box<dyn Processor>],
+    publishers: &[Box<dyn Publisher>],
+    publisher_metrics: &[PublisherMetrics],
+    mut flowmessages: Vec<FlowMessage>,
+) {
+    for processor in processors.iter() {
+        if flowmessages.is_empty() {
+            return;
+        }
+        match processor.process(flowmessages) {
+            Ok(processed) => {
+                flowmessages = processed;
+            }
+            Err(e) => {
+                metrics::inc(
+                    "ferrisflow_processor_errors_total",
+                    &[("processor", &processor.to_string())],
+                );
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    if flowmessages.is_empty() {
+        return;
+    }
+    for (publisher, metrics) in publishers.iter().zip(publisher_metrics.iter()) {
+        let start = Instant::now();
+        let result = publisher.publish(&flowmessages);
+        metrics.duration.observe(start.elapsed().as_secs_f64());
+        match result {
+            Ok(_) => {
+                metrics.success.inc();
+            }
+            Err(e) => {
+                metrics.failure.inc();
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+/// The reason label of a packet no handler could decode.
+fn reason(e: &anyhow::Error) -> &'static str {
+    if let Some(e) = e.downcast_ref::<DecodeError>() {
+        return e.reason();
+    }
+    match e.downcast_ref::<io::Error>() {
+        Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => "truncated",
+        _ => "other",
+    }
+}
+
+fn publish_events(publishers: &[Box<dyn Publisher>], events: &[Event]) {
+    if events.is_empty() {
+        return;
+    }
+    for publisher in publishers.iter() {
+        match publisher.publish_events(events) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::handler::{NetflowV5Handler, NetflowV9Handler};
+    use super::super::sequence::SequenceTracker;
+    use super::*;
+
+    #[test]
+    fn exporter_and_version_labels() {
+        let handlers: Vec<Box<dyn Handler>> = vec![
+            Box::new(NetflowV9Handler::new(SequenceTracker::default())),
+            Box::new(NetflowV5Handler::new(SequenceTracker::default())),
+        ];
+        let mut exporters = Exporters::new(&handlers);
+        assert_eq!(exporters.labels, ["5", "9", OTHER]);
+        assert_eq!(exporters.version(&[0, 9, 0, 1]), 1);
+        assert_eq!(exporters.version(&[0, 10, 0, 1]), 2);
+        assert_eq!(exporters.version(&[0]), 2);
+
+        let first = exporters.get(IpAddr::from([198, 51, 100, 1]));
+        for i in 1..MAX_EXPORTERS as u32 {
+            exporters.get(IpAddr::from((0x0a00_0000 + i).to_be_bytes()));
+        }
+        assert!(Arc::ptr_eq(
+            &first,
+            &exporters.get(IpAddr::from([198, 51, 100, 1]))
+        ));
+        let other = exporters.get(IpAddr::from([203, 0, 113, 1]));
+        assert!(Arc::ptr_eq(
+            &other,
+            &exporters.get(IpAddr::from([203, 0, 113, 2]))
+        ));
+        assert_eq!(exporters.exporters.len(), MAX_EXPORTERS);
+        other.received[0].inc();
+        assert!(metrics::render()
+            .contains("ferrisflow_packets_received_total{exporter=\"other\",version=\"5\"} 1\n"));
+    }
+}
@@ -16,7 +16,7 @@ impl TemplateCacheKey {
         version: u16,
     ) -> TemplateCacheKey {
         TemplateCacheKey {
-            exporter_ip: exporter_ip,
+            exporter_ip,
             source_id,
             template_id,
             version,
@@ -50,9 +50,9 @@ impl TemplateCacheValue {
         is_option: bool,
     ) -> TemplateCacheValue {
         TemplateCacheValue {
-            fields: fields,
-            scope_fields: scope_fields,
-            is_option: is_option,
+            fields,
+            scope_fields,
+            is_option,
         }
     }
 }
@@ -84,4 +84,18 @@ impl TemplateCache {
     pub fn contains_key(&self, k: &TemplateCacheKey) -> bool {
         self.map.contains_key(k)
     }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl Default for TemplateCache {
+    fn default() -> TemplateCache {
+        TemplateCache::new()
+    }
 }
@@ -1,16 +1,66 @@
 use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ByteOrder};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn bytes_to_usize(v: &Vec<u8>) -> Result<usize> {
+pub fn bytes_to_usize(v: &[u8]) -> Result<usize> {
     let v_len = v.len();
     match v_len {
         1 => Ok(v[0] as usize),
-        2 => Ok(BigEndian::read_u16(v.as_slice()) as usize),
-        3 => Ok(BigEndian::read_u24(v.as_slice()) as usize),
-        4 => Ok(BigEndian::read_u32(v.as_slice()) as usize),
-        6 => Ok(BigEndian::read_u48(v.as_slice()) as usize),
-        8 => Ok(BigEndian::read_u64(v.as_slice()) as usize),
-        16 => Ok(BigEndian::read_u128(v.as_slice()) as usize),
+        2 => Ok(BigEndian::read_u16(v) as usize),
+        3 => Ok(BigEndian::read_u24(v) as usize),
+        4 => Ok(BigEndian::read_u32(v) as usize),
+        6 => Ok(BigEndian::read_u48(v) as usize),
+        8 => Ok(BigEndian::read_u64(v) as usize),
+        16 => Ok(BigEndian::read_u128(v) as usize),
         _ => Err(anyhow!("read error")),
     }
 }
+
+pub fn bytes_to_string(v: &[u8]) -> String {
+    let end = v.iter().position(|&x| x == 0).unwrap_or(v.len());
+    String::from_utf8_lossy(&v[..end]).trim().to_string()
+}
+
+pub fn parse_duration(v: &str) -> Result<Duration> {
+    let v = v.trim();
+    let (digits, millis) = if let Some(digits) = v.strip_suffix("ms") {
+        (digits, 1)
+    } else {
+        match v.chars().last() {
+            Some('s') => (&v[..v.len() - 1], 1000),
+            Some('m') => (&v[..v.len() - 1], 60 * 1000),
+            Some('h') => (&v[..v.len() - 1], 60 * 60 * 1000),
+            Some('d') => (&v[..v.len() - 1], 24 * 60 * 60 * 1000),
+            _ => (v, 1000),
+        }
+    };
+    let value = digits
+        .parse::<u64>()
+        .map_err(|_| anyhow!("invalid duration {}", v))?;
+    if value == 0 {
+        return Err(anyhow!("duration must be positive: {}", v));
+    }
+    Ok(Duration::from_millis(value * millis))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses an integer with an optional `K`, `M`, `G` or `T` suffix.
+pub fn parse_number(word: &str) -> Option<u64> {
+    let (digits, multiplier) = match word.chars().last()? {
+        'k' | 'K' => (&word[..word.len() - 1], 1_000),
+        'm' | 'M' => (&word[..word.len() - 1], 1_000_000),
+        'g' | 'G' => (&word[..word.len() - 1], 1_000_000_000),
+        't' | 'T' => (&word[..word.len() - 1], 1_000_000_000_000),
+        _ => (word, 1),
+    };
+    if let Some(hex) = digits.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok()?.checked_mul(multiplier);
+    }
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}